@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE idempotency_key (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	key VARCHAR(255) NOT NULL,
+	request_hash VARCHAR(64) NOT NULL,
+	response_status INT,
+	response_body TEXT,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+	UNIQUE (user_id, key),
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE posting;
+DROP FUNCTION check_journal_entry_balanced();
+DROP TABLE journal_entry;
+DROP TABLE ledger_account;
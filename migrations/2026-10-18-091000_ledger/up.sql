@@ -0,0 +1,65 @@
+-- Your SQL goes here
+CREATE TABLE ledger_account (
+	id SERIAL PRIMARY KEY,
+	wallet_id INT UNIQUE,
+	code VARCHAR(60) UNIQUE,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+	CHECK ((wallet_id IS NULL) <> (code IS NULL)),
+    FOREIGN KEY (wallet_id) REFERENCES wallet(id)
+);
+
+CREATE TABLE journal_entry (
+	id SERIAL PRIMARY KEY,
+	transaction_id INT,
+	description VARCHAR(255) NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (transaction_id) REFERENCES transaction(id)
+);
+
+CREATE TABLE posting (
+	id SERIAL PRIMARY KEY,
+	journal_entry_id INT NOT NULL,
+	account_id INT NOT NULL,
+	amount DECIMAL(10, 2) NOT NULL CHECK (amount <> 0),
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (journal_entry_id) REFERENCES journal_entry(id),
+    FOREIGN KEY (account_id) REFERENCES ledger_account(id)
+);
+
+CREATE INDEX posting_account_id_idx ON posting (account_id);
+
+-- Postings of a journal entry must sum to zero. Checked at commit so that all legs of an entry
+-- can be inserted first.
+CREATE FUNCTION check_journal_entry_balanced() RETURNS trigger AS $$
+BEGIN
+    IF (SELECT SUM(amount) FROM posting WHERE journal_entry_id = NEW.journal_entry_id) <> 0 THEN
+        RAISE EXCEPTION 'journal entry % does not balance', NEW.journal_entry_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER posting_balanced
+    AFTER INSERT OR UPDATE ON posting
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE PROCEDURE check_journal_entry_balanced();
+
+-- System accounts for money entering and leaving the platform
+INSERT INTO ledger_account (code) VALUES ('external_cash_in'), ('external_cash_out');
+
+-- Bring existing wallets into the ledger with their current balance as an opening entry
+INSERT INTO ledger_account (wallet_id) SELECT id FROM wallet;
+
+INSERT INTO journal_entry (description) VALUES ('Opening balances');
+
+INSERT INTO posting (journal_entry_id, account_id, amount)
+SELECT currval('journal_entry_id_seq'), ledger_account.id, wallet.balance
+FROM wallet JOIN ledger_account ON ledger_account.wallet_id = wallet.id
+WHERE wallet.balance <> 0;
+
+INSERT INTO posting (journal_entry_id, account_id, amount)
+SELECT currval('journal_entry_id_seq'),
+       (SELECT id FROM ledger_account WHERE code = 'external_cash_in'),
+       -SUM(balance)
+FROM wallet
+HAVING SUM(balance) <> 0;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+UPDATE journal_entry SET transaction_id = NULL
+WHERE transaction_id IN (SELECT id FROM transaction WHERE kind IN ('deposit', 'withdrawal'));
+DELETE FROM transaction WHERE kind IN ('deposit', 'withdrawal');
+
+ALTER TABLE transaction
+	DROP COLUMN kind,
+	ALTER COLUMN from_wallet SET NOT NULL,
+	ALTER COLUMN to_wallet SET NOT NULL;
@@ -0,0 +1,39 @@
+-- Your SQL goes here
+ALTER TABLE transaction
+	ADD COLUMN kind VARCHAR(20) NOT NULL DEFAULT 'transfer'
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal')),
+	ALTER COLUMN from_wallet DROP NOT NULL,
+	ALTER COLUMN to_wallet DROP NOT NULL,
+	ADD CHECK (from_wallet IS NOT NULL OR to_wallet IS NOT NULL);
+
+ALTER TABLE transaction ALTER COLUMN kind DROP DEFAULT;
+
+-- Give deposits and withdrawals recorded before this migration a transaction row
+DO $$
+DECLARE
+    entry RECORD;
+    new_id INT;
+BEGIN
+    FOR entry IN
+        SELECT journal_entry.id, journal_entry.description, journal_entry.created_at,
+               ledger_account.wallet_id, posting.amount
+        FROM journal_entry
+        JOIN posting ON posting.journal_entry_id = journal_entry.id
+        JOIN ledger_account ON ledger_account.id = posting.account_id
+        WHERE journal_entry.transaction_id IS NULL
+          AND journal_entry.description IN ('Deposit', 'Withdrawal')
+          AND ledger_account.wallet_id IS NOT NULL
+    LOOP
+        INSERT INTO transaction (from_wallet, to_wallet, amount, kind, created_at)
+        VALUES (
+            CASE WHEN entry.amount < 0 THEN entry.wallet_id END,
+            CASE WHEN entry.amount > 0 THEN entry.wallet_id END,
+            ABS(entry.amount),
+            CASE WHEN entry.amount > 0 THEN 'deposit' ELSE 'withdrawal' END,
+            entry.created_at
+        )
+        RETURNING id INTO new_id;
+
+        UPDATE journal_entry SET transaction_id = new_id WHERE id = entry.id;
+    END LOOP;
+END $$;
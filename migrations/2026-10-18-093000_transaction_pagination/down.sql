@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX transaction_to_wallet_created_at_idx;
+DROP INDEX transaction_from_wallet_created_at_idx;
+
+ALTER TABLE transaction ALTER COLUMN created_at DROP NOT NULL;
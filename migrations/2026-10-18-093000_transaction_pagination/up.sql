@@ -0,0 +1,6 @@
+-- Your SQL goes here
+UPDATE transaction SET created_at = CURRENT_TIMESTAMP WHERE created_at IS NULL;
+ALTER TABLE transaction ALTER COLUMN created_at SET NOT NULL;
+
+CREATE INDEX transaction_from_wallet_created_at_idx ON transaction (from_wallet, created_at DESC, id DESC);
+CREATE INDEX transaction_to_wallet_created_at_idx ON transaction (to_wallet, created_at DESC, id DESC);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE refresh_token (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	family_id VARCHAR(64) NOT NULL,
+	token_hash VARCHAR(64) UNIQUE NOT NULL,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	revoked_at TIMESTAMP WITH TIME ZONE,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX refresh_token_family_id_idx ON refresh_token (family_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN tokens_valid_after;
+DROP TABLE revoked_token;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE revoked_token (
+	jti VARCHAR(64) PRIMARY KEY,
+	user_id INT NOT NULL,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+-- Access tokens issued before this are rejected
+ALTER TABLE users ADD COLUMN tokens_valid_after TIMESTAMP WITH TIME ZONE;
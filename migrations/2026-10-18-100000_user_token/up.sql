@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE user_token (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	purpose VARCHAR(30) NOT NULL CHECK (purpose IN ('password_reset')),
+	token_hash VARCHAR(64) UNIQUE NOT NULL,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	used_at TIMESTAMP WITH TIME ZONE,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM user_token WHERE purpose = 'email_verification';
+
+ALTER TABLE user_token
+	DROP CONSTRAINT user_token_purpose_check,
+	ADD CONSTRAINT user_token_purpose_check CHECK (purpose IN ('password_reset'));
+
+ALTER TABLE users DROP COLUMN email_verified_at;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP WITH TIME ZONE;
+
+-- Accounts created before verification existed are trusted
+UPDATE users SET email_verified_at = COALESCE(created_at, CURRENT_TIMESTAMP);
+
+ALTER TABLE user_token
+	DROP CONSTRAINT user_token_purpose_check,
+	ADD CONSTRAINT user_token_purpose_check CHECK (purpose IN ('password_reset', 'email_verification'));
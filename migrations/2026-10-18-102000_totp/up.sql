@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE user_totp (
+	user_id INT PRIMARY KEY,
+	secret VARCHAR(64) NOT NULL,
+	confirmed_at TIMESTAMP WITH TIME ZONE,
+	last_used_step BIGINT,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE recovery_code (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	code_hash VARCHAR(64) NOT NULL,
+	used_at TIMESTAMP WITH TIME ZONE,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+	UNIQUE (user_id, code_hash)
+);
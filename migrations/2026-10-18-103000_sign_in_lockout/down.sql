@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE security_event;
+DROP TABLE sign_in_attempt;
+ALTER TABLE users DROP COLUMN locked_until;
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE sign_in_attempt (
+	id SERIAL PRIMARY KEY,
+	user_id INT,
+	email VARCHAR(120) NOT NULL,
+	ip_address VARCHAR(45) NOT NULL,
+	succeeded BOOLEAN NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX sign_in_attempt_user_id_created_at_idx ON sign_in_attempt (user_id, created_at DESC);
+CREATE INDEX sign_in_attempt_ip_address_created_at_idx ON sign_in_attempt (ip_address, created_at DESC);
+
+CREATE TABLE security_event (
+	id SERIAL PRIMARY KEY,
+	user_id INT,
+	kind VARCHAR(40) NOT NULL,
+	ip_address VARCHAR(45),
+	detail TEXT,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX security_event_user_id_created_at_idx ON security_event (user_id, created_at DESC);
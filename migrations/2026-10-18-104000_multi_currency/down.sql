@@ -0,0 +1,26 @@
+-- This file should undo anything in `up.sql`
+CREATE OR REPLACE FUNCTION check_journal_entry_balanced() RETURNS trigger AS $$
+BEGIN
+    IF (SELECT SUM(amount) FROM posting WHERE journal_entry_id = NEW.journal_entry_id) <> 0 THEN
+        RAISE EXCEPTION 'journal entry % does not balance', NEW.journal_entry_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE posting ALTER COLUMN amount TYPE DECIMAL(10, 2);
+
+ALTER TABLE ledger_account
+	DROP CONSTRAINT ledger_account_code_currency_key,
+	DROP COLUMN currency,
+	ADD UNIQUE (code);
+
+ALTER TABLE transaction
+	DROP COLUMN currency,
+	ALTER COLUMN amount TYPE DECIMAL(10, 2);
+
+ALTER TABLE wallet
+	DROP COLUMN currency,
+	ALTER COLUMN balance TYPE DECIMAL(10, 2);
+
+DROP TABLE currency;
@@ -0,0 +1,72 @@
+-- Your SQL goes here
+CREATE TABLE currency (
+	code VARCHAR(3) PRIMARY KEY CHECK (code ~ '^[A-Z]{3}$'),
+	name VARCHAR(60) NOT NULL,
+	-- Digits after the decimal point, e.g. 2 for USD cents and 0 for JPY
+	minor_units SMALLINT NOT NULL CHECK (minor_units BETWEEN 0 AND 4),
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO currency (code, name, minor_units) VALUES
+	('USD', 'US Dollar', 2),
+	('EUR', 'Euro', 2),
+	('GBP', 'Pound Sterling', 2),
+	('SGD', 'Singapore Dollar', 2),
+	('INR', 'Indian Rupee', 2),
+	('JPY', 'Yen', 0),
+	('KWD', 'Kuwaiti Dinar', 3);
+
+-- Wallets created before currencies existed hold US dollars. Amounts are stored with the scale of
+-- their currency rather than a fixed one.
+ALTER TABLE wallet
+	ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'USD',
+	ALTER COLUMN balance TYPE NUMERIC,
+	ADD UNIQUE (user_id, currency),
+	ADD FOREIGN KEY (currency) REFERENCES currency(code);
+
+ALTER TABLE wallet ALTER COLUMN currency DROP DEFAULT;
+
+ALTER TABLE transaction
+	ADD COLUMN currency VARCHAR(3),
+	ALTER COLUMN amount TYPE NUMERIC,
+	ADD FOREIGN KEY (currency) REFERENCES currency(code);
+
+UPDATE transaction SET currency = wallet.currency
+FROM wallet
+WHERE wallet.id = COALESCE(transaction.from_wallet, transaction.to_wallet);
+
+ALTER TABLE transaction ALTER COLUMN currency SET NOT NULL;
+
+-- Each currency has its own system accounts, so balances in different currencies never mix
+ALTER TABLE ledger_account
+	ADD COLUMN currency VARCHAR(3),
+	ADD FOREIGN KEY (currency) REFERENCES currency(code);
+
+UPDATE ledger_account SET currency = COALESCE(
+	(SELECT wallet.currency FROM wallet WHERE wallet.id = ledger_account.wallet_id),
+	'USD'
+);
+
+ALTER TABLE ledger_account
+	ALTER COLUMN currency SET NOT NULL,
+	DROP CONSTRAINT ledger_account_code_key,
+	ADD UNIQUE (code, currency);
+
+ALTER TABLE posting ALTER COLUMN amount TYPE NUMERIC;
+
+-- Postings must sum to zero in every currency of the journal entry
+CREATE OR REPLACE FUNCTION check_journal_entry_balanced() RETURNS trigger AS $$
+BEGIN
+    IF EXISTS (
+        SELECT 1
+        FROM posting
+        JOIN ledger_account ON ledger_account.id = posting.account_id
+        WHERE posting.journal_entry_id = NEW.journal_entry_id
+        GROUP BY ledger_account.currency
+        HAVING SUM(posting.amount) <> 0
+    ) THEN
+        RAISE EXCEPTION 'journal entry % does not balance', NEW.journal_entry_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
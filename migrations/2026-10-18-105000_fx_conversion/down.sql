@@ -0,0 +1,28 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM posting WHERE journal_entry_id IN (
+	SELECT journal_entry.id FROM journal_entry
+	JOIN transaction ON transaction.id = journal_entry.transaction_id
+	WHERE transaction.kind = 'conversion'
+);
+DELETE FROM journal_entry
+WHERE transaction_id IN (SELECT id FROM transaction WHERE kind = 'conversion');
+DELETE FROM transaction WHERE kind = 'conversion';
+-- recompute cached balances without the deleted postings
+UPDATE wallet SET balance = COALESCE((
+	SELECT SUM(posting.amount) FROM posting
+	JOIN ledger_account ON ledger_account.id = posting.account_id
+	WHERE ledger_account.wallet_id = wallet.id
+), 0);
+
+ALTER TABLE transaction
+	DROP CONSTRAINT transaction_conversion_check,
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal')),
+	DROP COLUMN fx_quote_id,
+	DROP COLUMN fx_rate,
+	DROP COLUMN to_currency,
+	DROP COLUMN to_amount;
+
+DROP TABLE fx_quote;
+DROP TABLE fx_rate;
@@ -0,0 +1,52 @@
+-- Your SQL goes here
+-- Rates are set by admins and take effect at `effective_at`, so a rate can be scheduled ahead of time
+-- and old rates are kept for auditing
+CREATE TABLE fx_rate (
+	id SERIAL PRIMARY KEY,
+	base_currency VARCHAR(3) NOT NULL,
+	quote_currency VARCHAR(3) NOT NULL,
+	-- Mid-market units of quote_currency per unit of base_currency
+	rate NUMERIC NOT NULL CHECK (rate > 0),
+	-- Fraction taken off the mid rate for customers, e.g. 0.005 for 0.5%
+	spread NUMERIC NOT NULL CHECK (spread >= 0 AND spread < 1),
+	effective_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+	CHECK (base_currency <> quote_currency),
+    FOREIGN KEY (base_currency) REFERENCES currency(code),
+    FOREIGN KEY (quote_currency) REFERENCES currency(code)
+);
+
+CREATE INDEX fx_rate_pair_idx ON fx_rate (base_currency, quote_currency, effective_at DESC);
+
+CREATE TABLE fx_quote (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	fx_rate_id INT NOT NULL,
+	from_currency VARCHAR(3) NOT NULL,
+	to_currency VARCHAR(3) NOT NULL,
+	from_amount NUMERIC NOT NULL CHECK (from_amount > 0),
+	to_amount NUMERIC NOT NULL CHECK (to_amount > 0),
+	-- Rate given to the customer, after the spread
+	rate NUMERIC NOT NULL,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	used_at TIMESTAMP WITH TIME ZONE,
+	created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (fx_rate_id) REFERENCES fx_rate(id),
+    FOREIGN KEY (from_currency) REFERENCES currency(code),
+    FOREIGN KEY (to_currency) REFERENCES currency(code)
+);
+
+-- A conversion debits `amount` in `currency` and credits `to_amount` in `to_currency`
+ALTER TABLE transaction
+	ADD COLUMN to_amount NUMERIC,
+	ADD COLUMN to_currency VARCHAR(3),
+	ADD COLUMN fx_rate NUMERIC,
+	ADD COLUMN fx_quote_id INT UNIQUE,
+	ADD FOREIGN KEY (to_currency) REFERENCES currency(code),
+	ADD FOREIGN KEY (fx_quote_id) REFERENCES fx_quote(id),
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion')),
+	ADD CONSTRAINT transaction_conversion_check
+		CHECK ((kind = 'conversion') = (to_amount IS NOT NULL AND to_currency IS NOT NULL AND fx_rate IS NOT NULL));
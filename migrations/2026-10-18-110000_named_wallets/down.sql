@@ -0,0 +1,31 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM posting WHERE journal_entry_id IN (
+	SELECT journal_entry.id FROM journal_entry
+	JOIN transaction ON transaction.id = journal_entry.transaction_id
+	WHERE transaction.kind = 'move'
+);
+DELETE FROM journal_entry
+WHERE transaction_id IN (SELECT id FROM transaction WHERE kind = 'move');
+DELETE FROM transaction WHERE kind = 'move';
+-- recompute cached balances without the deleted postings
+UPDATE wallet SET balance = COALESCE((
+	SELECT SUM(posting.amount) FROM posting
+	JOIN ledger_account ON ledger_account.id = posting.account_id
+	WHERE ledger_account.wallet_id = wallet.id
+), 0);
+
+ALTER TABLE transaction
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion'));
+
+-- fails if a user has more than one wallet in a currency
+DROP INDEX wallet_default_idx;
+DROP INDEX wallet_name_idx;
+
+ALTER TABLE wallet
+	DROP CONSTRAINT wallet_default_open_check,
+	DROP COLUMN closed_at,
+	DROP COLUMN is_default,
+	DROP COLUMN name,
+	ADD UNIQUE (user_id, currency);
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+-- Users can hold several named wallets per currency. Transfers are received into the user's
+-- default wallet in the currency. Closed wallets are kept for their history.
+ALTER TABLE wallet
+	ADD COLUMN name VARCHAR(60),
+	ADD COLUMN is_default BOOLEAN NOT NULL DEFAULT FALSE,
+	ADD COLUMN closed_at TIMESTAMPTZ,
+	DROP CONSTRAINT wallet_user_id_currency_key;
+
+-- existing wallets are the only one in their currency
+UPDATE wallet SET name = currency, is_default = TRUE;
+
+ALTER TABLE wallet
+	ALTER COLUMN name SET NOT NULL,
+	ADD CONSTRAINT wallet_default_open_check CHECK (NOT (is_default AND closed_at IS NOT NULL));
+
+CREATE UNIQUE INDEX wallet_name_idx ON wallet (user_id, name) WHERE closed_at IS NULL;
+CREATE UNIQUE INDEX wallet_default_idx ON wallet (user_id, currency) WHERE is_default;
+
+-- moves between a user's own wallets aren't payments to anyone
+ALTER TABLE transaction
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion', 'move'));
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE wallet
+	DROP CONSTRAINT wallet_closed_check,
+	DROP CONSTRAINT wallet_status_check,
+	ALTER COLUMN status TYPE BOOLEAN USING status <> 'frozen';
+
+ALTER TABLE users
+	DROP CONSTRAINT users_status_check,
+	ALTER COLUMN status TYPE BOOLEAN USING status = 'active';
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Users and wallets move between active, frozen and closed. Closed is final.
+ALTER TABLE users
+	ALTER COLUMN status TYPE VARCHAR(10)
+		USING CASE WHEN status THEN 'active' ELSE 'frozen' END,
+	ADD CONSTRAINT users_status_check CHECK (status IN ('active', 'frozen', 'closed'));
+
+ALTER TABLE wallet
+	ALTER COLUMN status TYPE VARCHAR(10)
+		USING CASE
+			WHEN closed_at IS NOT NULL THEN 'closed'
+			WHEN status THEN 'active'
+			ELSE 'frozen'
+		END,
+	ADD CONSTRAINT wallet_status_check CHECK (status IN ('active', 'frozen', 'closed')),
+	ADD CONSTRAINT wallet_closed_check CHECK ((status = 'closed') = (closed_at IS NOT NULL));
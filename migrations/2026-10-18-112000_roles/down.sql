@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM posting WHERE journal_entry_id IN (
+	SELECT journal_entry.id FROM journal_entry
+	JOIN transaction ON transaction.id = journal_entry.transaction_id
+	WHERE transaction.kind = 'adjustment'
+);
+DELETE FROM journal_entry
+WHERE transaction_id IN (SELECT id FROM transaction WHERE kind = 'adjustment');
+DELETE FROM transaction WHERE kind = 'adjustment';
+-- recompute cached balances without the deleted postings
+UPDATE wallet SET balance = COALESCE((
+	SELECT SUM(posting.amount) FROM posting
+	JOIN ledger_account ON ledger_account.id = posting.account_id
+	WHERE ledger_account.wallet_id = wallet.id
+), 0);
+
+ALTER TABLE transaction
+	DROP CONSTRAINT transaction_adjustment_check,
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion', 'move')),
+	DROP COLUMN reason,
+	DROP COLUMN admin_id;
+
+ALTER TABLE users
+	DROP CONSTRAINT users_role_check,
+	DROP COLUMN role;
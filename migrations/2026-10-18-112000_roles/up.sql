@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- What a user may do beyond their own account is decided by their role
+ALTER TABLE users
+	ADD COLUMN role VARCHAR(10) NOT NULL DEFAULT 'user',
+	ADD CONSTRAINT users_role_check CHECK (role IN ('user', 'support', 'admin'));
+
+-- Manual adjustments record the admin who made them and why
+ALTER TABLE transaction
+	ADD COLUMN admin_id INT,
+	ADD COLUMN reason TEXT,
+	ADD FOREIGN KEY (admin_id) REFERENCES users(id),
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion', 'move', 'adjustment')),
+	ADD CONSTRAINT transaction_adjustment_check
+		CHECK (kind <> 'adjustment' OR (admin_id IS NOT NULL AND btrim(reason) <> ''));
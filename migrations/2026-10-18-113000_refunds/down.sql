@@ -0,0 +1,26 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM posting WHERE journal_entry_id IN (
+	SELECT journal_entry.id FROM journal_entry
+	JOIN transaction ON transaction.id = journal_entry.transaction_id
+	WHERE transaction.original_transaction_id IS NOT NULL
+);
+DELETE FROM journal_entry WHERE transaction_id IN (
+	SELECT id FROM transaction WHERE original_transaction_id IS NOT NULL
+);
+DELETE FROM transaction WHERE original_transaction_id IS NOT NULL;
+-- recompute cached balances without the deleted postings
+UPDATE wallet SET balance = COALESCE((
+	SELECT SUM(posting.amount) FROM posting
+	JOIN ledger_account ON ledger_account.id = posting.account_id
+	WHERE ledger_account.wallet_id = wallet.id
+), 0);
+
+DROP INDEX transaction_original_transaction_id_idx;
+
+ALTER TABLE transaction
+	DROP CONSTRAINT transaction_reversal_check,
+	DROP CONSTRAINT transaction_original_check,
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion', 'move', 'adjustment')),
+	DROP COLUMN original_transaction_id;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Refunds by the recipient and reversals by an admin send back all or part of an earlier transfer
+ALTER TABLE transaction
+	ADD COLUMN original_transaction_id INT,
+	ADD FOREIGN KEY (original_transaction_id) REFERENCES transaction(id),
+	DROP CONSTRAINT transaction_kind_check,
+	ADD CONSTRAINT transaction_kind_check
+		CHECK (kind IN ('transfer', 'deposit', 'withdrawal', 'fee', 'reversal', 'conversion', 'move', 'adjustment', 'refund')),
+	ADD CONSTRAINT transaction_original_check
+		CHECK ((kind IN ('refund', 'reversal')) = (original_transaction_id IS NOT NULL)),
+	ADD CONSTRAINT transaction_reversal_check
+		CHECK (kind <> 'reversal' OR (admin_id IS NOT NULL AND btrim(reason) <> ''));
+
+CREATE INDEX transaction_original_transaction_id_idx ON transaction (original_transaction_id);
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- A user asking another user to pay them. Pending requests past `expires_at` are expired.
+CREATE TABLE payment_request (
+	id SERIAL PRIMARY KEY,
+	requester_id INT NOT NULL,
+	payer_id INT NOT NULL,
+	amount NUMERIC NOT NULL CHECK (amount > 0),
+	currency VARCHAR(3) NOT NULL,
+	note VARCHAR(255),
+	status VARCHAR(10) NOT NULL DEFAULT 'pending',
+	-- the transfer that paid an accepted request
+	transaction_id INT UNIQUE,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (requester_id) REFERENCES users(id),
+	FOREIGN KEY (payer_id) REFERENCES users(id),
+	FOREIGN KEY (currency) REFERENCES currency(code),
+	FOREIGN KEY (transaction_id) REFERENCES transaction(id),
+	CONSTRAINT payment_request_status_check
+		CHECK (status IN ('pending', 'accepted', 'declined', 'cancelled', 'expired')),
+	CONSTRAINT payment_request_accepted_check
+		CHECK ((status = 'accepted') = (transaction_id IS NOT NULL)),
+	CONSTRAINT payment_request_self_check CHECK (requester_id <> payer_id)
+);
+
+CREATE INDEX payment_request_requester_id_idx ON payment_request (requester_id, created_at DESC);
+CREATE INDEX payment_request_payer_id_idx ON payment_request (payer_id, created_at DESC);
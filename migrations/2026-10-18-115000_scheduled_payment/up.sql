@@ -0,0 +1,39 @@
+-- Your SQL goes here
+-- A transfer the scheduler makes on the user's behalf, once or on a recurring rule. Active
+-- schedules are paid once `next_run_at` has passed.
+CREATE TABLE scheduled_payment (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	wallet_id INT NOT NULL,
+	recipient_id INT NOT NULL,
+	amount NUMERIC NOT NULL CHECK (amount > 0),
+	currency VARCHAR(3) NOT NULL,
+	frequency VARCHAR(10) NOT NULL,
+	-- five field cron pattern of a 'cron' schedule
+	cron VARCHAR(100),
+	status VARCHAR(10) NOT NULL DEFAULT 'active',
+	starts_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	ends_at TIMESTAMP WITH TIME ZONE,
+	next_run_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	-- failed attempts at the current payment, reset once it is paid or given up on
+	failed_attempts INT NOT NULL DEFAULT 0,
+	last_run_at TIMESTAMP WITH TIME ZONE,
+	last_transaction_id INT,
+	last_error TEXT,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users(id),
+	FOREIGN KEY (wallet_id) REFERENCES wallet(id),
+	FOREIGN KEY (recipient_id) REFERENCES users(id),
+	FOREIGN KEY (currency) REFERENCES currency(code),
+	FOREIGN KEY (last_transaction_id) REFERENCES transaction(id),
+	CONSTRAINT scheduled_payment_frequency_check
+		CHECK (frequency IN ('once', 'weekly', 'monthly', 'cron')),
+	CONSTRAINT scheduled_payment_cron_check CHECK ((frequency = 'cron') = (cron IS NOT NULL)),
+	CONSTRAINT scheduled_payment_status_check
+		CHECK (status IN ('active', 'paused', 'held', 'cancelled', 'completed', 'failed')),
+	CONSTRAINT scheduled_payment_self_check CHECK (user_id <> recipient_id)
+);
+
+CREATE INDEX scheduled_payment_user_id_idx ON scheduled_payment (user_id, created_at DESC);
+CREATE INDEX scheduled_payment_due_idx ON scheduled_payment (next_run_at) WHERE status = 'active';
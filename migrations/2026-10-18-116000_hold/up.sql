@@ -0,0 +1,31 @@
+-- Your SQL goes here
+-- Funds in a wallet reserved for a merchant until they capture or void them. Active holds
+-- reduce the wallet's available balance but not its ledger balance, and stop counting once
+-- `expires_at` has passed.
+CREATE TABLE hold (
+	id SERIAL PRIMARY KEY,
+	wallet_id INT NOT NULL,
+	merchant_id INT NOT NULL,
+	amount NUMERIC NOT NULL CHECK (amount > 0),
+	currency VARCHAR(3) NOT NULL,
+	note VARCHAR(255),
+	status VARCHAR(10) NOT NULL DEFAULT 'active',
+	-- amount paid to the merchant, the rest is released
+	captured_amount NUMERIC,
+	-- the transfer to the merchant
+	transaction_id INT UNIQUE,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (wallet_id) REFERENCES wallet(id),
+	FOREIGN KEY (merchant_id) REFERENCES users(id),
+	FOREIGN KEY (currency) REFERENCES currency(code),
+	FOREIGN KEY (transaction_id) REFERENCES transaction(id),
+	CONSTRAINT hold_status_check CHECK (status IN ('active', 'captured', 'voided', 'expired')),
+	CONSTRAINT hold_captured_check CHECK ((status = 'captured') = (captured_amount IS NOT NULL)),
+	CONSTRAINT hold_captured_amount_check
+		CHECK (captured_amount > 0 AND captured_amount <= amount)
+);
+
+CREATE INDEX hold_wallet_id_idx ON hold (wallet_id) WHERE status = 'active';
+CREATE INDEX hold_merchant_id_idx ON hold (merchant_id, created_at DESC);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE spending_limit;
+
+ALTER TABLE users
+	DROP CONSTRAINT users_tier_check,
+	DROP COLUMN tier;
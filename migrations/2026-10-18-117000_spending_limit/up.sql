@@ -0,0 +1,30 @@
+-- Your SQL goes here
+-- Users get the spending limits of their tier unless they have their own
+ALTER TABLE users
+	ADD COLUMN tier VARCHAR(10) NOT NULL DEFAULT 'standard',
+	ADD CONSTRAINT users_tier_check CHECK (tier IN ('basic', 'standard', 'premium'));
+
+-- Caps on what a user can send and withdraw from their wallets in one currency, for a tier or
+-- for one user. A null column is no limit, and a user's limits fall back to their tier's one
+-- column at a time.
+CREATE TABLE spending_limit (
+	id SERIAL PRIMARY KEY,
+	tier VARCHAR(10),
+	user_id INT,
+	currency VARCHAR(3) NOT NULL,
+	per_transaction NUMERIC CHECK (per_transaction > 0),
+	daily_amount NUMERIC CHECK (daily_amount > 0),
+	weekly_amount NUMERIC CHECK (weekly_amount > 0),
+	monthly_amount NUMERIC CHECK (monthly_amount > 0),
+	daily_count INT CHECK (daily_count >= 0),
+	weekly_count INT CHECK (weekly_count >= 0),
+	monthly_count INT CHECK (monthly_count >= 0),
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users(id),
+	FOREIGN KEY (currency) REFERENCES currency(code),
+	CONSTRAINT spending_limit_owner_check CHECK ((tier IS NULL) <> (user_id IS NULL)),
+	CONSTRAINT spending_limit_tier_check CHECK (tier IN ('basic', 'standard', 'premium')),
+	CONSTRAINT spending_limit_tier_currency_key UNIQUE (tier, currency),
+	CONSTRAINT spending_limit_user_id_currency_key UNIQUE (user_id, currency)
+);
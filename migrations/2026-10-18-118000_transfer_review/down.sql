@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+UPDATE payment_request SET status = 'pending' WHERE status = 'held';
+
+ALTER TABLE payment_request
+	DROP CONSTRAINT payment_request_status_check,
+	ADD CONSTRAINT payment_request_status_check
+		CHECK (status IN ('pending', 'accepted', 'declined', 'cancelled', 'expired'));
+
+DROP TABLE transfer_review;
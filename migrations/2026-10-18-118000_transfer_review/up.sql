@@ -0,0 +1,54 @@
+-- Your SQL goes here
+-- A transfer the risk engine held for an admin to approve or reject, or blocked outright. Pending
+-- reviews reserve the amount in the sender's wallet until they are decided.
+CREATE TABLE transfer_review (
+	id SERIAL PRIMARY KEY,
+	user_id INT NOT NULL,
+	wallet_id INT NOT NULL,
+	recipient_id INT NOT NULL,
+	amount NUMERIC NOT NULL CHECK (amount > 0),
+	currency VARCHAR(3) NOT NULL,
+	score INT NOT NULL,
+	-- names of the risk rules that scored the transfer
+	rules TEXT[] NOT NULL,
+	status VARCHAR(10) NOT NULL DEFAULT 'pending',
+	reviewer_id INT,
+	note TEXT,
+	reviewed_at TIMESTAMP WITH TIME ZONE,
+	transaction_id INT UNIQUE,
+	-- what the transfer was made for, so deciding it can finish it. A review with none of these is
+	-- a plain transfer.
+	fx_quote_id INT,
+	payment_request_id INT,
+	hold_id INT,
+	scheduled_payment_id INT,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users(id),
+	FOREIGN KEY (wallet_id) REFERENCES wallet(id),
+	FOREIGN KEY (recipient_id) REFERENCES users(id),
+	FOREIGN KEY (currency) REFERENCES currency(code),
+	FOREIGN KEY (reviewer_id) REFERENCES users(id),
+	FOREIGN KEY (transaction_id) REFERENCES transaction(id),
+	FOREIGN KEY (fx_quote_id) REFERENCES fx_quote(id),
+	FOREIGN KEY (payment_request_id) REFERENCES payment_request(id),
+	FOREIGN KEY (hold_id) REFERENCES hold(id),
+	FOREIGN KEY (scheduled_payment_id) REFERENCES scheduled_payment(id),
+	CONSTRAINT transfer_review_status_check
+		CHECK (status IN ('pending', 'approved', 'rejected', 'blocked')),
+	CONSTRAINT transfer_review_approved_check CHECK (transaction_id IS NULL OR status = 'approved'),
+	CONSTRAINT transfer_review_reviewed_check
+		CHECK ((status IN ('approved', 'rejected')) = (reviewer_id IS NOT NULL)),
+	CONSTRAINT transfer_review_source_check
+		CHECK (num_nonnulls(fx_quote_id, payment_request_id, hold_id, scheduled_payment_id) <= 1)
+);
+
+CREATE INDEX transfer_review_queue_idx ON transfer_review (status, created_at);
+CREATE INDEX transfer_review_user_id_idx ON transfer_review (user_id, created_at DESC);
+CREATE INDEX transfer_review_wallet_id_idx ON transfer_review (wallet_id) WHERE status = 'pending';
+
+-- accepted requests whose payment is waiting for review
+ALTER TABLE payment_request
+	DROP CONSTRAINT payment_request_status_check,
+	ADD CONSTRAINT payment_request_status_check
+		CHECK (status IN ('pending', 'held', 'accepted', 'declined', 'cancelled', 'expired'));
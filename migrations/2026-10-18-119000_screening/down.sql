@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE screening_hit;
+
+ALTER TABLE users DROP COLUMN legal_name;
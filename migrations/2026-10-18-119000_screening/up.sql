@@ -0,0 +1,34 @@
+-- Your SQL goes here
+-- Screened along with the username, when given at sign up
+ALTER TABLE users ADD COLUMN legal_name VARCHAR(255);
+
+-- A user's name matching a watchlist entry. Exact matches are confirmed straight away, close ones
+-- wait for an admin to confirm or dismiss them. Users with a confirmed hit can't send or receive
+-- transfers, and sign ups that are confirmed hits are recorded without a user.
+CREATE TABLE screening_hit (
+	id SERIAL PRIMARY KEY,
+	user_id INT,
+	context VARCHAR(10) NOT NULL,
+	-- the user's name that matched
+	screened_name VARCHAR(255) NOT NULL,
+	entry_id VARCHAR(100) NOT NULL,
+	entry_name VARCHAR(255) NOT NULL,
+	-- similarity of the names, from 0 to 1
+	score DOUBLE PRECISION NOT NULL,
+	status VARCHAR(10) NOT NULL DEFAULT 'pending',
+	reviewer_id INT,
+	note TEXT,
+	reviewed_at TIMESTAMP WITH TIME ZONE,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users(id),
+	FOREIGN KEY (reviewer_id) REFERENCES users(id),
+	CONSTRAINT screening_hit_context_check CHECK (context IN ('sign_up', 'transfer')),
+	CONSTRAINT screening_hit_status_check CHECK (status IN ('pending', 'confirmed', 'dismissed')),
+	-- a dismissed hit isn't raised again for the same user
+	CONSTRAINT screening_hit_user_id_entry_id_key UNIQUE (user_id, entry_id)
+);
+
+CREATE INDEX screening_hit_queue_idx ON screening_hit (status, created_at);
+-- Blocked sign ups have no user, so they are recorded once per listed party instead
+CREATE UNIQUE INDEX screening_hit_sign_up_entry_id_key ON screening_hit (entry_id) WHERE user_id IS NULL;
@@ -0,0 +1,228 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+
+use crate::keys::KeyStore;
+
+/// Settings read from the environment at startup
+pub struct Config {
+    pub database_url: String,
+    /// Lifetime of the JWT returned by `/sign_in` and `/token/refresh`
+    pub access_token_ttl: Duration,
+    /// Lifetime of a refresh token, restarted each time it is rotated
+    pub refresh_token_ttl: Duration,
+    /// How long an idempotency key stays reserved for a request that never finished, e.g.
+    /// because the server stopped, before it can be used again
+    pub idempotency_reservation_ttl: Duration,
+    pub jwt_keys: KeyStore,
+    /// How long token revocation lookups are cached for. Revocations made by other instances can
+    /// take this long to be seen.
+    pub revocation_cache_ttl: Duration,
+    /// Lifetime of a password reset token
+    pub password_reset_ttl: Duration,
+    /// File that notifications are appended to. Notifications are logged when unset.
+    pub notifier_file: Option<PathBuf>,
+    /// Lifetime of an email verification token
+    pub email_verification_ttl: Duration,
+    pub unverified_policy: UnverifiedPolicy,
+    /// Lifetime of the token returned by `/sign_in` to users with two-factor authentication
+    pub mfa_challenge_ttl: Duration,
+    /// Transfers larger than this in their currency need a TOTP code. No step up in currencies
+    /// without one.
+    pub step_up_threshold: CurrencyAmounts,
+    /// Failed sign in attempts within `sign_in_failure_window` before an account is locked
+    pub sign_in_max_failures: i64,
+    /// Failed sign in attempts from one IP address within `sign_in_failure_window`, across all
+    /// accounts, before it is blocked
+    pub sign_in_max_ip_failures: i64,
+    pub sign_in_failure_window: Duration,
+    /// How long an account stays locked after too many failed sign in attempts
+    pub sign_in_lockout: Duration,
+    /// ISO 4217 code of the wallet opened at sign up, and of requests that don't name a currency
+    pub default_currency: String,
+    /// How long an FX quote can be used for a conversion
+    pub fx_quote_ttl: Duration,
+    /// How long a payment request can be accepted for
+    pub payment_request_ttl: Duration,
+    /// How long a hold reserves funds for if it isn't captured or voided
+    pub hold_ttl: Duration,
+    /// How often the scheduler looks for due scheduled payments
+    pub scheduler_interval: Duration,
+    /// Attempts at a scheduled payment, including the first, before it is given up on
+    pub scheduled_payment_max_attempts: i32,
+    /// Wait between attempts at a scheduled payment that failed, e.g. for insufficient funds
+    pub scheduled_payment_retry_delay: Duration,
+    /// Risk score at which a transfer is held for an admin to review
+    pub risk_review_score: i32,
+    /// Risk score at which a transfer is blocked
+    pub risk_block_score: i32,
+    /// Amount in each currency at which a first transfer to someone counts as risky
+    pub risk_large_amount: CurrencyAmounts,
+    /// `.csv` or `.json` list of parties users are screened against
+    pub watchlist_file: Option<PathBuf>,
+    /// Name similarity, from 0 to 1, at which a user is recorded as a possible watchlist match
+    pub watchlist_match_threshold: f64,
+    /// Whether a name with the same words as a listed party's is confirmed as a hit without
+    /// review, blocking the user, rather than recorded as pending
+    pub watchlist_auto_confirm: bool,
+}
+
+/// Which way money is moving for a user
+#[derive(Debug, Clone, Copy)]
+pub enum MoneyMovement {
+    /// Into the user's wallet, from a deposit or transfer
+    Receive,
+    /// Out of the user's wallet, from a withdrawal or transfer
+    Send,
+}
+
+/// What users who have not verified their email are allowed to do with their wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnverifiedPolicy {
+    /// No restrictions
+    Allow,
+    /// Can deposit and receive transfers, but not withdraw or send
+    ReceiveOnly,
+    /// Can't move money at all
+    Deny,
+}
+
+impl UnverifiedPolicy {
+    pub fn allows(self, movement: MoneyMovement) -> bool {
+        match self {
+            UnverifiedPolicy::Allow => true,
+            UnverifiedPolicy::ReceiveOnly => matches!(movement, MoneyMovement::Receive),
+            UnverifiedPolicy::Deny => false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("expected one of allow, receive_only or deny")]
+pub struct ParseUnverifiedPolicyError;
+
+impl FromStr for UnverifiedPolicy {
+    type Err = ParseUnverifiedPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(UnverifiedPolicy::Allow),
+            "receive_only" => Ok(UnverifiedPolicy::ReceiveOnly),
+            "deny" => Ok(UnverifiedPolicy::Deny),
+            _ => Err(ParseUnverifiedPolicyError),
+        }
+    }
+}
+
+/// An amount in each of several currencies, e.g. `USD:1000,EUR:900`. An amount without a currency
+/// is in `DEFAULT_CURRENCY`.
+#[derive(Debug, Clone, Default)]
+pub struct CurrencyAmounts(HashMap<String, BigDecimal>);
+
+impl CurrencyAmounts {
+    /// The amount in `currency`, if there is one
+    pub fn get(&self, currency: &str) -> Option<&BigDecimal> {
+        self.0.get(currency)
+    }
+
+    pub(crate) fn parse(value: &str, default_currency: &str) -> anyhow::Result<Self> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let (currency, amount) = part.split_once(':').unwrap_or((default_currency, part));
+                let amount = amount
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid amount {amount}"))?;
+                Ok((currency.trim().to_ascii_uppercase(), amount))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default_currency = env_or("DEFAULT_CURRENCY", "USD".to_string())?.to_ascii_uppercase();
+        Ok(Self {
+            database_url: std::env::var("DATABASE_URL").unwrap_or_default(),
+            access_token_ttl: Duration::seconds(env_or("ACCESS_TOKEN_TTL_SECS", 60 * 60)?),
+            refresh_token_ttl: Duration::seconds(env_or(
+                "REFRESH_TOKEN_TTL_SECS",
+                30 * 24 * 60 * 60,
+            )?),
+            idempotency_reservation_ttl: Duration::seconds(env_or(
+                "IDEMPOTENCY_RESERVATION_TTL_SECS",
+                60,
+            )?),
+            jwt_keys: KeyStore::from_env()?,
+            revocation_cache_ttl: Duration::seconds(env_or("REVOCATION_CACHE_TTL_SECS", 30)?),
+            password_reset_ttl: Duration::seconds(env_or("PASSWORD_RESET_TTL_SECS", 30 * 60)?),
+            notifier_file: std::env::var_os("NOTIFIER_FILE").map(PathBuf::from),
+            email_verification_ttl: Duration::seconds(env_or(
+                "EMAIL_VERIFICATION_TTL_SECS",
+                24 * 60 * 60,
+            )?),
+            unverified_policy: env_or("UNVERIFIED_ACCOUNT_POLICY", UnverifiedPolicy::ReceiveOnly)?,
+            mfa_challenge_ttl: Duration::seconds(env_or("MFA_CHALLENGE_TTL_SECS", 5 * 60)?),
+            step_up_threshold: env_amounts("STEP_UP_THRESHOLD", &default_currency)?
+                .unwrap_or_default(),
+            sign_in_max_failures: env_or("SIGN_IN_MAX_FAILURES", 5)?,
+            sign_in_max_ip_failures: env_or("SIGN_IN_MAX_IP_FAILURES", 50)?,
+            sign_in_failure_window: Duration::seconds(env_or(
+                "SIGN_IN_FAILURE_WINDOW_SECS",
+                15 * 60,
+            )?),
+            sign_in_lockout: Duration::seconds(env_or("SIGN_IN_LOCKOUT_SECS", 15 * 60)?),
+            default_currency: default_currency.clone(),
+            fx_quote_ttl: Duration::seconds(env_or("FX_QUOTE_TTL_SECS", 30)?),
+            payment_request_ttl: Duration::seconds(env_or(
+                "PAYMENT_REQUEST_TTL_SECS",
+                7 * 24 * 60 * 60,
+            )?),
+            hold_ttl: Duration::seconds(env_or("HOLD_TTL_SECS", 7 * 24 * 60 * 60)?),
+            scheduler_interval: Duration::seconds(env_or("SCHEDULER_INTERVAL_SECS", 60)?),
+            scheduled_payment_max_attempts: env_or("SCHEDULED_PAYMENT_MAX_ATTEMPTS", 3)?,
+            scheduled_payment_retry_delay: Duration::seconds(env_or(
+                "SCHEDULED_PAYMENT_RETRY_SECS",
+                60 * 60,
+            )?),
+            risk_review_score: env_or("RISK_REVIEW_SCORE", 50)?,
+            risk_block_score: env_or("RISK_BLOCK_SCORE", 100)?,
+            risk_large_amount: env_amounts("RISK_LARGE_AMOUNT", &default_currency)?.unwrap_or_else(
+                || CurrencyAmounts(HashMap::from([(default_currency, BigDecimal::from(1000))])),
+            ),
+            watchlist_file: std::env::var_os("WATCHLIST_FILE").map(PathBuf::from),
+            watchlist_match_threshold: env_or("WATCHLIST_MATCH_THRESHOLD", 0.9)?,
+            watchlist_auto_confirm: env_or("WATCHLIST_AUTO_CONFIRM", false)?,
+        })
+    }
+}
+
+/// Parses the variable `name`, falling back to `default` when it is unset
+fn env_or<T>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("Invalid value for {name}: {value}")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Parses the variable `name` as amounts per currency, if it is set
+fn env_amounts(name: &str, default_currency: &str) -> anyhow::Result<Option<CurrencyAmounts>> {
+    match std::env::var(name) {
+        Ok(value) => CurrencyAmounts::parse(&value, default_currency)
+            .map(Some)
+            .with_context(|| format!("Invalid value for {name}: {value}")),
+        Err(_) => Ok(None),
+    }
+}
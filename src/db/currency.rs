@@ -0,0 +1,42 @@
+use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use super::{handle_duplicate_error, models::Currency, schema::currency, Error, SmplDB};
+
+/// Returns the minor units of the currency, if it is supported
+pub(super) async fn minor_units(conn: &mut AsyncPgConnection, code: &str) -> Result<i16, Error> {
+    currency::table
+        .find(code)
+        .select(currency::minor_units)
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::UnsupportedCurrency)
+}
+
+/// Returns `amount` with exactly as many decimal places as the currency's minor units. Rejects it
+/// if `code` isn't a supported currency or the amount has more decimal places than that.
+pub(super) async fn scale_amount(
+    conn: &mut AsyncPgConnection,
+    code: &str,
+    amount: &BigDecimal,
+) -> Result<BigDecimal, Error> {
+    let minor_units = minor_units(conn, code).await?;
+    if amount.normalized().fractional_digit_count() > i64::from(minor_units) {
+        return Err(Error::InvalidPrecision { minor_units });
+    }
+    Ok(amount.with_scale(minor_units.into()))
+}
+
+impl SmplDB {
+    pub async fn list_currencies(&self) -> Result<Vec<Currency>, Error> {
+        let mut conn = self.get_conn().await?;
+        currency::table
+            .select(Currency::as_select())
+            .order(currency::code.asc())
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+}
@@ -1,6 +1,8 @@
 use diesel_async::pooled_connection::deadpool::PoolError;
 use thiserror::Error;
 
+use super::Limit;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to get connection from diesel connection pool: {0}")]
@@ -9,6 +11,88 @@ pub enum Error {
     DieselFailure(#[from] diesel::result::Error),
     #[error("Unique Violation in DB")]
     Duplicate,
-    #[error("Transaction was rolled back")]
-    RollbackTransaction,
+    #[error("Idempotency key reservation expired and the key was reserved again")]
+    IdempotencyKeyExpired,
+    #[error("Wallet balance is lower than the amount")]
+    InsufficientFunds,
+    #[error("No user with the recipient's username")]
+    RecipientNotFound,
+    #[error("Sender and recipient are the same user")]
+    SelfTransfer,
+    #[error("Wallet is frozen")]
+    WalletFrozen,
+    #[error("Recipient's wallet is frozen")]
+    RecipientWalletFrozen,
+    #[error("Currency is not supported")]
+    UnsupportedCurrency,
+    #[error("Amount has more than {minor_units} decimal places")]
+    InvalidPrecision { minor_units: i16 },
+    #[error("User has no wallet in the currency")]
+    WalletNotFound,
+    #[error("Recipient has no wallet in the currency")]
+    RecipientWalletNotFound,
+    #[error("No user with the id")]
+    UserNotFound,
+    #[error("Status can't change to the requested one")]
+    InvalidStatusTransition,
+    #[error("Account is frozen")]
+    AccountFrozen,
+    #[error("Account is closed")]
+    AccountClosed,
+    #[error("Recipient's account is frozen")]
+    RecipientFrozen,
+    #[error("Wallet is in a different currency")]
+    CurrencyMismatch,
+    #[error("Wallet still has a balance")]
+    WalletNotEmpty,
+    #[error("Default wallet can't be closed while the user has other wallets in its currency")]
+    DefaultWalletInUse,
+    #[error("No FX rate for the currency pair")]
+    FxRateNotFound,
+    #[error("Converted amount rounds to zero")]
+    ConversionTooSmall,
+    #[error("No FX quote with the id for the user")]
+    FxQuoteNotFound,
+    #[error("FX quote has expired")]
+    FxQuoteExpired,
+    #[error("FX quote was already used")]
+    FxQuoteUsed,
+    #[error("No transaction with the id for the user")]
+    TransactionNotFound,
+    #[error("Transaction isn't a transfer the user received")]
+    NotRefundable,
+    #[error("Amount is zero or more than is left to refund")]
+    RefundExceedsPayment,
+    #[error("No payment request with the id for the user")]
+    PaymentRequestNotFound,
+    #[error("Payment request was already accepted, declined, cancelled or expired")]
+    PaymentRequestNotPending,
+    #[error("Payment request has expired")]
+    PaymentRequestExpired,
+    #[error("No scheduled payment with the id for the user")]
+    ScheduledPaymentNotFound,
+    #[error("Cron pattern is invalid, or given for a schedule that isn't cron")]
+    InvalidCronPattern,
+    #[error("Schedule ends before its first payment")]
+    NoScheduledRuns,
+    #[error("No hold with the id for the user")]
+    HoldNotFound,
+    #[error("Hold was already captured, voided or expired")]
+    HoldNotActive,
+    #[error("Hold has expired")]
+    HoldExpired,
+    #[error("Amount is zero or more than the hold")]
+    CaptureExceedsHold,
+    #[error("Payment would go over the {0}")]
+    LimitExceeded(Limit),
+    #[error("No transfer review with the id")]
+    TransferReviewNotFound,
+    #[error("Transfer review was already decided")]
+    TransferReviewNotPending,
+    #[error("Transfer was blocked as likely fraud")]
+    TransferBlocked,
+    #[error("No screening hit with the id")]
+    ScreeningHitNotFound,
+    #[error("Sender or recipient has a confirmed watchlist match")]
+    ScreeningMatch,
 }
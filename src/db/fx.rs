@@ -0,0 +1,186 @@
+use bigdecimal::{BigDecimal, One, RoundingMode, Zero};
+use chrono::{DateTime, Duration, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+
+use super::{
+    currency, handle_duplicate_error, handle_transaction_error,
+    models::{FxQuote, FxRate},
+    schema::{fx_quote, fx_rate},
+    Error, SmplDB,
+};
+
+/// Returns the rate for converting `base` into `quote` in effect now
+async fn current_rate(
+    conn: &mut AsyncPgConnection,
+    base: &str,
+    quote: &str,
+) -> Result<Option<FxRate>, diesel::result::Error> {
+    fx_rate::table
+        .filter(fx_rate::base_currency.eq(base))
+        .filter(fx_rate::quote_currency.eq(quote))
+        .filter(fx_rate::effective_at.le(Utc::now()))
+        .select(FxRate::as_select())
+        .order(fx_rate::effective_at.desc())
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// Marks the user's FX quote as used and returns it, unless it has expired or was already used.
+/// Must be called inside a DB transaction.
+pub(super) async fn use_quote(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    quote_id: i32,
+) -> Result<FxQuote, Error> {
+    let (quote, used_at): (FxQuote, Option<DateTime<Utc>>) = fx_quote::table
+        .filter(fx_quote::id.eq(quote_id))
+        .filter(fx_quote::user_id.eq(user_id))
+        .select((FxQuote::as_select(), fx_quote::used_at))
+        .for_update()
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::FxQuoteNotFound)?;
+
+    if used_at.is_some() {
+        return Err(Error::FxQuoteUsed);
+    }
+    let now = Utc::now();
+    if quote.expires_at <= now {
+        return Err(Error::FxQuoteExpired);
+    }
+
+    diesel::update(fx_quote::table.find(quote_id))
+        .set(fx_quote::used_at.eq(now))
+        .execute(conn)
+        .await?;
+    Ok(quote)
+}
+
+/// Returns the FX quote a held conversion was made with
+pub(super) async fn get_quote(
+    conn: &mut AsyncPgConnection,
+    quote_id: i32,
+) -> Result<FxQuote, Error> {
+    fx_quote::table
+        .find(quote_id)
+        .select(FxQuote::as_select())
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::FxQuoteNotFound)
+}
+
+impl SmplDB {
+    /// Adds a rate for converting `base` into `quote`, in effect from `effective_at`
+    pub async fn set_fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        rate: &BigDecimal,
+        spread: &BigDecimal,
+        effective_at: DateTime<Utc>,
+    ) -> Result<FxRate, Error> {
+        let mut conn = self.get_conn().await?;
+        currency::minor_units(&mut conn, base).await?;
+        currency::minor_units(&mut conn, quote).await?;
+
+        diesel::insert_into(fx_rate::table)
+            .values((
+                fx_rate::base_currency.eq(base),
+                fx_rate::quote_currency.eq(quote),
+                fx_rate::rate.eq(rate),
+                fx_rate::spread.eq(spread),
+                fx_rate::effective_at.eq(effective_at),
+            ))
+            .returning(FxRate::as_returning())
+            .get_result(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Returns the rate in effect now for every currency pair that has one
+    pub async fn list_fx_rates(&self) -> Result<Vec<FxRate>, Error> {
+        let mut conn = self.get_conn().await?;
+        fx_rate::table
+            .filter(fx_rate::effective_at.le(Utc::now()))
+            .distinct_on((fx_rate::base_currency, fx_rate::quote_currency))
+            .select(FxRate::as_select())
+            .order((
+                fx_rate::base_currency,
+                fx_rate::quote_currency,
+                fx_rate::effective_at.desc(),
+            ))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Returns the user's FX quote, whether or not it can still be used
+    pub async fn get_fx_quote(
+        &self,
+        user_id: i32,
+        quote_id: i32,
+    ) -> Result<Option<FxQuote>, Error> {
+        let mut conn = self.get_conn().await?;
+        Ok(fx_quote::table
+            .filter(fx_quote::id.eq(quote_id))
+            .filter(fx_quote::user_id.eq(user_id))
+            .select(FxQuote::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?)
+    }
+
+    /// Quotes converting `amount` of `from` into `to` at the current rate less its spread. The
+    /// quote can be used for a conversion until `ttl` has passed.
+    pub async fn create_fx_quote(
+        &self,
+        user_id: i32,
+        from: &str,
+        to: &str,
+        amount: &BigDecimal,
+        ttl: Duration,
+    ) -> Result<FxQuote, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let from_amount = currency::scale_amount(conn, from, amount).await?;
+                let to_minor_units = currency::minor_units(conn, to).await?;
+                let fx_rate = current_rate(conn, from, to)
+                    .await?
+                    .ok_or(Error::FxRateNotFound)?;
+
+                let rate = (&fx_rate.rate * (BigDecimal::one() - &fx_rate.spread)).normalized();
+                // round in the platform's favour
+                let to_amount = (&from_amount * &rate)
+                    .with_scale_round(to_minor_units.into(), RoundingMode::Down);
+                if to_amount <= BigDecimal::zero() {
+                    return Err(Error::ConversionTooSmall);
+                }
+
+                Ok(diesel::insert_into(fx_quote::table)
+                    .values((
+                        fx_quote::user_id.eq(user_id),
+                        fx_quote::fx_rate_id.eq(fx_rate.id),
+                        fx_quote::from_currency.eq(from),
+                        fx_quote::to_currency.eq(to),
+                        fx_quote::from_amount.eq(&from_amount),
+                        fx_quote::to_amount.eq(&to_amount),
+                        fx_quote::rate.eq(&rate),
+                        fx_quote::expires_at.eq(Utc::now() + ttl),
+                    ))
+                    .returning(FxQuote::as_returning())
+                    .get_result(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+}
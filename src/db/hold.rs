@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Utc};
+use diesel::{
+    dsl, BoolExpressionMethods, ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Deserialize;
+
+use super::{
+    currency, handle_duplicate_error, handle_transaction_error,
+    idempotency::{complete_idempotent_request, IdempotentRequest},
+    models::{AccountStatus, Hold, HoldStatus, Wallet},
+    schema::{hold, users, wallet},
+    screening::has_confirmed_hit,
+    transaction::{pay, wallet_owner},
+    transfer_review::{self, AssessRisk, PaymentOutcome, TransferSource},
+    users::{lock_active_user, lock_recipient},
+    wallet::{has_default_wallet, lock_wallet},
+    Error, SmplDB, WalletRef,
+};
+
+/// Which of the user's holds to list
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldParty {
+    /// Holds on the user's wallets
+    Payer,
+    /// Holds the user can capture
+    Merchant,
+}
+
+/// A hold with the usernames of the payer and the merchant
+pub type HoldWithUsers = (Hold, String, String);
+
+/// Total of the wallet's active, unexpired holds and transfers waiting for review
+pub(super) async fn held_balance(
+    conn: &mut AsyncPgConnection,
+    wallet_id: i32,
+) -> Result<BigDecimal, diesel::result::Error> {
+    let held: Option<BigDecimal> = hold::table
+        .filter(hold::wallet_id.eq(wallet_id))
+        .filter(hold::status.eq(HoldStatus::Active))
+        .filter(hold::expires_at.gt(Utc::now()))
+        .select(dsl::sum(hold::amount))
+        .first(conn)
+        .await?;
+    Ok(held.unwrap_or_default() + transfer_review::pending_balance(conn, wallet_id).await?)
+}
+
+/// The wallet's balance less its holds and transfers waiting for review. The wallet should be locked so holds can't be added
+/// while the caller relies on it.
+pub(super) async fn available_balance(
+    conn: &mut AsyncPgConnection,
+    wallet: &Wallet,
+) -> Result<BigDecimal, diesel::result::Error> {
+    Ok(&wallet.balance - held_balance(conn, wallet.id).await?)
+}
+
+/// Marks the active holds past their expiry as expired. Expired holds already don't count
+/// against balances, this only updates their status.
+async fn expire_holds(
+    conn: &mut AsyncPgConnection,
+    user_id: Option<i32>,
+) -> Result<usize, diesel::result::Error> {
+    let now = Utc::now();
+    let query = diesel::update(hold::table)
+        .filter(hold::status.eq(HoldStatus::Active))
+        .filter(hold::expires_at.le(now))
+        .set((
+            hold::status.eq(HoldStatus::Expired),
+            hold::updated_at.eq(now),
+        ));
+    match user_id {
+        Some(user_id) => {
+            let wallets = wallet::table
+                .filter(wallet::user_id.eq(user_id))
+                .select(wallet::id);
+            query
+                .filter(
+                    hold::wallet_id
+                        .eq_any(wallets)
+                        .or(hold::merchant_id.eq(user_id)),
+                )
+                .execute(conn)
+                .await
+        }
+        None => query.execute(conn).await,
+    }
+}
+
+/// Finishes a capture that was held for review, paying the merchant with the transfer if it was
+/// approved or voiding the hold if it was rejected
+pub(super) async fn resolve_held_capture(
+    conn: &mut AsyncPgConnection,
+    hold_id: i32,
+    transaction_id: Option<i32>,
+) -> Result<(), diesel::result::Error> {
+    let query = diesel::update(hold::table.find(hold_id));
+    match transaction_id {
+        Some(transaction_id) => {
+            query
+                .set((
+                    hold::transaction_id.eq(transaction_id),
+                    hold::updated_at.eq(Utc::now()),
+                ))
+                .execute(conn)
+                .await?
+        }
+        None => {
+            query
+                .set((
+                    hold::status.eq(HoldStatus::Voided),
+                    hold::captured_amount.eq(None::<BigDecimal>),
+                    hold::updated_at.eq(Utc::now()),
+                ))
+                .execute(conn)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+/// Locks an active, unexpired hold the merchant can capture for update
+async fn lock_active_hold(
+    conn: &mut AsyncPgConnection,
+    merchant_id: i32,
+    hold_id: i32,
+) -> Result<Hold, Error> {
+    let found: Hold = hold::table
+        .find(hold_id)
+        .filter(hold::merchant_id.eq(merchant_id))
+        .select(Hold::as_select())
+        .for_update()
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::HoldNotFound)?;
+    match found.status {
+        HoldStatus::Active if found.expires_at > Utc::now() => Ok(found),
+        HoldStatus::Active | HoldStatus::Expired => Err(Error::HoldExpired),
+        _ => Err(Error::HoldNotActive),
+    }
+}
+
+/// Returns the hold if it is on the user's wallet or they are its merchant
+async fn find_hold(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    hold_id: i32,
+) -> Result<HoldWithUsers, Error> {
+    let (payer, merchant) = diesel::alias!(users as payer, users as merchant);
+    hold::table
+        .inner_join(wallet::table)
+        .inner_join(payer.on(wallet::user_id.eq(payer.field(users::id))))
+        .inner_join(merchant.on(hold::merchant_id.eq(merchant.field(users::id))))
+        .filter(hold::id.eq(hold_id))
+        .filter(
+            wallet::user_id
+                .eq(user_id)
+                .or(hold::merchant_id.eq(user_id)),
+        )
+        .select((
+            Hold::as_select(),
+            payer.field(users::username),
+            merchant.field(users::username),
+        ))
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::HoldNotFound)
+}
+
+impl SmplDB {
+    /// Reserves `amount` of the user's wallet for `merchant_username` until `expires_at`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn authorize_hold(
+        &self,
+        user_id: i32,
+        from_wallet: &WalletRef,
+        merchant_username: &str,
+        amount: BigDecimal,
+        note: Option<&str>,
+        expires_at: DateTime<Utc>,
+        idempotency: Option<IdempotentRequest<'_, HoldWithUsers>>,
+    ) -> Result<HoldWithUsers, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                lock_active_user(conn, user_id).await?;
+                let from_wallet = lock_wallet(conn, user_id, from_wallet).await?;
+                if from_wallet.status != AccountStatus::Active {
+                    return Err(Error::WalletFrozen);
+                }
+                let amount = currency::scale_amount(conn, &from_wallet.currency, &amount).await?;
+
+                let merchant_id = lock_recipient(conn, merchant_username).await?;
+                if merchant_id == user_id {
+                    return Err(Error::SelfTransfer);
+                }
+                if !has_default_wallet(conn, merchant_id, &from_wallet.currency).await? {
+                    return Err(Error::RecipientWalletNotFound);
+                }
+                if has_confirmed_hit(conn, &[user_id, merchant_id]).await? {
+                    return Err(Error::ScreeningMatch);
+                }
+
+                if available_balance(conn, &from_wallet).await? < amount {
+                    return Err(Error::InsufficientFunds);
+                }
+
+                let hold_id: i32 = diesel::insert_into(hold::table)
+                    .values((
+                        hold::wallet_id.eq(from_wallet.id),
+                        hold::merchant_id.eq(merchant_id),
+                        hold::amount.eq(amount),
+                        hold::currency.eq(&from_wallet.currency),
+                        hold::note.eq(note),
+                        hold::expires_at.eq(expires_at),
+                    ))
+                    .returning(hold::id)
+                    .get_result(conn)
+                    .await?;
+                let authorized = find_hold(conn, user_id, hold_id).await?;
+                complete_idempotent_request(conn, idempotency, &authorized).await?;
+                Ok(authorized)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Pays `amount`, or all, of an active hold to the merchant and releases the rest. If `risk`
+    /// holds the payment for review the hold stays captured until it is decided, and if it
+    /// blocks the payment the hold stays active.
+    pub async fn capture_hold(
+        &self,
+        merchant_id: i32,
+        hold_id: i32,
+        amount: Option<BigDecimal>,
+        risk: &dyn AssessRisk,
+        idempotency: Option<IdempotentRequest<'_, (PaymentOutcome, HoldWithUsers)>>,
+    ) -> Result<(PaymentOutcome, HoldWithUsers), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let found = lock_active_hold(conn, merchant_id, hold_id).await?;
+                let amount = match amount {
+                    Some(amount) => currency::scale_amount(conn, &found.currency, &amount).await?,
+                    None => found.amount.clone(),
+                };
+                if amount.is_zero() || amount > found.amount {
+                    return Err(Error::CaptureExceedsHold);
+                }
+
+                // captured first so the hold no longer counts against the payment
+                diesel::update(hold::table.find(found.id))
+                    .set((
+                        hold::status.eq(HoldStatus::Captured),
+                        hold::captured_amount.eq(&amount),
+                        hold::updated_at.eq(Utc::now()),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                let (payer_id, _) = wallet_owner(conn, found.wallet_id).await?;
+                let merchant_username: String = users::table
+                    .find(merchant_id)
+                    .select(users::username)
+                    .first(conn)
+                    .await?;
+                let from_wallet = WalletRef::Id {
+                    id: found.wallet_id,
+                    currency: Some(found.currency),
+                };
+                let outcome = pay(
+                    conn,
+                    payer_id,
+                    &from_wallet,
+                    &merchant_username,
+                    &amount,
+                    risk,
+                    TransferSource::Hold(found.id),
+                )
+                .await?;
+
+                match &outcome {
+                    PaymentOutcome::Paid(transaction) => {
+                        diesel::update(hold::table.find(found.id))
+                            .set(hold::transaction_id.eq(transaction.id))
+                            .execute(conn)
+                            .await?;
+                    }
+                    PaymentOutcome::Held(_) => {}
+                    PaymentOutcome::Blocked(_) => {
+                        diesel::update(hold::table.find(found.id))
+                            .set((
+                                hold::status.eq(HoldStatus::Active),
+                                hold::captured_amount.eq(None::<BigDecimal>),
+                            ))
+                            .execute(conn)
+                            .await?;
+                    }
+                }
+                let captured = (outcome, find_hold(conn, merchant_id, found.id).await?);
+                complete_idempotent_request(conn, idempotency, &captured).await?;
+                Ok(captured)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Releases an active hold without paying the merchant
+    pub async fn void_hold(&self, merchant_id: i32, hold_id: i32) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let found = lock_active_hold(conn, merchant_id, hold_id).await?;
+                diesel::update(hold::table.find(found.id))
+                    .set((
+                        hold::status.eq(HoldStatus::Voided),
+                        hold::updated_at.eq(Utc::now()),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Returns the hold if it is on the user's wallet or they are its merchant
+    pub async fn get_hold(&self, user_id: i32, hold_id: i32) -> Result<HoldWithUsers, Error> {
+        let mut conn = self.get_conn().await?;
+        expire_holds(&mut conn, Some(user_id)).await?;
+        find_hold(&mut conn, user_id, hold_id).await
+    }
+
+    /// Returns the holds on the user's wallets and that they can capture, newest first
+    pub async fn list_holds(
+        &self,
+        user_id: i32,
+        party: Option<HoldParty>,
+        status: Option<HoldStatus>,
+    ) -> Result<Vec<HoldWithUsers>, Error> {
+        let mut conn = self.get_conn().await?;
+        expire_holds(&mut conn, Some(user_id)).await?;
+
+        let (payer, merchant) = diesel::alias!(users as payer, users as merchant);
+        let mut query = hold::table
+            .inner_join(wallet::table)
+            .inner_join(payer.on(wallet::user_id.eq(payer.field(users::id))))
+            .inner_join(merchant.on(hold::merchant_id.eq(merchant.field(users::id))))
+            .select((
+                Hold::as_select(),
+                payer.field(users::username),
+                merchant.field(users::username),
+            ))
+            .into_boxed();
+        query = match party {
+            Some(HoldParty::Payer) => query.filter(wallet::user_id.eq(user_id)),
+            Some(HoldParty::Merchant) => query.filter(hold::merchant_id.eq(user_id)),
+            None => query.filter(
+                wallet::user_id
+                    .eq(user_id)
+                    .or(hold::merchant_id.eq(user_id)),
+            ),
+        };
+        if let Some(status) = status {
+            query = query.filter(hold::status.eq(status));
+        }
+        query
+            .order((hold::created_at.desc(), hold::id.desc()))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Totals of the active holds and transfers waiting for review on each of the wallets that has
+    /// any
+    pub async fn held_balances(
+        &self,
+        wallet_ids: &[i32],
+    ) -> Result<HashMap<i32, BigDecimal>, Error> {
+        let mut conn = self.get_conn().await?;
+        let held: Vec<(i32, Option<BigDecimal>)> = hold::table
+            .filter(hold::wallet_id.eq_any(wallet_ids))
+            .filter(hold::status.eq(HoldStatus::Active))
+            .filter(hold::expires_at.gt(Utc::now()))
+            .group_by(hold::wallet_id)
+            .select((hold::wallet_id, dsl::sum(hold::amount)))
+            .load(&mut conn)
+            .await?;
+        let mut held: HashMap<i32, BigDecimal> = held
+            .into_iter()
+            .map(|(wallet_id, amount)| (wallet_id, amount.unwrap_or_default()))
+            .collect();
+        for (wallet_id, pending) in transfer_review::pending_balances(&mut conn, wallet_ids).await?
+        {
+            *held.entry(wallet_id).or_default() += pending;
+        }
+        Ok(held)
+    }
+
+    /// Marks every active hold past its expiry as expired, returning how many were
+    pub async fn expire_holds(&self) -> Result<usize, Error> {
+        let mut conn = self.get_conn().await?;
+        Ok(expire_holds(&mut conn, None).await?)
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error, models::IdempotencyRecord, schema::idempotency_key, Error, SmplDB,
+};
+
+/// Result of reserving an idempotency key
+#[derive(Debug)]
+pub enum KeyReservation {
+    /// Newly reserved, with the id of the reservation
+    Reserved(i32),
+    /// Already reserved, with what is stored under it
+    Taken(IdempotencyRecord),
+}
+
+/// A response stored under an idempotency key so replays can return it
+#[derive(Debug)]
+pub struct StoredResponse {
+    pub status: i32,
+    pub body: String,
+}
+
+/// A request made with a reserved idempotency key, whose response is stored in the same DB
+/// transaction as its changes
+pub struct IdempotentRequest<'a, T> {
+    /// Id of the reserved key
+    pub key_id: i32,
+    /// The response to the request's result, or `None` to free the key so the request can be
+    /// made again
+    pub respond: &'a (dyn Fn(&T) -> Option<StoredResponse> + Sync),
+}
+
+/// Stores the response to `result` under the request's key, or frees the key if there is none.
+/// Must be called inside the DB transaction that made `result`, so the response is stored if and
+/// only if the changes are. Fails if the reservation expired and the key was reserved again.
+pub(super) async fn complete_idempotent_request<T>(
+    conn: &mut AsyncPgConnection,
+    request: Option<IdempotentRequest<'_, T>>,
+    result: &T,
+) -> Result<(), Error> {
+    let Some(request) = request else {
+        return Ok(());
+    };
+    let reservation = idempotency_key::table
+        .find(request.key_id)
+        .filter(idempotency_key::response_status.is_null());
+    let updated = match (request.respond)(result) {
+        Some(response) => {
+            diesel::update(reservation)
+                .set((
+                    idempotency_key::response_status.eq(response.status),
+                    idempotency_key::response_body.eq(response.body),
+                ))
+                .execute(conn)
+                .await?
+        }
+        None => diesel::delete(reservation).execute(conn).await?,
+    };
+    if updated == 0 {
+        return Err(Error::IdempotencyKeyExpired);
+    }
+    Ok(())
+}
+
+impl SmplDB {
+    /// Reserves `key` for the user, replacing a reservation without a response that is older than
+    /// `ttl`, e.g. one whose request was cut off.
+    pub async fn reserve_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<KeyReservation, Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::delete(idempotency_key::table)
+            .filter(idempotency_key::user_id.eq(user_id))
+            .filter(idempotency_key::key.eq(key))
+            .filter(idempotency_key::response_status.is_null())
+            .filter(idempotency_key::created_at.lt(Utc::now() - ttl))
+            .execute(&mut conn)
+            .await?;
+
+        let reserved = diesel::insert_into(idempotency_key::table)
+            .values((
+                idempotency_key::user_id.eq(user_id),
+                idempotency_key::key.eq(key),
+                idempotency_key::request_hash.eq(request_hash),
+            ))
+            .on_conflict((idempotency_key::user_id, idempotency_key::key))
+            .do_nothing()
+            .returning(idempotency_key::id)
+            .get_result(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)?;
+        if let Some(key_id) = reserved {
+            return Ok(KeyReservation::Reserved(key_id));
+        }
+
+        idempotency_key::table
+            .select(IdempotencyRecord::as_select())
+            .filter(idempotency_key::user_id.eq(user_id))
+            .filter(idempotency_key::key.eq(key))
+            .first(&mut conn)
+            .await
+            .map(KeyReservation::Taken)
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Frees a reserved key whose request failed, so the client can retry with it.
+    pub async fn release_idempotency_key(&self, key_id: i32) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::delete(idempotency_key::table.find(key_id))
+            .filter(idempotency_key::response_status.is_null())
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::Utc;
+use diesel::{ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error,
+    models::{LedgerLine, Wallet},
+    schema::{journal_entry, ledger_account, posting, wallet},
+    Error, SmplDB, WalletRef,
+};
+
+/// Accounts owned by the platform rather than a user
+#[derive(Debug, Clone, Copy)]
+pub enum SystemAccount {
+    /// Counterparty of every deposit
+    ExternalCashIn,
+    /// Counterparty of every withdrawal
+    ExternalCashOut,
+    /// Holds the platform's side of currency conversions, including the spread it earns
+    FxPosition,
+    /// Counterparty of every manual adjustment
+    ManualAdjustment,
+}
+
+impl SystemAccount {
+    fn code(self) -> &'static str {
+        match self {
+            SystemAccount::ExternalCashIn => "external_cash_in",
+            SystemAccount::ExternalCashOut => "external_cash_out",
+            SystemAccount::FxPosition => "fx_position",
+            SystemAccount::ManualAdjustment => "manual_adjustment",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Account {
+    Wallet(i32),
+    System(SystemAccount),
+}
+
+/// Creates the ledger account backing a newly created wallet
+pub(super) async fn open_wallet_account(
+    conn: &mut AsyncPgConnection,
+    wallet_id: i32,
+    currency: &str,
+) -> Result<i32, diesel::result::Error> {
+    diesel::insert_into(ledger_account::table)
+        .values((
+            ledger_account::wallet_id.eq(wallet_id),
+            ledger_account::currency.eq(currency),
+        ))
+        .returning(ledger_account::id)
+        .get_result(conn)
+        .await
+}
+
+/// Returns the id of the account's ledger account in `currency`. System accounts are opened the
+/// first time a currency uses them, while a wallet's account only exists in the wallet's currency.
+async fn resolve_account(
+    conn: &mut AsyncPgConnection,
+    account: Account,
+    currency: &str,
+) -> Result<i32, diesel::result::Error> {
+    match account {
+        Account::Wallet(wallet_id) => {
+            ledger_account::table
+                .filter(ledger_account::wallet_id.eq(wallet_id))
+                .filter(ledger_account::currency.eq(currency))
+                .select(ledger_account::id)
+                .first(conn)
+                .await
+        }
+        Account::System(system) => {
+            diesel::insert_into(ledger_account::table)
+                .values((
+                    ledger_account::code.eq(system.code()),
+                    ledger_account::currency.eq(currency),
+                ))
+                .on_conflict((ledger_account::code, ledger_account::currency))
+                .do_nothing()
+                .execute(conn)
+                .await?;
+            ledger_account::table
+                .filter(ledger_account::code.eq(system.code()))
+                .filter(ledger_account::currency.eq(currency))
+                .select(ledger_account::id)
+                .first(conn)
+                .await
+        }
+    }
+}
+
+/// Records a journal entry and updates the cached balance of every wallet it touches.
+///
+/// Positive amounts increase an account's balance, negative amounts decrease it. The amounts are
+/// in `currency` and must sum to zero. Must be called inside a DB transaction.
+pub(super) async fn post_journal_entry(
+    conn: &mut AsyncPgConnection,
+    transaction_id: Option<i32>,
+    description: &str,
+    currency: &str,
+    postings: &[(Account, BigDecimal)],
+) -> Result<i32, diesel::result::Error> {
+    let postings: Vec<_> = postings
+        .iter()
+        .map(|(account, amount)| (*account, currency, amount.clone()))
+        .collect();
+    post_multi_currency_entry(conn, transaction_id, description, &postings).await
+}
+
+/// Like [`post_journal_entry`], but each posting has its own currency. The amounts must sum to
+/// zero in each currency.
+pub(super) async fn post_multi_currency_entry(
+    conn: &mut AsyncPgConnection,
+    transaction_id: Option<i32>,
+    description: &str,
+    postings: &[(Account, &str, BigDecimal)],
+) -> Result<i32, diesel::result::Error> {
+    let mut totals: HashMap<&str, BigDecimal> = HashMap::new();
+    for (_, currency, amount) in postings {
+        *totals.entry(currency).or_default() += amount;
+    }
+    if let Some((currency, total)) = totals.iter().find(|(_, total)| !total.is_zero()) {
+        return Err(diesel::result::Error::QueryBuilderError(
+            format!("journal entry `{description}` does not balance: {total} {currency}").into(),
+        ));
+    }
+
+    let entry_id: i32 = diesel::insert_into(journal_entry::table)
+        .values((
+            journal_entry::transaction_id.eq(transaction_id),
+            journal_entry::description.eq(description),
+        ))
+        .returning(journal_entry::id)
+        .get_result(conn)
+        .await?;
+
+    let now = Utc::now();
+    for (account, currency, amount) in postings {
+        let account_id = resolve_account(conn, *account, currency).await?;
+        diesel::insert_into(posting::table)
+            .values((
+                posting::journal_entry_id.eq(entry_id),
+                posting::account_id.eq(account_id),
+                posting::amount.eq(amount),
+            ))
+            .execute(conn)
+            .await?;
+
+        // keep the cached wallet balance in step with the ledger
+        if let Account::Wallet(wallet_id) = account {
+            diesel::update(wallet::table.find(wallet_id))
+                .set((
+                    wallet::balance.eq(wallet::balance + amount),
+                    wallet::updated_at.eq(now),
+                ))
+                .execute(conn)
+                .await?;
+        }
+    }
+
+    Ok(entry_id)
+}
+
+impl SmplDB {
+    /// Returns the wallet with every posting made against it, oldest first
+    pub async fn get_wallet_ledger(
+        &self,
+        user_id: i32,
+        wallet: &WalletRef,
+    ) -> Result<(Wallet, Vec<LedgerLine>), Error> {
+        let wallet = self.get_wallet(user_id, wallet).await?;
+        let lines = self.get_wallet_postings(wallet.id).await?;
+        Ok((wallet, lines))
+    }
+
+    /// Returns every posting made against any wallet, open or closed, oldest first
+    pub async fn get_wallet_postings(&self, wallet_id: i32) -> Result<Vec<LedgerLine>, Error> {
+        let mut conn = self.get_conn().await?;
+
+        let account_id: Option<i32> = ledger_account::table
+            .filter(ledger_account::wallet_id.eq(wallet_id))
+            .select(ledger_account::id)
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)?;
+        let Some(account_id) = account_id else {
+            return Ok(Vec::new());
+        };
+
+        posting::table
+            .inner_join(journal_entry::table.on(posting::journal_entry_id.eq(journal_entry::id)))
+            .filter(posting::account_id.eq(account_id))
+            .select((
+                journal_entry::id,
+                journal_entry::transaction_id,
+                journal_entry::description,
+                posting::amount,
+                journal_entry::created_at,
+            ))
+            .order(posting::id.asc())
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+}
@@ -1,12 +1,43 @@
+mod currency;
 mod error;
+mod fx;
+mod hold;
+mod idempotency;
+mod ledger;
 pub mod models;
+mod payment_request;
+mod refresh_token;
+mod refund;
+mod revocation;
+mod scheduled_payment;
 mod schema;
+mod screening;
+mod security;
+mod spending_limit;
+mod totp;
 mod transaction;
+mod transfer_review;
+mod user_token;
 mod users;
 mod wallet;
 use diesel::{result::DatabaseErrorKind, Connection, PgConnection};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 pub use error::Error;
+pub use hold::{HoldParty, HoldWithUsers};
+pub use idempotency::{IdempotentRequest, KeyReservation, StoredResponse};
+pub use payment_request::{PaymentRequestWithUsers, RequestDirection};
+pub use refresh_token::RefreshOutcome;
+pub use refund::Refunder;
+pub use scheduled_payment::{Schedule, ScheduledPaymentWithRecipient, ScheduledRun};
+pub use screening::{ScreeningHitWithUser, WatchlistMatch};
+pub use security::SecurityEventKind;
+pub use spending_limit::{Allowance, Limit, Limits};
+pub use transaction::{TransactionCursor, TransactionDirection, TransactionFilter};
+pub use transfer_review::{
+    AssessRisk, PaymentOutcome, RiskAssessment, RiskDecision, TransferReviewWithUsers, TransferRisk,
+};
+pub use user_token::TokenPurpose;
+pub use wallet::{AdjustmentDirection, WalletRef, WalletWithHeld};
 
 use anyhow::Context;
 use diesel_async::{
@@ -22,11 +53,19 @@ fn handle_duplicate_error(e: diesel::result::Error) -> Error {
         diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
             Error::Duplicate
         }
-        diesel::result::Error::RollbackTransaction => Error::RollbackTransaction,
         e => Error::DieselFailure(e),
     }
 }
 
+/// [`handle_duplicate_error`] for the result of a DB transaction, whose queries' errors were
+/// already converted with `?`
+fn handle_transaction_error(e: Error) -> Error {
+    match e {
+        Error::DieselFailure(e) => handle_duplicate_error(e),
+        e => e,
+    }
+}
+
 pub struct SmplDB {
     pool: Pool<AsyncPgConnection>,
 }
@@ -1,7 +1,16 @@
+use std::io::Write;
+
 use bigdecimal::BigDecimal;
 use chrono::{DateTime, Utc};
-use diesel::prelude::*;
-use serde::Serialize;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Queryable, Selectable, Serialize)]
 #[diesel(table_name = super::schema::users)]
@@ -12,21 +21,203 @@ pub struct User {
     pub email: String,
     #[serde(skip)]
     pub password: String,
-    pub status: bool,
+    pub status: AccountStatus,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub locked_until: Option<DateTime<Utc>>,
+    pub role: Role,
+    pub tier: Tier,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legal_name: Option<String>,
+}
+
+/// Status of a user or wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    /// Can't send or receive money until unfrozen
+    Frozen,
+    /// Can't be used again
+    Closed,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Closed => "closed",
+        }
+    }
+
+    /// Active and frozen accounts can be frozen, unfrozen and closed. Closed accounts stay closed.
+    pub fn can_become(self, next: AccountStatus) -> bool {
+        self != AccountStatus::Closed && self != next
+    }
+}
+
+impl ToSql<Text, Pg> for AccountStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for AccountStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "active" => Ok(AccountStatus::Active),
+            "frozen" => Ok(AccountStatus::Frozen),
+            "closed" => Ok(AccountStatus::Closed),
+            other => Err(format!("Unrecognized account status: {other}").into()),
+        }
+    }
+}
+
+/// Decides which [`Permission`](crate::permission::Permission)s a user has beyond their own
+/// account
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    /// Can look up users and wallets
+    Support,
+    /// Can do everything
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Support => "support",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for Role {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "user" => Ok(Role::User),
+            "support" => Ok(Role::Support),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unrecognized role: {other}").into()),
+        }
+    }
+}
+
+/// Decides a user's spending limits when they don't have their own
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    Basic,
+    #[default]
+    Standard,
+    Premium,
+}
+
+impl Tier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Basic => "basic",
+            Tier::Standard => "standard",
+            Tier::Premium => "premium",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for Tier {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for Tier {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "basic" => Ok(Tier::Basic),
+            "standard" => Ok(Tier::Standard),
+            "premium" => Ok(Tier::Premium),
+            other => Err(format!("Unrecognized tier: {other}").into()),
+        }
+    }
 }
 
 #[derive(Debug, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::currency)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Currency {
+    /// ISO 4217 code, e.g. `USD`
+    pub code: String,
+    pub name: String,
+    /// Digits after the decimal point amounts can have
+    pub minor_units: i16,
+}
+
+#[derive(Debug, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::fx_rate)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FxRate {
+    pub id: i32,
+    pub base_currency: String,
+    pub quote_currency: String,
+    /// Mid-market units of `quote_currency` per unit of `base_currency`
+    pub rate: BigDecimal,
+    /// Fraction taken off `rate` for customers
+    pub spread: BigDecimal,
+    pub effective_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::fx_quote)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FxQuote {
+    pub id: i32,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub from_amount: BigDecimal,
+    pub to_amount: BigDecimal,
+    /// Units of `to_currency` per unit of `from_currency`, after the spread
+    pub rate: BigDecimal,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
 #[diesel(table_name = super::schema::wallet)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Wallet {
     pub id: i32,
     pub user_id: i32,
+    pub name: String,
+    pub currency: String,
+    /// Transfers in `currency` are received into the user's default wallet
+    pub is_default: bool,
     pub balance: BigDecimal,
-    pub status: bool,
+    pub status: AccountStatus,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Queryable, Selectable, Serialize)]
@@ -34,8 +225,584 @@ pub struct Wallet {
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Transaction {
     pub id: i32,
-    pub from_wallet: i32,
-    pub to_wallet: i32,
+    pub from_wallet: Option<i32>,
+    pub to_wallet: Option<i32>,
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub created_at: DateTime<Utc>,
+    pub kind: TransactionKind,
+    /// Amount credited to `to_wallet` by a conversion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_amount: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_currency: Option<String>,
+    /// Units of `to_currency` given per unit of `currency` by a conversion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fx_rate: Option<BigDecimal>,
+    /// Admin who made an adjustment or reversal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_id: Option<i32>,
+    /// Why an adjustment, refund or reversal was made
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Transfer a refund or reversal sends money back for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_transaction_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    /// Between two wallets
+    Transfer,
+    /// Into a wallet from outside the platform
+    Deposit,
+    /// Out of a wallet to outside the platform
+    Withdrawal,
+    Fee,
+    /// Back to the sender of a transfer, by an admin
+    Reversal,
+    /// Back to the sender of a transfer, by its recipient
+    Refund,
+    /// Between two wallets in different currencies
+    Conversion,
+    /// Into or out of a wallet by an admin, to correct its balance
+    Adjustment,
+    /// Between two of a user's own wallets in the same currency
+    Move,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Transfer => "transfer",
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Fee => "fee",
+            TransactionKind::Reversal => "reversal",
+            TransactionKind::Conversion => "conversion",
+            TransactionKind::Adjustment => "adjustment",
+            TransactionKind::Refund => "refund",
+            TransactionKind::Move => "move",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for TransactionKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for TransactionKind {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "transfer" => Ok(TransactionKind::Transfer),
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            "fee" => Ok(TransactionKind::Fee),
+            "reversal" => Ok(TransactionKind::Reversal),
+            "conversion" => Ok(TransactionKind::Conversion),
+            "adjustment" => Ok(TransactionKind::Adjustment),
+            "refund" => Ok(TransactionKind::Refund),
+            "move" => Ok(TransactionKind::Move),
+            other => Err(format!("Unrecognized transaction kind: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::payment_request)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PaymentRequest {
+    pub id: i32,
+    pub requester_id: i32,
+    pub payer_id: i32,
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub note: Option<String>,
+    pub status: PaymentRequestStatus,
+    /// Transfer that paid an accepted request
+    pub transaction_id: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRequestStatus {
+    /// Waiting for the payer
+    Pending,
+    /// Accepted by the payer, with the payment held for review
+    Held,
+    /// Paid by the payer
+    Accepted,
+    /// Refused by the payer
+    Declined,
+    /// Withdrawn by the requester
+    Cancelled,
+    /// Not answered before `expires_at`
+    Expired,
+}
+
+impl PaymentRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentRequestStatus::Pending => "pending",
+            PaymentRequestStatus::Held => "held",
+            PaymentRequestStatus::Accepted => "accepted",
+            PaymentRequestStatus::Declined => "declined",
+            PaymentRequestStatus::Cancelled => "cancelled",
+            PaymentRequestStatus::Expired => "expired",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for PaymentRequestStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for PaymentRequestStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "pending" => Ok(PaymentRequestStatus::Pending),
+            "held" => Ok(PaymentRequestStatus::Held),
+            "accepted" => Ok(PaymentRequestStatus::Accepted),
+            "declined" => Ok(PaymentRequestStatus::Declined),
+            "cancelled" => Ok(PaymentRequestStatus::Cancelled),
+            "expired" => Ok(PaymentRequestStatus::Expired),
+            other => Err(format!("Unrecognized payment request status: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::hold)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Hold {
+    pub id: i32,
+    pub wallet_id: i32,
+    pub merchant_id: i32,
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub note: Option<String>,
+    pub status: HoldStatus,
+    /// Amount paid to the merchant, the rest was released
+    pub captured_amount: Option<BigDecimal>,
+    /// Transfer to the merchant of a captured hold
+    pub transaction_id: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldStatus {
+    /// Reserving funds in the wallet
+    Active,
+    /// Paid to the merchant
+    Captured,
+    /// Released by the merchant
+    Voided,
+    /// Not captured before `expires_at`
+    Expired,
+}
+
+impl HoldStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HoldStatus::Active => "active",
+            HoldStatus::Captured => "captured",
+            HoldStatus::Voided => "voided",
+            HoldStatus::Expired => "expired",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for HoldStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for HoldStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "active" => Ok(HoldStatus::Active),
+            "captured" => Ok(HoldStatus::Captured),
+            "voided" => Ok(HoldStatus::Voided),
+            "expired" => Ok(HoldStatus::Expired),
+            other => Err(format!("Unrecognized hold status: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::scheduled_payment)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ScheduledPayment {
+    pub id: i32,
+    pub user_id: i32,
+    pub wallet_id: i32,
+    pub recipient_id: i32,
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub frequency: ScheduleFrequency,
+    /// Five field cron pattern of a [`ScheduleFrequency::Cron`] schedule
+    pub cron: Option<String>,
+    pub status: ScheduleStatus,
+    pub starts_at: DateTime<Utc>,
+    /// No payments are made after this
+    pub ends_at: Option<DateTime<Utc>>,
+    pub next_run_at: DateTime<Utc>,
+    /// Failed attempts at the current payment
+    pub failed_attempts: i32,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_transaction_id: Option<i32>,
+    /// Why the last attempt failed
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleFrequency {
+    /// Paid once at `starts_at`
+    Once,
+    /// Paid every 7 days from `starts_at`
+    Weekly,
+    /// Paid on the day of the month of `starts_at`, or the last day of shorter months
+    Monthly,
+    /// Paid at the times matching a cron pattern
+    Cron,
+}
+
+impl ScheduleFrequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleFrequency::Once => "once",
+            ScheduleFrequency::Weekly => "weekly",
+            ScheduleFrequency::Monthly => "monthly",
+            ScheduleFrequency::Cron => "cron",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ScheduleFrequency {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ScheduleFrequency {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "once" => Ok(ScheduleFrequency::Once),
+            "weekly" => Ok(ScheduleFrequency::Weekly),
+            "monthly" => Ok(ScheduleFrequency::Monthly),
+            "cron" => Ok(ScheduleFrequency::Cron),
+            other => Err(format!("Unrecognized schedule frequency: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    /// Will be paid when due
+    Active,
+    /// Skipped by the scheduler until resumed
+    Paused,
+    /// Waiting for a review of its payment, and can't be changed until it is decided
+    Held,
+    /// Stopped by the user
+    Cancelled,
+    /// Made its last payment
+    Completed,
+    /// Stopped after a payment that can't succeed by retrying
+    Failed,
+}
+
+impl ScheduleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleStatus::Active => "active",
+            ScheduleStatus::Paused => "paused",
+            ScheduleStatus::Held => "held",
+            ScheduleStatus::Cancelled => "cancelled",
+            ScheduleStatus::Completed => "completed",
+            ScheduleStatus::Failed => "failed",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ScheduleStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ScheduleStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "active" => Ok(ScheduleStatus::Active),
+            "paused" => Ok(ScheduleStatus::Paused),
+            "held" => Ok(ScheduleStatus::Held),
+            "cancelled" => Ok(ScheduleStatus::Cancelled),
+            "completed" => Ok(ScheduleStatus::Completed),
+            "failed" => Ok(ScheduleStatus::Failed),
+            other => Err(format!("Unrecognized schedule status: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::transfer_review)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TransferReview {
+    pub id: i32,
+    pub user_id: i32,
+    pub wallet_id: i32,
+    pub recipient_id: i32,
+    pub amount: BigDecimal,
+    pub currency: String,
+    /// Total of the risk rules' scores
+    pub score: i32,
+    /// Risk rules that scored the transfer
+    pub rules: Vec<String>,
+    pub status: ReviewStatus,
+    /// Admin who approved or rejected the transfer
+    pub reviewer_id: Option<i32>,
+    pub note: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    /// Transfer made once approved
+    pub transaction_id: Option<i32>,
+    /// Quote of a conversion, made at its rate once approved
+    pub fx_quote_id: Option<i32>,
+    /// Request the transfer pays
+    pub payment_request_id: Option<i32>,
+    /// Hold the transfer captures
+    pub hold_id: Option<i32>,
+    /// Scheduled payment the transfer is a payment of
+    pub scheduled_payment_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    /// Waiting for an admin, with the amount reserved
+    Pending,
+    Approved,
+    Rejected,
+    /// Scored too high to be reviewed
+    Blocked,
+}
+
+impl ReviewStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "pending",
+            ReviewStatus::Approved => "approved",
+            ReviewStatus::Rejected => "rejected",
+            ReviewStatus::Blocked => "blocked",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ReviewStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ReviewStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "pending" => Ok(ReviewStatus::Pending),
+            "approved" => Ok(ReviewStatus::Approved),
+            "rejected" => Ok(ReviewStatus::Rejected),
+            "blocked" => Ok(ReviewStatus::Blocked),
+            other => Err(format!("Unrecognized review status: {other}").into()),
+        }
+    }
+}
+
+/// A user's name that matched a watchlist entry
+#[derive(Debug, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::screening_hit)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ScreeningHit {
+    pub id: i32,
+    /// Unset for sign ups that were blocked
+    pub user_id: Option<i32>,
+    pub context: ScreeningContext,
+    pub screened_name: String,
+    pub entry_id: String,
+    pub entry_name: String,
+    /// Similarity of the names, from 0 to 1
+    pub score: f64,
+    pub status: ScreeningStatus,
+    /// Admin who confirmed or dismissed the hit
+    pub reviewer_id: Option<i32>,
+    pub note: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What the user was doing when they were screened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreeningContext {
+    SignUp,
+    /// Sending or receiving a transfer
+    Transfer,
+}
+
+impl ScreeningContext {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScreeningContext::SignUp => "sign_up",
+            ScreeningContext::Transfer => "transfer",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ScreeningContext {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ScreeningContext {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "sign_up" => Ok(ScreeningContext::SignUp),
+            "transfer" => Ok(ScreeningContext::Transfer),
+            other => Err(format!("Unrecognized screening context: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreeningStatus {
+    /// Waiting for an admin to confirm or dismiss it
+    Pending,
+    /// The user is the listed party, so they can't send or receive transfers
+    Confirmed,
+    /// A false positive
+    Dismissed,
+}
+
+impl ScreeningStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScreeningStatus::Pending => "pending",
+            ScreeningStatus::Confirmed => "confirmed",
+            ScreeningStatus::Dismissed => "dismissed",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ScreeningStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ScreeningStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "pending" => Ok(ScreeningStatus::Pending),
+            "confirmed" => Ok(ScreeningStatus::Confirmed),
+            "dismissed" => Ok(ScreeningStatus::Dismissed),
+            other => Err(format!("Unrecognized screening status: {other}").into()),
+        }
+    }
+}
+
+/// Spending limits in one currency of a tier, or of a user. `None` is no limit.
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = super::schema::spending_limit)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SpendingLimit {
+    pub id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<Tier>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i32>,
+    pub currency: String,
+    pub per_transaction: Option<BigDecimal>,
+    pub daily_amount: Option<BigDecimal>,
+    pub weekly_amount: Option<BigDecimal>,
+    pub monthly_amount: Option<BigDecimal>,
+    pub daily_count: Option<i32>,
+    pub weekly_count: Option<i32>,
+    pub monthly_count: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = super::schema::idempotency_key)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+}
+
+/// A single posting against a wallet's ledger account
+#[derive(Debug, Queryable, Serialize)]
+pub struct LedgerLine {
+    pub journal_entry_id: i32,
+    pub transaction_id: Option<i32>,
+    pub description: String,
     pub amount: BigDecimal,
     pub created_at: Option<DateTime<Utc>>,
 }
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = super::schema::refresh_token)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub family_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = super::schema::user_totp)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserTotp {
+    /// Base32 encoded shared secret
+    pub secret: String,
+    /// Unset until the user has proven they can generate codes
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,347 @@
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Deserialize;
+
+use super::{
+    currency, handle_duplicate_error, handle_transaction_error,
+    idempotency::{complete_idempotent_request, IdempotentRequest},
+    models::{PaymentRequest, PaymentRequestStatus},
+    schema::{payment_request, users},
+    transaction::pay,
+    transfer_review::{AssessRisk, PaymentOutcome, TransferSource},
+    users::{lock_active_user, lock_recipient},
+    wallet::has_default_wallet,
+    Error, SmplDB, WalletRef,
+};
+
+/// Which of the user's payment requests to list
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestDirection {
+    /// Requests for the user to pay
+    Incoming,
+    /// Requests the user made
+    Outgoing,
+}
+
+/// A payment request with the usernames of the requester and the payer
+pub type PaymentRequestWithUsers = (PaymentRequest, String, String);
+
+/// Side of a payment request a user is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Party {
+    Requester,
+    Payer,
+}
+
+/// Marks the user's pending requests that are past their expiry as expired
+async fn expire_requests(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+) -> Result<(), diesel::result::Error> {
+    let now = Utc::now();
+    diesel::update(payment_request::table)
+        .filter(
+            payment_request::requester_id
+                .eq(user_id)
+                .or(payment_request::payer_id.eq(user_id)),
+        )
+        .filter(payment_request::status.eq(PaymentRequestStatus::Pending))
+        .filter(payment_request::expires_at.le(now))
+        .set((
+            payment_request::status.eq(PaymentRequestStatus::Expired),
+            payment_request::updated_at.eq(now),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Locks a pending, unexpired request the user is `party` to for update
+async fn lock_pending_request(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    request_id: i32,
+    party: Party,
+) -> Result<PaymentRequest, Error> {
+    let request: PaymentRequest = payment_request::table
+        .find(request_id)
+        .select(PaymentRequest::as_select())
+        .for_update()
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::PaymentRequestNotFound)?;
+    let party_id = match party {
+        Party::Requester => request.requester_id,
+        Party::Payer => request.payer_id,
+    };
+    if party_id != user_id {
+        return Err(Error::PaymentRequestNotFound);
+    }
+    match request.status {
+        PaymentRequestStatus::Pending if request.expires_at > Utc::now() => Ok(request),
+        PaymentRequestStatus::Pending | PaymentRequestStatus::Expired => {
+            Err(Error::PaymentRequestExpired)
+        }
+        _ => Err(Error::PaymentRequestNotPending),
+    }
+}
+
+/// Moves a locked request out of pending
+async fn resolve_request(
+    conn: &mut AsyncPgConnection,
+    request_id: i32,
+    status: PaymentRequestStatus,
+    transaction_id: Option<i32>,
+) -> Result<(), diesel::result::Error> {
+    diesel::update(payment_request::table.find(request_id))
+        .set((
+            payment_request::status.eq(status),
+            payment_request::transaction_id.eq(transaction_id),
+            payment_request::updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Finishes a request whose payment was held for review, accepting it with the transfer if it was
+/// approved or returning it to pending if it was rejected
+pub(super) async fn resolve_held_request(
+    conn: &mut AsyncPgConnection,
+    request_id: i32,
+    transaction_id: Option<i32>,
+) -> Result<(), diesel::result::Error> {
+    let status = match transaction_id {
+        Some(_) => PaymentRequestStatus::Accepted,
+        None => PaymentRequestStatus::Pending,
+    };
+    resolve_request(conn, request_id, status, transaction_id).await
+}
+
+impl SmplDB {
+    /// Asks `payer_username` to pay `amount` into the requester's default wallet in `currency`
+    pub async fn create_payment_request(
+        &self,
+        requester_id: i32,
+        payer_username: &str,
+        amount: BigDecimal,
+        currency: &str,
+        note: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<PaymentRequest, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                lock_active_user(conn, requester_id).await?;
+                let amount = currency::scale_amount(conn, currency, &amount).await?;
+
+                let payer_id = lock_recipient(conn, payer_username).await?;
+                if payer_id == requester_id {
+                    return Err(Error::SelfTransfer);
+                }
+
+                // the payment will go to the requester's default wallet
+                if !has_default_wallet(conn, requester_id, currency).await? {
+                    return Err(Error::WalletNotFound);
+                }
+
+                Ok(diesel::insert_into(payment_request::table)
+                    .values((
+                        payment_request::requester_id.eq(requester_id),
+                        payment_request::payer_id.eq(payer_id),
+                        payment_request::amount.eq(amount),
+                        payment_request::currency.eq(currency),
+                        payment_request::note.eq(note),
+                        payment_request::expires_at.eq(expires_at),
+                    ))
+                    .returning(PaymentRequest::as_returning())
+                    .get_result(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Returns the request if the user made it or was asked to pay it
+    pub async fn get_payment_request(
+        &self,
+        user_id: i32,
+        request_id: i32,
+    ) -> Result<PaymentRequestWithUsers, Error> {
+        let mut conn = self.get_conn().await?;
+        expire_requests(&mut conn, user_id).await?;
+
+        let (requester, payer) = diesel::alias!(users as requester, users as payer);
+        payment_request::table
+            .inner_join(requester.on(payment_request::requester_id.eq(requester.field(users::id))))
+            .inner_join(payer.on(payment_request::payer_id.eq(payer.field(users::id))))
+            .filter(payment_request::id.eq(request_id))
+            .filter(
+                payment_request::requester_id
+                    .eq(user_id)
+                    .or(payment_request::payer_id.eq(user_id)),
+            )
+            .select((
+                PaymentRequest::as_select(),
+                requester.field(users::username),
+                payer.field(users::username),
+            ))
+            .first(&mut conn)
+            .await
+            .optional()?
+            .ok_or(Error::PaymentRequestNotFound)
+    }
+
+    /// Returns the user's requests, newest first
+    pub async fn list_payment_requests(
+        &self,
+        user_id: i32,
+        direction: Option<RequestDirection>,
+        status: Option<PaymentRequestStatus>,
+    ) -> Result<Vec<PaymentRequestWithUsers>, Error> {
+        let mut conn = self.get_conn().await?;
+        expire_requests(&mut conn, user_id).await?;
+
+        let (requester, payer) = diesel::alias!(users as requester, users as payer);
+        let mut query = payment_request::table
+            .inner_join(requester.on(payment_request::requester_id.eq(requester.field(users::id))))
+            .inner_join(payer.on(payment_request::payer_id.eq(payer.field(users::id))))
+            .select((
+                PaymentRequest::as_select(),
+                requester.field(users::username),
+                payer.field(users::username),
+            ))
+            .into_boxed();
+        query = match direction {
+            Some(RequestDirection::Incoming) => query.filter(payment_request::payer_id.eq(user_id)),
+            Some(RequestDirection::Outgoing) => {
+                query.filter(payment_request::requester_id.eq(user_id))
+            }
+            None => query.filter(
+                payment_request::requester_id
+                    .eq(user_id)
+                    .or(payment_request::payer_id.eq(user_id)),
+            ),
+        };
+        if let Some(status) = status {
+            query = query.filter(payment_request::status.eq(status));
+        }
+
+        query
+            .order((
+                payment_request::created_at.desc(),
+                payment_request::id.desc(),
+            ))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Pays a pending request addressed to the user from `from_wallet`, which must be in the
+    /// request's currency. If `risk` holds the payment for review the request is held until it
+    /// is decided, and if it blocks the payment the request stays pending.
+    pub async fn accept_payment_request(
+        &self,
+        payer_id: i32,
+        request_id: i32,
+        from_wallet: &WalletRef,
+        risk: &dyn AssessRisk,
+        idempotency: Option<IdempotentRequest<'_, PaymentOutcome>>,
+    ) -> Result<PaymentOutcome, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let request =
+                    lock_pending_request(conn, payer_id, request_id, Party::Payer).await?;
+                let requester_username: String = users::table
+                    .find(request.requester_id)
+                    .select(users::username)
+                    .first(conn)
+                    .await?;
+
+                let outcome = pay(
+                    conn,
+                    payer_id,
+                    from_wallet,
+                    &requester_username,
+                    &request.amount,
+                    risk,
+                    TransferSource::PaymentRequest(request.id),
+                )
+                .await?;
+                match &outcome {
+                    PaymentOutcome::Paid(transaction) => {
+                        resolve_request(
+                            conn,
+                            request.id,
+                            PaymentRequestStatus::Accepted,
+                            Some(transaction.id),
+                        )
+                        .await?
+                    }
+                    PaymentOutcome::Held(_) => {
+                        resolve_request(conn, request.id, PaymentRequestStatus::Held, None).await?
+                    }
+                    PaymentOutcome::Blocked(_) => {}
+                }
+                complete_idempotent_request(conn, idempotency, &outcome).await?;
+                Ok(outcome)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Refuses a pending request addressed to the user
+    pub async fn decline_payment_request(
+        &self,
+        payer_id: i32,
+        request_id: i32,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let request =
+                    lock_pending_request(conn, payer_id, request_id, Party::Payer).await?;
+                Ok(resolve_request(conn, request.id, PaymentRequestStatus::Declined, None).await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Withdraws a pending request the user made
+    pub async fn cancel_payment_request(
+        &self,
+        requester_id: i32,
+        request_id: i32,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let request =
+                    lock_pending_request(conn, requester_id, request_id, Party::Requester).await?;
+                Ok(
+                    resolve_request(conn, request.id, PaymentRequestStatus::Cancelled, None)
+                        .await?,
+                )
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+}
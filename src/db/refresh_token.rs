@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error,
+    models::{RefreshToken, Role},
+    schema::{refresh_token, users},
+    Error, SmplDB,
+};
+
+/// Result of presenting a refresh token
+#[derive(Debug)]
+pub enum RefreshOutcome {
+    /// The token was valid and has been replaced
+    Rotated {
+        user_id: i32,
+        /// Current role of the user, for the new access token
+        role: Role,
+    },
+    /// No such token
+    Invalid,
+    Expired,
+    /// The token had already been rotated, so the whole family has been revoked
+    Reused,
+}
+
+impl SmplDB {
+    pub async fn create_refresh_token(
+        &self,
+        user_id: i32,
+        family_id: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::insert_into(refresh_token::table)
+            .values((
+                refresh_token::user_id.eq(user_id),
+                refresh_token::family_id.eq(family_id),
+                refresh_token::token_hash.eq(token_hash),
+                refresh_token::expires_at.eq(expires_at),
+            ))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+
+    /// Revokes the token with `token_hash` and stores `new_token_hash` in its family in its place.
+    /// Presenting an already revoked token revokes every token in its family.
+    pub async fn rotate_refresh_token(
+        &self,
+        token_hash: &str,
+        new_token_hash: &str,
+        new_expires_at: DateTime<Utc>,
+    ) -> Result<RefreshOutcome, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                // Lock the token row for update
+                let token: Option<RefreshToken> = refresh_token::table
+                    .filter(refresh_token::token_hash.eq(token_hash))
+                    .select(RefreshToken::as_select())
+                    .for_update() // Lock the row
+                    .first(conn)
+                    .await
+                    .optional()?;
+
+                let Some(RefreshToken {
+                    id,
+                    user_id,
+                    family_id,
+                    expires_at,
+                    revoked_at,
+                }) = token
+                else {
+                    return Ok(RefreshOutcome::Invalid);
+                };
+
+                let now = Utc::now();
+                if revoked_at.is_some() {
+                    diesel::update(refresh_token::table)
+                        .filter(refresh_token::family_id.eq(&family_id))
+                        .filter(refresh_token::revoked_at.is_null())
+                        .set(refresh_token::revoked_at.eq(now))
+                        .execute(conn)
+                        .await?;
+                    return Ok(RefreshOutcome::Reused);
+                }
+                if expires_at <= now {
+                    return Ok(RefreshOutcome::Expired);
+                }
+
+                diesel::update(refresh_token::table.find(id))
+                    .set(refresh_token::revoked_at.eq(now))
+                    .execute(conn)
+                    .await?;
+
+                diesel::insert_into(refresh_token::table)
+                    .values((
+                        refresh_token::user_id.eq(user_id),
+                        refresh_token::family_id.eq(&family_id),
+                        refresh_token::token_hash.eq(new_token_hash),
+                        refresh_token::expires_at.eq(new_expires_at),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                let role = users::table
+                    .find(user_id)
+                    .select(users::role)
+                    .first(conn)
+                    .await?;
+                Ok(RefreshOutcome::Rotated { user_id, role })
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    /// Revokes every token in the family of the user's refresh token
+    pub async fn revoke_refresh_token_family(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        let family_id: Option<String> = refresh_token::table
+            .filter(refresh_token::token_hash.eq(token_hash))
+            .filter(refresh_token::user_id.eq(user_id))
+            .select(refresh_token::family_id)
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)?;
+        let Some(family_id) = family_id else {
+            return Ok(());
+        };
+
+        diesel::update(refresh_token::table)
+            .filter(refresh_token::family_id.eq(family_id))
+            .filter(refresh_token::revoked_at.is_null())
+            .set(refresh_token::revoked_at.eq(Utc::now()))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{dsl, ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+use super::{
+    currency, handle_transaction_error,
+    hold::available_balance,
+    idempotency::{complete_idempotent_request, IdempotentRequest},
+    ledger::{self, Account},
+    models::{AccountStatus, Transaction, TransactionKind, Wallet},
+    schema::{transaction, users, wallet},
+    screening::has_confirmed_hit,
+    transaction::wallet_owner,
+    users::{check_recipient_status, lock_active_user},
+    Error, SmplDB,
+};
+
+/// Who is sending money back on a transfer
+#[derive(Debug, Clone, Copy)]
+pub enum Refunder {
+    /// The recipient, who needs an active account and wallets on both sides
+    Recipient(i32),
+    /// An admin, who can reverse transfers between frozen accounts
+    Admin(i32),
+}
+
+impl SmplDB {
+    /// Sends `amount`, or everything not yet sent back, of a transfer from the wallet that
+    /// received it to the wallet that paid it. Refunds and reversals of a transfer can't add up
+    /// to more than its amount.
+    pub async fn refund_transaction(
+        &self,
+        refunder: Refunder,
+        transaction_id: i32,
+        amount: Option<BigDecimal>,
+        reason: Option<&str>,
+        idempotency: Option<IdempotentRequest<'_, Transaction>>,
+    ) -> Result<Transaction, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                // Lock the original so concurrent refunds of it are counted
+                let original: Transaction = transaction::table
+                    .find(transaction_id)
+                    .select(Transaction::as_select())
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::TransactionNotFound)?;
+                let (Some(payer_wallet_id), Some(payee_wallet_id)) =
+                    (original.from_wallet, original.to_wallet)
+                else {
+                    return Err(Error::NotRefundable);
+                };
+                let (payer_id, _) = wallet_owner(conn, payer_wallet_id).await?;
+                let (payee_id, _) = wallet_owner(conn, payee_wallet_id).await?;
+
+                if let Refunder::Recipient(user_id) = refunder {
+                    if payee_id != user_id {
+                        return Err(if payer_id == user_id {
+                            Error::NotRefundable
+                        } else {
+                            Error::TransactionNotFound
+                        });
+                    }
+                }
+                if original.kind != TransactionKind::Transfer {
+                    return Err(Error::NotRefundable);
+                }
+
+                let refunded: Option<BigDecimal> = transaction::table
+                    .filter(transaction::original_transaction_id.eq(original.id))
+                    .select(dsl::sum(transaction::amount))
+                    .first(conn)
+                    .await?;
+                let remaining = &original.amount - refunded.unwrap_or_default();
+                let amount = match amount {
+                    Some(amount) => {
+                        currency::scale_amount(conn, &original.currency, &amount).await?
+                    }
+                    None => remaining.clone(),
+                };
+                if amount.is_zero() || amount > remaining {
+                    return Err(Error::RefundExceedsPayment);
+                }
+
+                if let Refunder::Recipient(user_id) = refunder {
+                    lock_active_user(conn, user_id).await?;
+                    // hold a share lock so the payer can't be frozen mid refund
+                    let payer_status: AccountStatus = users::table
+                        .find(payer_id)
+                        .select(users::status)
+                        .for_share()
+                        .first(conn)
+                        .await?;
+                    check_recipient_status(payer_status)?;
+                }
+
+                // lock in id order so refunds between the same wallets can't deadlock
+                let mut ids = [payee_wallet_id, payer_wallet_id];
+                ids.sort_unstable();
+                let mut locked = Vec::with_capacity(2);
+                for id in ids {
+                    let found: Wallet = wallet::table
+                        .find(id)
+                        .select(Wallet::as_select())
+                        .for_update()
+                        .first(conn)
+                        .await?;
+                    locked.push(found);
+                }
+                let (from_wallet, to_wallet) = if locked[0].id == payee_wallet_id {
+                    (&locked[0], &locked[1])
+                } else {
+                    (&locked[1], &locked[0])
+                };
+                // not even admins can move money to or from a listed party
+                if has_confirmed_hit(conn, &[payee_id, payer_id]).await? {
+                    return Err(Error::ScreeningMatch);
+                }
+
+                if from_wallet.status == AccountStatus::Closed {
+                    return Err(Error::WalletNotFound);
+                }
+                if to_wallet.status == AccountStatus::Closed {
+                    return Err(Error::RecipientWalletNotFound);
+                }
+                if let Refunder::Recipient(_) = refunder {
+                    if from_wallet.status != AccountStatus::Active {
+                        return Err(Error::WalletFrozen);
+                    }
+                    if to_wallet.status != AccountStatus::Active {
+                        return Err(Error::RecipientWalletFrozen);
+                    }
+                }
+                // admins can reverse funds that are held
+                let balance = match refunder {
+                    Refunder::Recipient(_) => available_balance(conn, from_wallet).await?,
+                    Refunder::Admin(_) => from_wallet.balance.clone(),
+                };
+                if balance < amount {
+                    return Err(Error::InsufficientFunds);
+                }
+
+                let (kind, admin_id, description) = match refunder {
+                    Refunder::Recipient(_) => (TransactionKind::Refund, None, "Refund"),
+                    Refunder::Admin(admin_id) => {
+                        (TransactionKind::Reversal, Some(admin_id), "Reversal")
+                    }
+                };
+                let transaction: Transaction = diesel::insert_into(transaction::table)
+                    .values((
+                        transaction::from_wallet.eq(from_wallet.id),
+                        transaction::to_wallet.eq(to_wallet.id),
+                        transaction::amount.eq(&amount),
+                        transaction::currency.eq(&original.currency),
+                        transaction::kind.eq(kind),
+                        transaction::admin_id.eq(admin_id),
+                        transaction::reason.eq(reason),
+                        transaction::original_transaction_id.eq(original.id),
+                    ))
+                    .returning(Transaction::as_returning())
+                    .get_result(conn)
+                    .await?;
+
+                ledger::post_journal_entry(
+                    conn,
+                    Some(transaction.id),
+                    description,
+                    &original.currency,
+                    &[
+                        (Account::Wallet(from_wallet.id), -amount.clone()),
+                        (Account::Wallet(to_wallet.id), amount),
+                    ],
+                )
+                .await?;
+
+                complete_idempotent_request(conn, idempotency, &transaction).await?;
+                Ok(transaction)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use diesel::{dsl::exists, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error,
+    schema::{refresh_token, revoked_token, users},
+    Error, SmplDB,
+};
+
+impl SmplDB {
+    /// Revokes a single access token until it expires
+    pub async fn revoke_token(
+        &self,
+        user_id: i32,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+
+        // tokens past their expiry are rejected anyway
+        diesel::delete(revoked_token::table)
+            .filter(revoked_token::expires_at.lt(Utc::now()))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+
+        diesel::insert_into(revoked_token::table)
+            .values((
+                revoked_token::jti.eq(jti),
+                revoked_token::user_id.eq(user_id),
+                revoked_token::expires_at.eq(expires_at),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+
+    /// Rejects every access token issued to the user so far and revokes all their refresh tokens.
+    /// Returns the time from which tokens are accepted again.
+    pub async fn revoke_all_tokens(&self, user_id: i32) -> Result<DateTime<Utc>, Error> {
+        let now = Utc::now();
+
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                diesel::update(users::table.find(user_id))
+                    .set(users::tokens_valid_after.eq(now))
+                    .execute(conn)
+                    .await?;
+
+                diesel::update(refresh_token::table)
+                    .filter(refresh_token::user_id.eq(user_id))
+                    .filter(refresh_token::revoked_at.is_null())
+                    .set(refresh_token::revoked_at.eq(now))
+                    .execute(conn)
+                    .await?;
+
+                Ok(now)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    pub async fn is_token_revoked(&self, jti: &str) -> Result<bool, Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::select(exists(
+            revoked_token::table.filter(revoked_token::jti.eq(jti)),
+        ))
+        .get_result(&mut conn)
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    /// Returns the time before which the user's access tokens are rejected, if any
+    pub async fn get_tokens_valid_after(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let mut conn = self.get_conn().await?;
+        users::table
+            .find(user_id)
+            .select(users::tokens_valid_after)
+            .first(&mut conn)
+            .await
+            .optional()
+            .map(Option::flatten)
+            .map_err(handle_duplicate_error)
+    }
+}
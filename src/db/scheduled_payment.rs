@@ -0,0 +1,454 @@
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, Months, Utc};
+use croner::Cron;
+use diesel::{ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+
+use super::{
+    currency, handle_duplicate_error, handle_transaction_error,
+    idempotency::{complete_idempotent_request, IdempotentRequest},
+    models::{ScheduleFrequency, ScheduleStatus, ScheduledPayment},
+    schema::{scheduled_payment, users},
+    screening::has_confirmed_hit,
+    transaction::pay,
+    transfer_review::{AssessRisk, PaymentOutcome, TransferSource},
+    users::{lock_active_user, lock_recipient},
+    wallet::{has_default_wallet, lock_wallet},
+    Error, SmplDB, WalletRef,
+};
+
+/// A scheduled payment with the recipient's username
+pub type ScheduledPaymentWithRecipient = (ScheduledPayment, String);
+
+/// When and how often a scheduled payment is made
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub frequency: ScheduleFrequency,
+    /// Five field cron pattern, required for [`ScheduleFrequency::Cron`]
+    pub cron: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    fn of(schedule: &ScheduledPayment) -> Self {
+        Self {
+            frequency: schedule.frequency,
+            cron: schedule.cron.clone(),
+            starts_at: schedule.starts_at,
+            ends_at: schedule.ends_at,
+        }
+    }
+
+    fn parse_cron(&self) -> Result<Option<Cron>, Error> {
+        match (self.frequency, &self.cron) {
+            (ScheduleFrequency::Cron, Some(pattern)) => Cron::new(pattern)
+                .parse()
+                .map(Some)
+                .map_err(|_| Error::InvalidCronPattern),
+            (ScheduleFrequency::Cron, None) => Err(Error::InvalidCronPattern),
+            (_, Some(_)) => Err(Error::InvalidCronPattern),
+            (_, None) => Ok(None),
+        }
+    }
+
+    /// The first payment after `after`, or the first payment at all when `after` is `None`.
+    /// `None` once the schedule has no more payments.
+    fn next_run(&self, after: Option<DateTime<Utc>>) -> Result<Option<DateTime<Utc>>, Error> {
+        let cron = self.parse_cron()?;
+        let after = after.filter(|&after| after >= self.starts_at);
+        let next = match (self.frequency, after) {
+            (ScheduleFrequency::Cron, None) => {
+                cron.and_then(|cron| cron.find_next_occurrence(&self.starts_at, true).ok())
+            }
+            (ScheduleFrequency::Cron, Some(after)) => {
+                cron.and_then(|cron| cron.find_next_occurrence(&after, false).ok())
+            }
+            (_, None) => Some(self.starts_at),
+            (ScheduleFrequency::Once, Some(_)) => None,
+            (ScheduleFrequency::Weekly, Some(after)) => {
+                let weeks = (after - self.starts_at).num_weeks() + 1;
+                Some(self.starts_at + Duration::weeks(weeks))
+            }
+            // counted from the start so a payment on the 31st isn't moved to the 28th for good
+            (ScheduleFrequency::Monthly, Some(after)) => (1..)
+                .map_while(|months| self.starts_at.checked_add_months(Months::new(months)))
+                .find(|&next| next > after),
+        };
+        Ok(next.filter(|&next| self.ends_at.is_none_or(|ends_at| next <= ends_at)))
+    }
+}
+
+/// What happened when the scheduler ran a due payment
+#[derive(Debug)]
+pub enum ScheduledRun {
+    /// Paid, with the id of the transfer
+    Paid(i32),
+    /// Held for review, with the id of the review. The schedule waits for the decision.
+    Held(i32),
+    /// Failed, and will be tried again at `next_run_at`
+    WillRetry(Error),
+    /// Failed, and won't be tried again. Recurring schedules that ran out of retries move on to
+    /// their next payment, otherwise the schedule has failed.
+    GaveUp(Error),
+}
+
+/// Failures that can succeed later without the user changing the schedule
+fn is_retryable(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::InsufficientFunds
+            | Error::TransferBlocked
+            | Error::AccountFrozen
+            | Error::WalletFrozen
+            | Error::RecipientFrozen
+            | Error::RecipientWalletFrozen
+            | Error::LimitExceeded(_)
+    )
+}
+
+/// Moves a scheduled payment whose payment was held for review on to its next payment, with the
+/// transfer if it was approved. A one-off payment that was rejected has failed. Payments that
+/// were due while it was held are skipped.
+pub(super) async fn resolve_held_schedule(
+    conn: &mut AsyncPgConnection,
+    schedule_id: i32,
+    transaction_id: Option<i32>,
+) -> Result<(), Error> {
+    let schedule: ScheduledPayment = scheduled_payment::table
+        .find(schedule_id)
+        .filter(scheduled_payment::status.eq(ScheduleStatus::Held))
+        .select(ScheduledPayment::as_select())
+        .for_update()
+        .first(conn)
+        .await?;
+
+    let now = Utc::now();
+    let rejected = transaction_id.is_none();
+    let (status, next_run_at) = match Schedule::of(&schedule).next_run(Some(now))? {
+        _ if rejected && schedule.frequency == ScheduleFrequency::Once => {
+            (ScheduleStatus::Failed, schedule.next_run_at)
+        }
+        Some(next) => (ScheduleStatus::Active, next),
+        None => (ScheduleStatus::Completed, schedule.next_run_at),
+    };
+    diesel::update(scheduled_payment::table.find(schedule.id))
+        .set((
+            scheduled_payment::status.eq(status),
+            scheduled_payment::next_run_at.eq(next_run_at),
+            scheduled_payment::failed_attempts.eq(0),
+            scheduled_payment::last_transaction_id
+                .eq(transaction_id.or(schedule.last_transaction_id)),
+            scheduled_payment::last_error.eq(rejected.then_some("Payment was rejected in review")),
+            scheduled_payment::updated_at.eq(now),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+impl SmplDB {
+    /// Schedules paying `amount` from the user's wallet to `to_username` on `schedule`
+    pub async fn create_scheduled_payment(
+        &self,
+        user_id: i32,
+        from_wallet: &WalletRef,
+        to_username: &str,
+        amount: BigDecimal,
+        schedule: &Schedule,
+        idempotency: Option<IdempotentRequest<'_, ScheduledPayment>>,
+    ) -> Result<ScheduledPayment, Error> {
+        let next_run_at = schedule.next_run(None)?.ok_or(Error::NoScheduledRuns)?;
+
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                lock_active_user(conn, user_id).await?;
+                let from_wallet = lock_wallet(conn, user_id, from_wallet).await?;
+                let amount = currency::scale_amount(conn, &from_wallet.currency, &amount).await?;
+
+                let recipient_id = lock_recipient(conn, to_username).await?;
+                if recipient_id == user_id {
+                    return Err(Error::SelfTransfer);
+                }
+                if !has_default_wallet(conn, recipient_id, &from_wallet.currency).await? {
+                    return Err(Error::RecipientWalletNotFound);
+                }
+                if has_confirmed_hit(conn, &[user_id, recipient_id]).await? {
+                    return Err(Error::ScreeningMatch);
+                }
+
+                let created = diesel::insert_into(scheduled_payment::table)
+                    .values((
+                        scheduled_payment::user_id.eq(user_id),
+                        scheduled_payment::wallet_id.eq(from_wallet.id),
+                        scheduled_payment::recipient_id.eq(recipient_id),
+                        scheduled_payment::amount.eq(amount),
+                        scheduled_payment::currency.eq(&from_wallet.currency),
+                        scheduled_payment::frequency.eq(schedule.frequency),
+                        scheduled_payment::cron.eq(&schedule.cron),
+                        scheduled_payment::starts_at.eq(schedule.starts_at),
+                        scheduled_payment::ends_at.eq(schedule.ends_at),
+                        scheduled_payment::next_run_at.eq(next_run_at),
+                    ))
+                    .returning(ScheduledPayment::as_returning())
+                    .get_result(conn)
+                    .await?;
+                complete_idempotent_request(conn, idempotency, &created).await?;
+                Ok(created)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    pub async fn get_scheduled_payment(
+        &self,
+        user_id: i32,
+        schedule_id: i32,
+    ) -> Result<ScheduledPaymentWithRecipient, Error> {
+        let mut conn = self.get_conn().await?;
+        scheduled_payment::table
+            .inner_join(users::table.on(scheduled_payment::recipient_id.eq(users::id)))
+            .filter(scheduled_payment::id.eq(schedule_id))
+            .filter(scheduled_payment::user_id.eq(user_id))
+            .select((ScheduledPayment::as_select(), users::username))
+            .first(&mut conn)
+            .await
+            .optional()?
+            .ok_or(Error::ScheduledPaymentNotFound)
+    }
+
+    /// Returns the user's scheduled payments, newest first
+    pub async fn list_scheduled_payments(
+        &self,
+        user_id: i32,
+        status: Option<ScheduleStatus>,
+    ) -> Result<Vec<ScheduledPaymentWithRecipient>, Error> {
+        let mut conn = self.get_conn().await?;
+        let mut query = scheduled_payment::table
+            .inner_join(users::table.on(scheduled_payment::recipient_id.eq(users::id)))
+            .filter(scheduled_payment::user_id.eq(user_id))
+            .select((ScheduledPayment::as_select(), users::username))
+            .into_boxed();
+        if let Some(status) = status {
+            query = query.filter(scheduled_payment::status.eq(status));
+        }
+        query
+            .order((
+                scheduled_payment::created_at.desc(),
+                scheduled_payment::id.desc(),
+            ))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Pauses, resumes or cancels one of the user's scheduled payments. Resuming a recurring
+    /// schedule skips the payments that were due while it was paused. A held schedule can't be
+    /// changed until its payment is reviewed.
+    pub async fn set_scheduled_payment_status(
+        &self,
+        user_id: i32,
+        schedule_id: i32,
+        status: ScheduleStatus,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let schedule: ScheduledPayment = scheduled_payment::table
+                    .filter(scheduled_payment::id.eq(schedule_id))
+                    .filter(scheduled_payment::user_id.eq(user_id))
+                    .select(ScheduledPayment::as_select())
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::ScheduledPaymentNotFound)?;
+
+                let now = Utc::now();
+                let mut status = status;
+                let mut next_run_at = schedule.next_run_at;
+                match (schedule.status, status) {
+                    (ScheduleStatus::Active, ScheduleStatus::Paused)
+                    | (
+                        ScheduleStatus::Active | ScheduleStatus::Paused,
+                        ScheduleStatus::Cancelled,
+                    ) => {}
+                    (ScheduleStatus::Paused, ScheduleStatus::Active) => {
+                        if next_run_at < now && schedule.frequency != ScheduleFrequency::Once {
+                            match Schedule::of(&schedule).next_run(Some(now))? {
+                                Some(next) => next_run_at = next,
+                                None => status = ScheduleStatus::Completed,
+                            }
+                        }
+                    }
+                    _ => return Err(Error::InvalidStatusTransition),
+                }
+
+                diesel::update(scheduled_payment::table.find(schedule.id))
+                    .set((
+                        scheduled_payment::status.eq(status),
+                        scheduled_payment::next_run_at.eq(next_run_at),
+                        scheduled_payment::updated_at.eq(now),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Ids of active scheduled payments that are due, oldest first
+    pub async fn due_scheduled_payments(&self, limit: i64) -> Result<Vec<i32>, Error> {
+        let mut conn = self.get_conn().await?;
+        Ok(scheduled_payment::table
+            .filter(scheduled_payment::status.eq(ScheduleStatus::Active))
+            .filter(scheduled_payment::next_run_at.le(Utc::now()))
+            .order(scheduled_payment::next_run_at)
+            .limit(limit)
+            .select(scheduled_payment::id)
+            .load(&mut conn)
+            .await?)
+    }
+
+    /// Makes a due payment, and moves the schedule on to its next payment or a retry. Returns
+    /// `None` if it isn't due, or another instance is running it. If `risk` holds the payment
+    /// for review the schedule is held until it is decided, and if it blocks the payment it is
+    /// retried like a failed one.
+    ///
+    /// The payment and the schedule update are made in one DB transaction so a payment can't be
+    /// made twice.
+    pub async fn run_scheduled_payment(
+        &self,
+        schedule_id: i32,
+        max_attempts: i32,
+        retry_delay: Duration,
+        risk: &dyn AssessRisk,
+    ) -> Result<Option<(ScheduledPayment, ScheduledRun)>, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let now = Utc::now();
+                let Some(schedule): Option<ScheduledPayment> = scheduled_payment::table
+                    .find(schedule_id)
+                    .filter(scheduled_payment::status.eq(ScheduleStatus::Active))
+                    .filter(scheduled_payment::next_run_at.le(now))
+                    .select(ScheduledPayment::as_select())
+                    .for_update()
+                    .skip_locked()
+                    .first(conn)
+                    .await
+                    .optional()?
+                else {
+                    return Ok(None);
+                };
+                let to_username: String = users::table
+                    .find(schedule.recipient_id)
+                    .select(users::username)
+                    .first(conn)
+                    .await?;
+
+                // in a savepoint so a failed payment leaves the schedule to be updated
+                let user_id = schedule.user_id;
+                let from_wallet = WalletRef::Id {
+                    id: schedule.wallet_id,
+                    currency: Some(schedule.currency.clone()),
+                };
+                let amount = &schedule.amount;
+                let paid = conn
+                    .transaction(|conn| {
+                        async move {
+                            let source = TransferSource::ScheduledPayment(schedule_id);
+                            pay(
+                                conn,
+                                user_id,
+                                &from_wallet,
+                                &to_username,
+                                amount,
+                                risk,
+                                source,
+                            )
+                            .await
+                        }
+                        .scope_boxed()
+                    })
+                    .await;
+                // paid, or held with the id of the review. A blocked payment keeps its review
+                // and is retried like a failed one.
+                let paid = match paid {
+                    Ok(PaymentOutcome::Paid(transaction)) => Ok(Ok(transaction)),
+                    Ok(PaymentOutcome::Held((review, ..))) => Ok(Err(review.id)),
+                    Ok(PaymentOutcome::Blocked(_)) => Err(Error::TransferBlocked),
+                    Err(e) => Err(e),
+                };
+
+                let recurring = schedule.frequency != ScheduleFrequency::Once;
+                let next_run = || Schedule::of(&schedule).next_run(Some(now));
+                let mut status = ScheduleStatus::Active;
+                let mut next_run_at = schedule.next_run_at;
+                let mut failed_attempts = 0;
+                let (transaction_id, run) = match paid {
+                    Ok(Ok(transaction)) => {
+                        match next_run()? {
+                            Some(next) => next_run_at = next,
+                            None => status = ScheduleStatus::Completed,
+                        }
+                        (Some(transaction.id), ScheduledRun::Paid(transaction.id))
+                    }
+                    Ok(Err(review_id)) => {
+                        status = ScheduleStatus::Held;
+                        (schedule.last_transaction_id, ScheduledRun::Held(review_id))
+                    }
+                    Err(e @ (Error::DieselFailure(_) | Error::ConnectionAcqusitionFailure(_))) => {
+                        return Err(e)
+                    }
+                    Err(e) if is_retryable(&e) && schedule.failed_attempts + 1 < max_attempts => {
+                        failed_attempts = schedule.failed_attempts + 1;
+                        next_run_at = now + retry_delay;
+                        (schedule.last_transaction_id, ScheduledRun::WillRetry(e))
+                    }
+                    Err(e) if is_retryable(&e) && recurring => {
+                        match next_run()? {
+                            Some(next) => next_run_at = next,
+                            None => status = ScheduleStatus::Completed,
+                        }
+                        (schedule.last_transaction_id, ScheduledRun::GaveUp(e))
+                    }
+                    Err(e) => {
+                        status = ScheduleStatus::Failed;
+                        (schedule.last_transaction_id, ScheduledRun::GaveUp(e))
+                    }
+                };
+                let last_error = match &run {
+                    ScheduledRun::Paid(_) | ScheduledRun::Held(_) => None,
+                    ScheduledRun::WillRetry(e) | ScheduledRun::GaveUp(e) => Some(e.to_string()),
+                };
+
+                let schedule = diesel::update(scheduled_payment::table.find(schedule.id))
+                    .set((
+                        scheduled_payment::status.eq(status),
+                        scheduled_payment::next_run_at.eq(next_run_at),
+                        scheduled_payment::failed_attempts.eq(failed_attempts),
+                        scheduled_payment::last_run_at.eq(now),
+                        scheduled_payment::last_transaction_id.eq(transaction_id),
+                        scheduled_payment::last_error.eq(last_error),
+                        scheduled_payment::updated_at.eq(now),
+                    ))
+                    .returning(ScheduledPayment::as_returning())
+                    .get_result(conn)
+                    .await?;
+                Ok(Some((schedule, run)))
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+}
@@ -1,11 +1,333 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    currency (code) {
+        #[max_length = 3]
+        code -> Varchar,
+        #[max_length = 60]
+        name -> Varchar,
+        minor_units -> Int2,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    fx_quote (id) {
+        id -> Int4,
+        user_id -> Int4,
+        fx_rate_id -> Int4,
+        #[max_length = 3]
+        from_currency -> Varchar,
+        #[max_length = 3]
+        to_currency -> Varchar,
+        from_amount -> Numeric,
+        to_amount -> Numeric,
+        rate -> Numeric,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    fx_rate (id) {
+        id -> Int4,
+        #[max_length = 3]
+        base_currency -> Varchar,
+        #[max_length = 3]
+        quote_currency -> Varchar,
+        rate -> Numeric,
+        spread -> Numeric,
+        effective_at -> Timestamptz,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    hold (id) {
+        id -> Int4,
+        wallet_id -> Int4,
+        merchant_id -> Int4,
+        amount -> Numeric,
+        #[max_length = 3]
+        currency -> Varchar,
+        #[max_length = 255]
+        note -> Nullable<Varchar>,
+        #[max_length = 10]
+        status -> Varchar,
+        captured_amount -> Nullable<Numeric>,
+        transaction_id -> Nullable<Int4>,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    idempotency_key (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 255]
+        key -> Varchar,
+        #[max_length = 64]
+        request_hash -> Varchar,
+        response_status -> Nullable<Int4>,
+        response_body -> Nullable<Text>,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    journal_entry (id) {
+        id -> Int4,
+        transaction_id -> Nullable<Int4>,
+        #[max_length = 255]
+        description -> Varchar,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    ledger_account (id) {
+        id -> Int4,
+        wallet_id -> Nullable<Int4>,
+        #[max_length = 60]
+        code -> Nullable<Varchar>,
+        created_at -> Nullable<Timestamptz>,
+        #[max_length = 3]
+        currency -> Varchar,
+    }
+}
+
+diesel::table! {
+    payment_request (id) {
+        id -> Int4,
+        requester_id -> Int4,
+        payer_id -> Int4,
+        amount -> Numeric,
+        #[max_length = 3]
+        currency -> Varchar,
+        #[max_length = 255]
+        note -> Nullable<Varchar>,
+        #[max_length = 10]
+        status -> Varchar,
+        transaction_id -> Nullable<Int4>,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    posting (id) {
+        id -> Int4,
+        journal_entry_id -> Int4,
+        account_id -> Int4,
+        amount -> Numeric,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    recovery_code (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    refresh_token (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        family_id -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    revoked_token (jti) {
+        #[max_length = 64]
+        jti -> Varchar,
+        user_id -> Int4,
+        expires_at -> Timestamptz,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    scheduled_payment (id) {
+        id -> Int4,
+        user_id -> Int4,
+        wallet_id -> Int4,
+        recipient_id -> Int4,
+        amount -> Numeric,
+        #[max_length = 3]
+        currency -> Varchar,
+        #[max_length = 10]
+        frequency -> Varchar,
+        #[max_length = 100]
+        cron -> Nullable<Varchar>,
+        #[max_length = 10]
+        status -> Varchar,
+        starts_at -> Timestamptz,
+        ends_at -> Nullable<Timestamptz>,
+        next_run_at -> Timestamptz,
+        failed_attempts -> Int4,
+        last_run_at -> Nullable<Timestamptz>,
+        last_transaction_id -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    screening_hit (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 10]
+        context -> Varchar,
+        #[max_length = 255]
+        screened_name -> Varchar,
+        #[max_length = 100]
+        entry_id -> Varchar,
+        #[max_length = 255]
+        entry_name -> Varchar,
+        score -> Float8,
+        #[max_length = 10]
+        status -> Varchar,
+        reviewer_id -> Nullable<Int4>,
+        note -> Nullable<Text>,
+        reviewed_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    security_event (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 40]
+        kind -> Varchar,
+        #[max_length = 45]
+        ip_address -> Nullable<Varchar>,
+        detail -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sign_in_attempt (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 120]
+        email -> Varchar,
+        #[max_length = 45]
+        ip_address -> Varchar,
+        succeeded -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    spending_limit (id) {
+        id -> Int4,
+        #[max_length = 10]
+        tier -> Nullable<Varchar>,
+        user_id -> Nullable<Int4>,
+        #[max_length = 3]
+        currency -> Varchar,
+        per_transaction -> Nullable<Numeric>,
+        daily_amount -> Nullable<Numeric>,
+        weekly_amount -> Nullable<Numeric>,
+        monthly_amount -> Nullable<Numeric>,
+        daily_count -> Nullable<Int4>,
+        weekly_count -> Nullable<Int4>,
+        monthly_count -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     transaction (id) {
         id -> Int4,
-        from_wallet -> Int4,
-        to_wallet -> Int4,
+        from_wallet -> Nullable<Int4>,
+        to_wallet -> Nullable<Int4>,
         amount -> Numeric,
+        created_at -> Timestamptz,
+        #[max_length = 20]
+        kind -> Varchar,
+        #[max_length = 3]
+        currency -> Varchar,
+        to_amount -> Nullable<Numeric>,
+        #[max_length = 3]
+        to_currency -> Nullable<Varchar>,
+        fx_rate -> Nullable<Numeric>,
+        fx_quote_id -> Nullable<Int4>,
+        admin_id -> Nullable<Int4>,
+        reason -> Nullable<Text>,
+        original_transaction_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    transfer_review (id) {
+        id -> Int4,
+        user_id -> Int4,
+        wallet_id -> Int4,
+        recipient_id -> Int4,
+        amount -> Numeric,
+        #[max_length = 3]
+        currency -> Varchar,
+        score -> Int4,
+        rules -> Array<Text>,
+        #[max_length = 10]
+        status -> Varchar,
+        reviewer_id -> Nullable<Int4>,
+        note -> Nullable<Text>,
+        reviewed_at -> Nullable<Timestamptz>,
+        transaction_id -> Nullable<Int4>,
+        fx_quote_id -> Nullable<Int4>,
+        payment_request_id -> Nullable<Int4>,
+        hold_id -> Nullable<Int4>,
+        scheduled_payment_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_token (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 30]
+        purpose -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    user_totp (user_id) {
+        user_id -> Int4,
+        #[max_length = 64]
+        secret -> Varchar,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_used_step -> Nullable<Int8>,
         created_at -> Nullable<Timestamptz>,
     }
 }
@@ -19,9 +341,19 @@ diesel::table! {
         email -> Varchar,
         #[max_length = 100]
         password -> Varchar,
-        status -> Bool,
+        #[max_length = 10]
+        status -> Varchar,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        tokens_valid_after -> Nullable<Timestamptz>,
+        email_verified_at -> Nullable<Timestamptz>,
+        locked_until -> Nullable<Timestamptz>,
+        #[max_length = 10]
+        role -> Varchar,
+        #[max_length = 10]
+        tier -> Varchar,
+        #[max_length = 255]
+        legal_name -> Nullable<Varchar>,
     }
 }
 
@@ -30,16 +362,79 @@ diesel::table! {
         id -> Int4,
         user_id -> Int4,
         balance -> Numeric,
-        status -> Bool,
+        #[max_length = 10]
+        status -> Varchar,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        #[max_length = 3]
+        currency -> Varchar,
+        #[max_length = 60]
+        name -> Varchar,
+        is_default -> Bool,
+        closed_at -> Nullable<Timestamptz>,
     }
 }
 
+diesel::joinable!(fx_quote -> fx_rate (fx_rate_id));
+diesel::joinable!(fx_quote -> users (user_id));
+diesel::joinable!(idempotency_key -> users (user_id));
+diesel::joinable!(journal_entry -> transaction (transaction_id));
+diesel::joinable!(ledger_account -> currency (currency));
+diesel::joinable!(ledger_account -> wallet (wallet_id));
+diesel::joinable!(hold -> currency (currency));
+diesel::joinable!(hold -> transaction (transaction_id));
+diesel::joinable!(hold -> users (merchant_id));
+diesel::joinable!(hold -> wallet (wallet_id));
+diesel::joinable!(payment_request -> currency (currency));
+diesel::joinable!(payment_request -> transaction (transaction_id));
+diesel::joinable!(posting -> journal_entry (journal_entry_id));
+diesel::joinable!(posting -> ledger_account (account_id));
+diesel::joinable!(recovery_code -> users (user_id));
+diesel::joinable!(refresh_token -> users (user_id));
+diesel::joinable!(revoked_token -> users (user_id));
+diesel::joinable!(scheduled_payment -> currency (currency));
+diesel::joinable!(scheduled_payment -> transaction (last_transaction_id));
+diesel::joinable!(scheduled_payment -> wallet (wallet_id));
+diesel::joinable!(security_event -> users (user_id));
+diesel::joinable!(sign_in_attempt -> users (user_id));
+diesel::joinable!(spending_limit -> currency (currency));
+diesel::joinable!(spending_limit -> users (user_id));
+diesel::joinable!(transaction -> fx_quote (fx_quote_id));
+diesel::joinable!(transaction -> users (admin_id));
+diesel::joinable!(transfer_review -> currency (currency));
+diesel::joinable!(transfer_review -> fx_quote (fx_quote_id));
+diesel::joinable!(transfer_review -> hold (hold_id));
+diesel::joinable!(transfer_review -> payment_request (payment_request_id));
+diesel::joinable!(transfer_review -> scheduled_payment (scheduled_payment_id));
+diesel::joinable!(transfer_review -> transaction (transaction_id));
+diesel::joinable!(transfer_review -> wallet (wallet_id));
+diesel::joinable!(user_token -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
+diesel::joinable!(wallet -> currency (currency));
 diesel::joinable!(wallet -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    currency,
+    fx_quote,
+    fx_rate,
+    hold,
+    idempotency_key,
+    journal_entry,
+    ledger_account,
+    payment_request,
+    posting,
+    recovery_code,
+    refresh_token,
+    revoked_token,
+    scheduled_payment,
+    screening_hit,
+    security_event,
+    sign_in_attempt,
+    spending_limit,
     transaction,
+    transfer_review,
+    user_token,
+    user_totp,
     users,
     wallet,
 );
@@ -0,0 +1,164 @@
+use chrono::Utc;
+use diesel::{
+    dsl, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+
+use super::{
+    handle_duplicate_error, handle_transaction_error,
+    models::{ScreeningContext, ScreeningHit, ScreeningStatus},
+    schema::{screening_hit, users},
+    Error, SmplDB,
+};
+
+/// A name that matched a watchlist entry closely enough to record
+#[derive(Debug)]
+pub struct WatchlistMatch {
+    pub screened_name: String,
+    pub entry_id: String,
+    pub entry_name: String,
+    /// Similarity of the names, from 0 to 1
+    pub score: f64,
+    /// The names have the same words once normalised and such hits are confirmed without review
+    pub confirmed: bool,
+}
+
+/// A screening hit with the username of the user it is on, if any
+pub type ScreeningHitWithUser = (ScreeningHit, Option<String>);
+
+/// Whether any of the users has a confirmed screening hit
+pub(super) async fn has_confirmed_hit(
+    conn: &mut AsyncPgConnection,
+    user_ids: &[i32],
+) -> Result<bool, diesel::result::Error> {
+    diesel::select(dsl::exists(
+        screening_hit::table
+            .filter(screening_hit::user_id.eq_any(user_ids))
+            .filter(screening_hit::status.eq(ScreeningStatus::Confirmed)),
+    ))
+    .get_result(conn)
+    .await
+}
+
+impl SmplDB {
+    /// Records the matches against the user, or against nobody for a blocked sign up. Entries the
+    /// user already has a hit for are skipped, so dismissed hits aren't raised again, as are
+    /// entries a blocked sign up was already recorded for, so retrying one can't flood the queue.
+    /// Returns the new hits.
+    pub async fn record_screening_hits(
+        &self,
+        user_id: Option<i32>,
+        context: ScreeningContext,
+        matches: &[WatchlistMatch],
+    ) -> Result<Vec<ScreeningHit>, Error> {
+        if matches.is_empty() {
+            return Ok(Vec::new());
+        }
+        let rows: Vec<_> = matches
+            .iter()
+            .map(|m| {
+                let status = if m.confirmed {
+                    ScreeningStatus::Confirmed
+                } else {
+                    ScreeningStatus::Pending
+                };
+                (
+                    screening_hit::user_id.eq(user_id),
+                    screening_hit::context.eq(context),
+                    screening_hit::screened_name.eq(&m.screened_name),
+                    screening_hit::entry_id.eq(&m.entry_id),
+                    screening_hit::entry_name.eq(&m.entry_name),
+                    screening_hit::score.eq(m.score),
+                    screening_hit::status.eq(status),
+                )
+            })
+            .collect();
+
+        let mut conn = self.get_conn().await?;
+        diesel::insert_into(screening_hit::table)
+            .values(rows)
+            .on_conflict_do_nothing()
+            .returning(ScreeningHit::as_returning())
+            .get_results(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    pub async fn get_screening_hit(&self, hit_id: i32) -> Result<ScreeningHitWithUser, Error> {
+        let mut conn = self.get_conn().await?;
+        screening_hit::table
+            .left_join(users::table.on(screening_hit::user_id.eq(users::id.nullable())))
+            .filter(screening_hit::id.eq(hit_id))
+            .select((ScreeningHit::as_select(), users::username.nullable()))
+            .first(&mut conn)
+            .await
+            .optional()?
+            .ok_or(Error::ScreeningHitNotFound)
+    }
+
+    /// Returns everyone's screening hits in `status`, oldest first
+    pub async fn screening_hit_queue(
+        &self,
+        status: ScreeningStatus,
+    ) -> Result<Vec<ScreeningHitWithUser>, Error> {
+        let mut conn = self.get_conn().await?;
+        screening_hit::table
+            .left_join(users::table.on(screening_hit::user_id.eq(users::id.nullable())))
+            .filter(screening_hit::status.eq(status))
+            .select((ScreeningHit::as_select(), users::username.nullable()))
+            .order((screening_hit::created_at, screening_hit::id))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Confirms a pending hit, or dismisses a pending or confirmed one
+    pub async fn review_screening_hit(
+        &self,
+        reviewer_id: i32,
+        hit_id: i32,
+        status: ScreeningStatus,
+        note: Option<&str>,
+    ) -> Result<ScreeningHit, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let current: ScreeningStatus = screening_hit::table
+                    .find(hit_id)
+                    .select(screening_hit::status)
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::ScreeningHitNotFound)?;
+                match (current, status) {
+                    (ScreeningStatus::Pending, ScreeningStatus::Confirmed)
+                    | (
+                        ScreeningStatus::Pending | ScreeningStatus::Confirmed,
+                        ScreeningStatus::Dismissed,
+                    ) => {}
+                    _ => return Err(Error::InvalidStatusTransition),
+                }
+
+                let now = Utc::now();
+                Ok(diesel::update(screening_hit::table.find(hit_id))
+                    .set((
+                        screening_hit::status.eq(status),
+                        screening_hit::reviewer_id.eq(reviewer_id),
+                        screening_hit::note.eq(note),
+                        screening_hit::reviewed_at.eq(now),
+                        screening_hit::updated_at.eq(now),
+                    ))
+                    .returning(ScreeningHit::as_returning())
+                    .get_result(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+}
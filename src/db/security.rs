@@ -0,0 +1,309 @@
+use chrono::{DateTime, Utc};
+use diesel::{dsl, ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error, handle_transaction_error,
+    models::{AccountStatus, Role, Tier, User},
+    schema::{security_event, sign_in_attempt, users},
+    Error, SmplDB,
+};
+
+/// Something that happened to an account that support may need to explain
+#[derive(Debug, Clone, Copy)]
+pub enum SecurityEventKind {
+    /// Too many failed sign in attempts for the account
+    AccountLocked,
+    /// Too many failed sign in attempts from an IP address
+    IpBlocked,
+    /// Frozen by an admin
+    AccountFrozen,
+    /// Unfrozen by an admin
+    AccountUnfrozen,
+    /// Closed by an admin
+    AccountClosed,
+    /// Given another role by an admin
+    RoleChanged,
+    /// Moved to another tier by an admin
+    TierChanged,
+}
+
+impl SecurityEventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SecurityEventKind::AccountLocked => "account_locked",
+            SecurityEventKind::IpBlocked => "ip_blocked",
+            SecurityEventKind::AccountFrozen => "account_frozen",
+            SecurityEventKind::AccountUnfrozen => "account_unfrozen",
+            SecurityEventKind::AccountClosed => "account_closed",
+            SecurityEventKind::RoleChanged => "role_changed",
+            SecurityEventKind::TierChanged => "tier_changed",
+        }
+    }
+}
+
+/// Failed sign in attempts counting towards a lockout
+#[derive(Debug)]
+pub struct RecentFailures {
+    pub count: i64,
+    pub last_failed_at: Option<DateTime<Utc>>,
+}
+
+impl SmplDB {
+    pub async fn record_sign_in_attempt(
+        &self,
+        user_id: Option<i32>,
+        email: &str,
+        ip_address: &str,
+        succeeded: bool,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::insert_into(sign_in_attempt::table)
+            .values((
+                sign_in_attempt::user_id.eq(user_id),
+                sign_in_attempt::email.eq(email),
+                sign_in_attempt::ip_address.eq(ip_address),
+                sign_in_attempt::succeeded.eq(succeeded),
+            ))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+
+    /// Failed sign in attempts for the user since `since`, ignoring any from before their last
+    /// successful sign in or lockout
+    pub async fn recent_account_failures(
+        &self,
+        user_id: i32,
+        since: DateTime<Utc>,
+    ) -> Result<RecentFailures, Error> {
+        let mut conn = self.get_conn().await?;
+        let last_success: Option<DateTime<Utc>> = sign_in_attempt::table
+            .filter(sign_in_attempt::user_id.eq(user_id))
+            .filter(sign_in_attempt::succeeded.eq(true))
+            .select(dsl::max(sign_in_attempt::created_at))
+            .first(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        let locked_until: Option<DateTime<Utc>> = users::table
+            .find(user_id)
+            .select(users::locked_until)
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)?
+            .flatten();
+        let since = [Some(since), last_success, locked_until]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(since);
+
+        let (count, last_failed_at) = sign_in_attempt::table
+            .filter(sign_in_attempt::user_id.eq(user_id))
+            .filter(sign_in_attempt::succeeded.eq(false))
+            .filter(sign_in_attempt::created_at.gt(since))
+            .select((dsl::count_star(), dsl::max(sign_in_attempt::created_at)))
+            .first(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(RecentFailures {
+            count,
+            last_failed_at,
+        })
+    }
+
+    /// Failed sign in attempts from `ip_address` since `since`, for any account
+    pub async fn recent_ip_failures(
+        &self,
+        ip_address: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let mut conn = self.get_conn().await?;
+        sign_in_attempt::table
+            .filter(sign_in_attempt::ip_address.eq(ip_address))
+            .filter(sign_in_attempt::succeeded.eq(false))
+            .filter(sign_in_attempt::created_at.gt(since))
+            .count()
+            .get_result(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Locks the user out of signing in until `until`, recording why
+    pub async fn lock_user(
+        &self,
+        user_id: i32,
+        until: DateTime<Utc>,
+        ip_address: &str,
+        detail: &str,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                diesel::update(users::table.find(user_id))
+                    .set(users::locked_until.eq(until))
+                    .execute(conn)
+                    .await?;
+                diesel::insert_into(security_event::table)
+                    .values((
+                        security_event::user_id.eq(user_id),
+                        security_event::kind.eq(SecurityEventKind::AccountLocked.as_str()),
+                        security_event::ip_address.eq(ip_address),
+                        security_event::detail.eq(detail),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    /// Moves the user to `status`, recording why
+    pub async fn set_user_status(
+        &self,
+        user_id: i32,
+        status: AccountStatus,
+        detail: Option<&str>,
+    ) -> Result<User, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let current: AccountStatus = users::table
+                    .find(user_id)
+                    .select(users::status)
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::UserNotFound)?;
+                if !current.can_become(status) {
+                    return Err(Error::InvalidStatusTransition);
+                }
+
+                let user = diesel::update(users::table.find(user_id))
+                    .set((
+                        users::status.eq(status),
+                        users::updated_at.eq(Some(Utc::now())),
+                    ))
+                    .returning(User::as_returning())
+                    .get_result(conn)
+                    .await?;
+                let kind = match status {
+                    AccountStatus::Active => SecurityEventKind::AccountUnfrozen,
+                    AccountStatus::Frozen => SecurityEventKind::AccountFrozen,
+                    AccountStatus::Closed => SecurityEventKind::AccountClosed,
+                };
+                diesel::insert_into(security_event::table)
+                    .values((
+                        security_event::user_id.eq(user_id),
+                        security_event::kind.eq(kind.as_str()),
+                        security_event::detail.eq(detail),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(user)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Gives the user `role`, recording the change
+    pub async fn set_user_role(&self, user_id: i32, role: Role) -> Result<User, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let current: Role = users::table
+                    .find(user_id)
+                    .select(users::role)
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::UserNotFound)?;
+
+                let user = diesel::update(users::table.find(user_id))
+                    .set((users::role.eq(role), users::updated_at.eq(Some(Utc::now()))))
+                    .returning(User::as_returning())
+                    .get_result(conn)
+                    .await?;
+                let detail = format!("{} -> {}", current.as_str(), role.as_str());
+                diesel::insert_into(security_event::table)
+                    .values((
+                        security_event::user_id.eq(user_id),
+                        security_event::kind.eq(SecurityEventKind::RoleChanged.as_str()),
+                        security_event::detail.eq(detail),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(user)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Moves the user to `tier`, recording the change
+    pub async fn set_user_tier(&self, user_id: i32, tier: Tier) -> Result<User, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let current: Tier = users::table
+                    .find(user_id)
+                    .select(users::tier)
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::UserNotFound)?;
+
+                let user = diesel::update(users::table.find(user_id))
+                    .set((users::tier.eq(tier), users::updated_at.eq(Some(Utc::now()))))
+                    .returning(User::as_returning())
+                    .get_result(conn)
+                    .await?;
+                let detail = format!("{} -> {}", current.as_str(), tier.as_str());
+                diesel::insert_into(security_event::table)
+                    .values((
+                        security_event::user_id.eq(user_id),
+                        security_event::kind.eq(SecurityEventKind::TierChanged.as_str()),
+                        security_event::detail.eq(detail),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(user)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    pub async fn record_security_event(
+        &self,
+        user_id: Option<i32>,
+        kind: SecurityEventKind,
+        ip_address: Option<&str>,
+        detail: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::insert_into(security_event::table)
+            .values((
+                security_event::user_id.eq(user_id),
+                security_event::kind.eq(kind.as_str()),
+                security_event::ip_address.eq(ip_address),
+                security_event::detail.eq(detail),
+            ))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+use std::fmt;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveTime, Utc};
+use diesel::{
+    dsl, sql_types::Integer, AsChangeset, BoolExpressionMethods, ExpressionMethods, Insertable,
+    NullableExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    currency, handle_duplicate_error,
+    models::{SpendingLimit, Tier, TransactionKind},
+    schema::{spending_limit, transaction, users, wallet},
+    Error, SmplDB,
+};
+
+/// Namespace of the advisory locks taken on a user's spending, so they can't collide with other
+/// advisory locks
+const SPENDING_LOCK: i32 = 1;
+
+/// Window spending is counted over. Windows are calendar days, weeks from Monday and months, in
+/// UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl LimitPeriod {
+    const ALL: [LimitPeriod; 3] = [
+        LimitPeriod::Daily,
+        LimitPeriod::Weekly,
+        LimitPeriod::Monthly,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LimitPeriod::Daily => "daily",
+            LimitPeriod::Weekly => "weekly",
+            LimitPeriod::Monthly => "monthly",
+        }
+    }
+
+    /// Start of the window `now` is in
+    fn start(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.date_naive();
+        let first_day = match self {
+            LimitPeriod::Daily => today,
+            LimitPeriod::Weekly => {
+                today - Duration::days(today.weekday().num_days_from_monday().into())
+            }
+            LimitPeriod::Monthly => today - Duration::days(today.day0().into()),
+        };
+        first_day.and_time(NaiveTime::MIN).and_utc()
+    }
+
+    /// Start of the window after the one starting at `start`
+    fn next(self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            LimitPeriod::Daily => start + Duration::days(1),
+            LimitPeriod::Weekly => start + Duration::weeks(1),
+            LimitPeriod::Monthly => start
+                .checked_add_months(Months::new(1))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+/// A spending limit that a payment would go over
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    PerTransaction,
+    Amount(LimitPeriod),
+    Count(LimitPeriod),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::PerTransaction => write!(f, "per transaction limit"),
+            Limit::Amount(period) => write!(f, "{} amount limit", period.as_str()),
+            Limit::Count(period) => write!(f, "{} transfer count limit", period.as_str()),
+        }
+    }
+}
+
+/// Spending limits to set in one currency. `None` is no limit, or for a user, their tier's limit.
+#[derive(Debug, Default, Deserialize, Insertable, AsChangeset)]
+#[diesel(table_name = spending_limit, treat_none_as_null = true)]
+pub struct Limits {
+    pub per_transaction: Option<BigDecimal>,
+    pub daily_amount: Option<BigDecimal>,
+    pub weekly_amount: Option<BigDecimal>,
+    pub monthly_amount: Option<BigDecimal>,
+    pub daily_count: Option<i32>,
+    pub weekly_count: Option<i32>,
+    pub monthly_count: Option<i32>,
+}
+
+impl Limits {
+    /// The user's own limits, falling back to their tier's one limit at a time. A limit the user
+    /// doesn't have always falls back, so their own limits can raise a tier's limit but not
+    /// remove it.
+    fn merge(own: Option<SpendingLimit>, tier: Option<SpendingLimit>) -> Self {
+        let amount = |f: fn(&SpendingLimit) -> &Option<BigDecimal>| {
+            own.as_ref()
+                .and_then(|l| f(l).clone())
+                .or_else(|| tier.as_ref().and_then(|l| f(l).clone()))
+        };
+        let count = |f: fn(&SpendingLimit) -> Option<i32>| {
+            own.as_ref()
+                .and_then(f)
+                .or_else(|| tier.as_ref().and_then(f))
+        };
+        Self {
+            per_transaction: amount(|l| &l.per_transaction),
+            daily_amount: amount(|l| &l.daily_amount),
+            weekly_amount: amount(|l| &l.weekly_amount),
+            monthly_amount: amount(|l| &l.monthly_amount),
+            daily_count: count(|l| l.daily_count),
+            weekly_count: count(|l| l.weekly_count),
+            monthly_count: count(|l| l.monthly_count),
+        }
+    }
+
+    fn period(&self, period: LimitPeriod) -> (Option<&BigDecimal>, Option<i32>) {
+        match period {
+            LimitPeriod::Daily => (self.daily_amount.as_ref(), self.daily_count),
+            LimitPeriod::Weekly => (self.weekly_amount.as_ref(), self.weekly_count),
+            LimitPeriod::Monthly => (self.monthly_amount.as_ref(), self.monthly_count),
+        }
+    }
+
+    /// Gives the amounts the currency's minor units, failing with [`Error::InvalidPrecision`] if
+    /// one has more decimal places than it
+    async fn scale(mut self, conn: &mut AsyncPgConnection, currency: &str) -> Result<Self, Error> {
+        for amount in [
+            &mut self.per_transaction,
+            &mut self.daily_amount,
+            &mut self.weekly_amount,
+            &mut self.monthly_amount,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *amount = currency::scale_amount(conn, currency, amount).await?;
+        }
+        Ok(self)
+    }
+}
+
+/// What is left of a user's limits in one window
+#[derive(Debug, Serialize)]
+pub struct PeriodAllowance {
+    pub amount_limit: Option<BigDecimal>,
+    pub amount_used: BigDecimal,
+    pub amount_remaining: Option<BigDecimal>,
+    pub count_limit: Option<i32>,
+    pub count_used: i64,
+    pub count_remaining: Option<i64>,
+    pub resets_at: DateTime<Utc>,
+}
+
+/// A user's limits in one currency and what is left of them
+#[derive(Debug, Serialize)]
+pub struct Allowance {
+    pub currency: String,
+    pub tier: Tier,
+    pub per_transaction: Option<BigDecimal>,
+    pub daily: PeriodAllowance,
+    pub weekly: PeriodAllowance,
+    pub monthly: PeriodAllowance,
+}
+
+/// The user's tier and the limits that apply to them in `currency`
+async fn user_limits(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    currency: &str,
+) -> Result<(Tier, Limits), Error> {
+    let tier: Tier = users::table
+        .find(user_id)
+        .select(users::tier)
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::UserNotFound)?;
+    let rows: Vec<SpendingLimit> = spending_limit::table
+        .filter(spending_limit::currency.eq(currency))
+        .filter(
+            spending_limit::user_id
+                .eq(user_id)
+                .or(spending_limit::tier.eq(tier)),
+        )
+        .select(SpendingLimit::as_select())
+        .load(conn)
+        .await?;
+    let (own, tier_limits): (Vec<_>, Vec<_>) = rows.into_iter().partition(|l| l.user_id.is_some());
+    Ok((
+        tier,
+        Limits::merge(own.into_iter().next(), tier_limits.into_iter().next()),
+    ))
+}
+
+/// What the user has sent and withdrawn from their wallets in `currency` since `since`, and in
+/// how many transactions. Moves, refunds and reversals don't count, and neither do conversions
+/// into their own wallets.
+async fn spent_since(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    currency: &str,
+    since: DateTime<Utc>,
+) -> Result<(BigDecimal, i64), diesel::result::Error> {
+    let own_wallets = wallet::table
+        .filter(wallet::user_id.eq(user_id))
+        .select(wallet::id.nullable());
+    let to_own_wallet = dsl::exists(
+        wallet::table
+            .filter(wallet::id.nullable().eq(transaction::to_wallet))
+            .filter(wallet::user_id.eq(user_id)),
+    );
+    let (amount, count): (Option<BigDecimal>, i64) = transaction::table
+        .filter(transaction::from_wallet.eq_any(own_wallets))
+        .filter(transaction::kind.eq_any([
+            TransactionKind::Transfer,
+            TransactionKind::Withdrawal,
+            TransactionKind::Conversion,
+        ]))
+        .filter(
+            transaction::kind
+                .ne(TransactionKind::Conversion)
+                .or(dsl::not(to_own_wallet)),
+        )
+        .filter(transaction::currency.eq(currency))
+        .filter(transaction::created_at.ge(since))
+        .select((dsl::sum(transaction::amount), dsl::count_star()))
+        .first(conn)
+        .await?;
+    Ok((amount.unwrap_or_default(), count))
+}
+
+/// What is left of `limits` in the window `period` that `now` is in
+async fn period_allowance(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    currency: &str,
+    limits: &Limits,
+    period: LimitPeriod,
+    now: DateTime<Utc>,
+) -> Result<PeriodAllowance, Error> {
+    let (amount_limit, count_limit) = limits.period(period);
+    let start = period.start(now);
+    let (amount_used, count_used) = spent_since(conn, user_id, currency, start).await?;
+    Ok(PeriodAllowance {
+        amount_remaining: amount_limit.map(|max| (max - &amount_used).max(BigDecimal::zero())),
+        amount_limit: amount_limit.cloned(),
+        amount_used,
+        count_remaining: count_limit.map(|max| (i64::from(max) - count_used).max(0)),
+        count_limit,
+        count_used,
+        resets_at: period.next(start),
+    })
+}
+
+/// Fails if sending `amount` of `currency` would take the user over a limit.
+///
+/// Holds a lock on the user's spending until the DB transaction ends, so it must be called in the
+/// one that records the payment. Concurrent payments by the user then can't both fit under a limit
+/// that only one of them does.
+pub(super) async fn check_spending_limits(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    currency: &str,
+    amount: &BigDecimal,
+) -> Result<(), Error> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind::<Integer, _>(SPENDING_LOCK)
+        .bind::<Integer, _>(user_id)
+        .execute(conn)
+        .await?;
+
+    let (_, limits) = user_limits(conn, user_id, currency).await?;
+    if limits
+        .per_transaction
+        .as_ref()
+        .is_some_and(|max| amount > max)
+    {
+        return Err(Error::LimitExceeded(Limit::PerTransaction));
+    }
+    let now = Utc::now();
+    for period in LimitPeriod::ALL {
+        let (max_amount, max_count) = limits.period(period);
+        if max_amount.is_none() && max_count.is_none() {
+            continue;
+        }
+        let (spent, count) = spent_since(conn, user_id, currency, period.start(now)).await?;
+        if max_count.is_some_and(|max| count >= i64::from(max)) {
+            return Err(Error::LimitExceeded(Limit::Count(period)));
+        }
+        if max_amount.is_some_and(|max| &(spent + amount) > max) {
+            return Err(Error::LimitExceeded(Limit::Amount(period)));
+        }
+    }
+    Ok(())
+}
+
+impl SmplDB {
+    /// Returns the limits that apply to the user in `currency`, and what is left of them
+    pub async fn get_allowance(&self, user_id: i32, currency: &str) -> Result<Allowance, Error> {
+        let mut conn = self.get_conn().await?;
+        currency::minor_units(&mut conn, currency).await?;
+        let (tier, limits) = user_limits(&mut conn, user_id, currency).await?;
+
+        let now = Utc::now();
+        let conn = &mut conn;
+        Ok(Allowance {
+            currency: currency.to_string(),
+            tier,
+            daily: period_allowance(conn, user_id, currency, &limits, LimitPeriod::Daily, now)
+                .await?,
+            weekly: period_allowance(conn, user_id, currency, &limits, LimitPeriod::Weekly, now)
+                .await?,
+            monthly: period_allowance(conn, user_id, currency, &limits, LimitPeriod::Monthly, now)
+                .await?,
+            per_transaction: limits.per_transaction,
+        })
+    }
+
+    /// Returns every tier's limits, by tier and currency
+    pub async fn list_tier_limits(&self) -> Result<Vec<SpendingLimit>, Error> {
+        let mut conn = self.get_conn().await?;
+        Ok(spending_limit::table
+            .filter(spending_limit::tier.is_not_null())
+            .select(SpendingLimit::as_select())
+            .order((spending_limit::tier, spending_limit::currency))
+            .load(&mut conn)
+            .await?)
+    }
+
+    /// Replaces the tier's limits in `currency`
+    pub async fn set_tier_limits(
+        &self,
+        tier: Tier,
+        currency: &str,
+        limits: Limits,
+    ) -> Result<SpendingLimit, Error> {
+        let mut conn = self.get_conn().await?;
+        let limits = limits.scale(&mut conn, currency).await?;
+        diesel::insert_into(spending_limit::table)
+            .values((
+                spending_limit::tier.eq(tier),
+                spending_limit::currency.eq(currency),
+                &limits,
+            ))
+            .on_conflict((spending_limit::tier, spending_limit::currency))
+            .do_update()
+            .set((&limits, spending_limit::updated_at.eq(Utc::now())))
+            .returning(SpendingLimit::as_returning())
+            .get_result(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Replaces the user's own limits in `currency`
+    pub async fn set_user_limits(
+        &self,
+        user_id: i32,
+        currency: &str,
+        limits: Limits,
+    ) -> Result<SpendingLimit, Error> {
+        let mut conn = self.get_conn().await?;
+        let limits = limits.scale(&mut conn, currency).await?;
+        let found: i64 = users::table
+            .find(user_id)
+            .count()
+            .get_result(&mut conn)
+            .await?;
+        if found == 0 {
+            return Err(Error::UserNotFound);
+        }
+        diesel::insert_into(spending_limit::table)
+            .values((
+                spending_limit::user_id.eq(user_id),
+                spending_limit::currency.eq(currency),
+                &limits,
+            ))
+            .on_conflict((spending_limit::user_id, spending_limit::currency))
+            .do_update()
+            .set((&limits, spending_limit::updated_at.eq(Utc::now())))
+            .returning(SpendingLimit::as_returning())
+            .get_result(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn daily_window_starts_at_midnight() {
+        let start = LimitPeriod::Daily.start(at("2026-10-18T23:59:59Z"));
+        assert_eq!(start, at("2026-10-18T00:00:00Z"));
+        assert_eq!(LimitPeriod::Daily.next(start), at("2026-10-19T00:00:00Z"));
+    }
+
+    #[test]
+    fn weekly_window_starts_on_monday() {
+        // a Sunday belongs to the week that started the Monday before
+        let start = LimitPeriod::Weekly.start(at("2026-10-18T12:00:00Z"));
+        assert_eq!(start, at("2026-10-12T00:00:00Z"));
+        assert_eq!(LimitPeriod::Weekly.next(start), at("2026-10-19T00:00:00Z"));
+
+        let monday = at("2026-10-19T00:00:00Z");
+        assert_eq!(LimitPeriod::Weekly.start(monday), monday);
+    }
+
+    #[test]
+    fn weekly_window_spans_the_new_year() {
+        let start = LimitPeriod::Weekly.start(at("2026-01-01T08:00:00Z"));
+        assert_eq!(start, at("2025-12-29T00:00:00Z"));
+        assert_eq!(LimitPeriod::Weekly.next(start), at("2026-01-05T00:00:00Z"));
+    }
+
+    #[test]
+    fn monthly_window_starts_on_the_first() {
+        let start = LimitPeriod::Monthly.start(at("2026-01-31T18:30:00Z"));
+        assert_eq!(start, at("2026-01-01T00:00:00Z"));
+        assert_eq!(LimitPeriod::Monthly.next(start), at("2026-02-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn monthly_window_rolls_over_the_year() {
+        let start = LimitPeriod::Monthly.start(at("2025-12-15T00:00:00Z"));
+        assert_eq!(start, at("2025-12-01T00:00:00Z"));
+        assert_eq!(LimitPeriod::Monthly.next(start), at("2026-01-01T00:00:00Z"));
+    }
+}
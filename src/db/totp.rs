@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    upsert::excluded, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error,
+    models::UserTotp,
+    schema::{recovery_code, user_totp},
+    Error, SmplDB,
+};
+
+impl SmplDB {
+    pub async fn get_totp(&self, user_id: i32) -> Result<Option<UserTotp>, Error> {
+        let mut conn = self.get_conn().await?;
+        user_totp::table
+            .find(user_id)
+            .select(UserTotp::as_select())
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Stores an unconfirmed TOTP secret for the user, replacing any earlier unconfirmed one.
+    /// Returns `false` if the user already has TOTP enabled.
+    pub async fn start_totp_enrolment(&self, user_id: i32, secret: &str) -> Result<bool, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let confirmed_at: Option<Option<DateTime<Utc>>> = user_totp::table
+                    .find(user_id)
+                    .select(user_totp::confirmed_at)
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?;
+                if let Some(Some(_)) = confirmed_at {
+                    return Ok(false);
+                }
+
+                diesel::insert_into(user_totp::table)
+                    .values((user_totp::user_id.eq(user_id), user_totp::secret.eq(secret)))
+                    .on_conflict(user_totp::user_id)
+                    .do_update()
+                    .set((
+                        user_totp::secret.eq(excluded(user_totp::secret)),
+                        user_totp::last_used_step.eq(None::<i64>),
+                        user_totp::created_at.eq(Utc::now()),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(true)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    /// Enables TOTP for the user and replaces their recovery codes. `step` is the time step of the
+    /// code used to confirm, which can't be used again. Returns `false` if there is no enrolment
+    /// to confirm.
+    pub async fn confirm_totp(
+        &self,
+        user_id: i32,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let updated = diesel::update(user_totp::table.find(user_id))
+                    .filter(user_totp::confirmed_at.is_null())
+                    .set((
+                        user_totp::confirmed_at.eq(Utc::now()),
+                        user_totp::last_used_step.eq(step),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if updated == 0 {
+                    return Ok(false);
+                }
+
+                diesel::delete(recovery_code::table)
+                    .filter(recovery_code::user_id.eq(user_id))
+                    .execute(conn)
+                    .await?;
+                let codes: Vec<_> = recovery_code_hashes
+                    .iter()
+                    .map(|hash| {
+                        (
+                            recovery_code::user_id.eq(user_id),
+                            recovery_code::code_hash.eq(hash),
+                        )
+                    })
+                    .collect();
+                diesel::insert_into(recovery_code::table)
+                    .values(codes)
+                    .execute(conn)
+                    .await?;
+                Ok(true)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    /// Records that a code from time `step` was used. Returns `false` if a code from this step or a
+    /// later one has already been used, so each code can only be used once.
+    pub async fn use_totp_step(&self, user_id: i32, step: i64) -> Result<bool, Error> {
+        let mut conn = self.get_conn().await?;
+        let updated = diesel::update(user_totp::table.find(user_id))
+            .filter(user_totp::confirmed_at.is_not_null())
+            .filter(
+                user_totp::last_used_step
+                    .is_null()
+                    .or(user_totp::last_used_step.lt(step)),
+            )
+            .set(user_totp::last_used_step.eq(step))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(updated == 1)
+    }
+
+    /// Uses up an unused recovery code. Returns `false` if there is no such code.
+    pub async fn use_recovery_code(&self, user_id: i32, code_hash: &str) -> Result<bool, Error> {
+        let mut conn = self.get_conn().await?;
+        let updated = diesel::update(recovery_code::table)
+            .filter(recovery_code::user_id.eq(user_id))
+            .filter(recovery_code::code_hash.eq(code_hash))
+            .filter(recovery_code::used_at.is_null())
+            .set(recovery_code::used_at.eq(Utc::now()))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(updated == 1)
+    }
+
+    /// Removes the user's TOTP secret and recovery codes
+    pub async fn disable_totp(&self, user_id: i32) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                diesel::delete(recovery_code::table)
+                    .filter(recovery_code::user_id.eq(user_id))
+                    .execute(conn)
+                    .await?;
+                diesel::delete(user_totp::table.find(user_id))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+}
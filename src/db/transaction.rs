@@ -1,141 +1,675 @@
+use std::{fmt, str::FromStr};
+
 use bigdecimal::BigDecimal;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use diesel::{
-    BoolExpressionMethods, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl,
+    BoolExpressionMethods, ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl,
     SelectableHelper,
 };
-use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Deserialize;
 
 use super::{
-    handle_duplicate_error,
-    models::Transaction,
+    currency, fx, handle_duplicate_error, handle_transaction_error,
+    hold::available_balance,
+    idempotency::{complete_idempotent_request, IdempotentRequest},
+    ledger::{self, Account, SystemAccount},
+    models::{AccountStatus, FxQuote, Transaction, TransactionKind, Wallet},
     schema::{transaction, users, wallet},
+    screening,
+    spending_limit::check_spending_limits,
+    transfer_review::{assess_transfer, AssessRisk, PaymentOutcome, TransferSource},
+    users::{lock_active_user, lock_recipient},
+    wallet::{find_wallet, lock_wallet, WalletRef},
     Error, SmplDB,
 };
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Default)]
+pub struct TransactionFilter {
+    pub direction: Option<TransactionDirection>,
+    /// Username of the other party of a transfer
+    pub counterparty: Option<String>,
+    pub min_amount: Option<BigDecimal>,
+    pub max_amount: Option<BigDecimal>,
+    pub currency: Option<String>,
+    /// One of the user's wallets, open or closed
+    pub wallet_id: Option<i32>,
+    /// Inclusive lower bound on `created_at`
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on `created_at`
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Position in a list of transactions ordered by `(created_at, id)`. Encoded as
+/// `<created_at in microseconds>.<id>`.
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionCursor {
+    created_at: DateTime<Utc>,
+    id: i32,
+}
+
+impl From<&Transaction> for TransactionCursor {
+    fn from(transaction: &Transaction) -> Self {
+        Self {
+            created_at: transaction.created_at,
+            id: transaction.id,
+        }
+    }
+}
+
+impl fmt::Display for TransactionCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.created_at.timestamp_micros(), self.id)
+    }
+}
+
+impl FromStr for TransactionCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (micros, id) = s.split_once('.').ok_or(())?;
+        let created_at = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or(())?;
+        let id = id.parse().map_err(|_| ())?;
+        Ok(Self { created_at, id })
+    }
+}
+
+/// Wallets a transfer moves money between, locked for update
+struct TransferWallets {
+    from_wallet: Wallet,
+    to_user_id: i32,
+    to_wallet_id: i32,
+}
+
+/// Locks the sender's wallet and the recipient's default wallet in `to_currency`, or in the
+/// sender's wallet's currency when `None`, checking that both can move money
+async fn lock_transfer_wallets(
+    conn: &mut AsyncPgConnection,
+    from_user_id: i32,
+    from_wallet: &WalletRef,
+    to_username: &str,
+    to_currency: Option<&str>,
+) -> Result<TransferWallets, Error> {
+    lock_active_user(conn, from_user_id).await?;
+    let from_wallet = find_wallet(conn, from_user_id, from_wallet).await?;
+
+    let to_user_id = lock_recipient(conn, to_username).await?;
+
+    let to_currency = to_currency.unwrap_or(&from_wallet.currency).to_string();
+    let to_wallet = find_wallet(conn, to_user_id, &WalletRef::Default(to_currency))
+        .await
+        .map_err(|e| match e {
+            Error::WalletNotFound => Error::RecipientWalletNotFound,
+            e => e,
+        })?;
+    if to_wallet.id == from_wallet.id {
+        return Err(Error::SelfTransfer);
+    }
+
+    // lock in id order so transfers in opposite directions can't deadlock
+    let mut wallets = [(from_user_id, from_wallet.id), (to_user_id, to_wallet.id)];
+    wallets.sort_unstable_by_key(|(_, id)| *id);
+    let mut locked = Vec::with_capacity(2);
+    for (user_id, id) in wallets {
+        let wallet = WalletRef::Id { id, currency: None };
+        locked.push(
+            lock_wallet(conn, user_id, &wallet)
+                .await
+                .map_err(|e| match e {
+                    Error::WalletNotFound if user_id == to_user_id => {
+                        Error::RecipientWalletNotFound
+                    }
+                    e => e,
+                })?,
+        );
+    }
+    let (from_wallet, to_wallet) = if locked[0].id == from_wallet.id {
+        (locked.swap_remove(0), locked.swap_remove(0))
+    } else {
+        (locked.swap_remove(1), locked.swap_remove(0))
+    };
+    if screening::has_confirmed_hit(conn, &[from_user_id, to_user_id]).await? {
+        return Err(Error::ScreeningMatch);
+    }
+
+    if from_wallet.status != AccountStatus::Active {
+        return Err(Error::WalletFrozen);
+    }
+    if to_wallet.status != AccountStatus::Active {
+        return Err(Error::RecipientWalletFrozen);
+    }
+    Ok(TransferWallets {
+        from_wallet,
+        to_user_id,
+        to_wallet_id: to_wallet.id,
+    })
+}
+
+/// A transfer that passed every check, with its wallets locked
+pub(super) struct CheckedTransfer {
+    pub from_user_id: i32,
+    pub from_wallet: Wallet,
+    pub to_user_id: i32,
+    pub to_wallet_id: i32,
+    pub amount: BigDecimal,
+}
+
+/// Checks that `amount` can be paid from the user's wallet into `to_username`'s default wallet in
+/// its currency. Must be called inside a DB transaction.
+pub(super) async fn check_transfer(
+    conn: &mut AsyncPgConnection,
+    from_user_id: i32,
+    from_wallet: &WalletRef,
+    to_username: &str,
+    amount: &BigDecimal,
+) -> Result<CheckedTransfer, Error> {
+    let TransferWallets {
+        from_wallet,
+        to_user_id,
+        to_wallet_id,
+    } = lock_transfer_wallets(conn, from_user_id, from_wallet, to_username, None).await?;
+    let amount = currency::scale_amount(conn, &from_wallet.currency, amount).await?;
+    if available_balance(conn, &from_wallet).await? < amount {
+        return Err(Error::InsufficientFunds);
+    }
+    check_spending_limits(conn, from_user_id, &from_wallet.currency, &amount).await?;
+    Ok(CheckedTransfer {
+        from_user_id,
+        from_wallet,
+        to_user_id,
+        to_wallet_id,
+        amount,
+    })
+}
+
+/// Pays `amount` from the user's wallet into `to_username`'s default wallet in its currency,
+/// unless `risk` holds the transfer for review or blocks it. Must be called inside a DB
+/// transaction.
+pub(super) async fn pay(
+    conn: &mut AsyncPgConnection,
+    from_user_id: i32,
+    from_wallet: &WalletRef,
+    to_username: &str,
+    amount: &BigDecimal,
+    risk: &dyn AssessRisk,
+    source: TransferSource,
+) -> Result<PaymentOutcome, Error> {
+    let transfer = check_transfer(conn, from_user_id, from_wallet, to_username, amount).await?;
+    if let Some(outcome) = assess_transfer(conn, &transfer, risk, source).await? {
+        return Ok(outcome);
+    }
+    Ok(PaymentOutcome::Paid(make_transfer(conn, transfer).await?))
+}
+
+/// Makes a checked transfer
+pub(super) async fn make_transfer(
+    conn: &mut AsyncPgConnection,
+    transfer: CheckedTransfer,
+) -> Result<Transaction, Error> {
+    let CheckedTransfer {
+        from_wallet,
+        to_wallet_id,
+        amount,
+        ..
+    } = transfer;
+    let currency = from_wallet.currency;
+
+    // make transaction
+    let transaction: Transaction = diesel::insert_into(transaction::table)
+        .values((
+            transaction::from_wallet.eq(from_wallet.id),
+            transaction::to_wallet.eq(to_wallet_id),
+            transaction::amount.eq(&amount),
+            transaction::currency.eq(&currency),
+            transaction::kind.eq(TransactionKind::Transfer),
+        ))
+        .returning(Transaction::as_returning())
+        .get_result(conn)
+        .await?;
+
+    // move the funds from sender to receiver
+    ledger::post_journal_entry(
+        conn,
+        Some(transaction.id),
+        "Transfer",
+        &currency,
+        &[
+            (Account::Wallet(from_wallet.id), -amount.clone()),
+            (Account::Wallet(to_wallet_id), amount),
+        ],
+    )
+    .await?;
+
+    Ok(transaction)
+}
+
+/// Checks that the quote's amount can be converted from the user's wallet, or their default
+/// wallet in the quote's currency, into `to_username`'s default wallet in the quote's target
+/// currency. Must be called inside a DB transaction.
+pub(super) async fn check_conversion(
+    conn: &mut AsyncPgConnection,
+    from_user_id: i32,
+    from_wallet_id: Option<i32>,
+    to_username: &str,
+    quote: &FxQuote,
+) -> Result<CheckedTransfer, Error> {
+    let from_wallet = match from_wallet_id {
+        Some(id) => WalletRef::Id {
+            id,
+            currency: Some(quote.from_currency.clone()),
+        },
+        None => WalletRef::Default(quote.from_currency.clone()),
+    };
+    let TransferWallets {
+        from_wallet,
+        to_user_id,
+        to_wallet_id,
+    } = lock_transfer_wallets(
+        conn,
+        from_user_id,
+        &from_wallet,
+        to_username,
+        Some(&quote.to_currency),
+    )
+    .await?;
+    if available_balance(conn, &from_wallet).await? < quote.from_amount {
+        return Err(Error::InsufficientFunds);
+    }
+    check_spending_limits(conn, from_user_id, &quote.from_currency, &quote.from_amount).await?;
+    Ok(CheckedTransfer {
+        from_user_id,
+        from_wallet,
+        to_user_id,
+        to_wallet_id,
+        amount: quote.from_amount.clone(),
+    })
+}
+
+/// Makes a checked conversion at the quote's rate
+pub(super) async fn make_conversion(
+    conn: &mut AsyncPgConnection,
+    transfer: CheckedTransfer,
+    quote: &FxQuote,
+) -> Result<Transaction, Error> {
+    let from_wallet_id = transfer.from_wallet.id;
+    let to_wallet_id = transfer.to_wallet_id;
+
+    let transaction: Transaction = diesel::insert_into(transaction::table)
+        .values((
+            transaction::from_wallet.eq(from_wallet_id),
+            transaction::to_wallet.eq(to_wallet_id),
+            transaction::amount.eq(&quote.from_amount),
+            transaction::currency.eq(&quote.from_currency),
+            transaction::to_amount.eq(&quote.to_amount),
+            transaction::to_currency.eq(&quote.to_currency),
+            transaction::fx_rate.eq(&quote.rate),
+            transaction::fx_quote_id.eq(quote.id),
+            transaction::kind.eq(TransactionKind::Conversion),
+        ))
+        .returning(Transaction::as_returning())
+        .get_result(conn)
+        .await?;
+
+    // the FX position account buys the source currency and sells the target one
+    let fx_position = Account::System(SystemAccount::FxPosition);
+    ledger::post_multi_currency_entry(
+        conn,
+        Some(transaction.id),
+        "Conversion",
+        &[
+            (
+                Account::Wallet(from_wallet_id),
+                &quote.from_currency,
+                -quote.from_amount.clone(),
+            ),
+            (fx_position, &quote.from_currency, quote.from_amount.clone()),
+            (fx_position, &quote.to_currency, -quote.to_amount.clone()),
+            (
+                Account::Wallet(to_wallet_id),
+                &quote.to_currency,
+                quote.to_amount.clone(),
+            ),
+        ],
+    )
+    .await?;
+
+    Ok(transaction)
+}
+
 impl SmplDB {
+    /// Pays `amount` from the user's wallet into `to_username`'s default wallet in its currency,
+    /// unless `risk` holds the transfer for review or blocks it
     pub async fn insert_payment(
         &self,
         from_user_id: i32,
+        from_wallet: &WalletRef,
         to_username: &str,
         amount: BigDecimal,
-    ) -> Result<Transaction, Error> {
+        risk: &dyn AssessRisk,
+        idempotency: Option<IdempotentRequest<'_, PaymentOutcome>>,
+    ) -> Result<PaymentOutcome, Error> {
         let mut conn = self.get_conn().await?;
         conn.transaction(|conn| {
             async move {
-                // Lock the from_wallet and to_wallet rows for update
-                let (from_wallet_id, from_wallet_balance): (i32, BigDecimal) = wallet::table
-                    .filter(wallet::user_id.eq(from_user_id))
-                    .select((wallet::id, wallet::balance))
-                    .for_update() // Lock the row for update
-                    .first(conn)
-                    .await?;
-
-                // get to_user_id
-                let to_user_id: i32 = users::table
-                    .filter(users::username.eq(to_username))
-                    .select(users::id)
-                    .first(conn)
-                    .await?;
-
-                let (to_wallet_id, to_wallet_balance): (i32, BigDecimal) = wallet::table
-                    .filter(wallet::id.eq(to_user_id))
-                    .select((wallet::id, wallet::balance))
-                    .for_update() // Lock the row for update
-                    .first(conn)
-                    .await?;
+                let source = TransferSource::Transfer;
+                let outcome = pay(
+                    conn,
+                    from_user_id,
+                    from_wallet,
+                    to_username,
+                    &amount,
+                    risk,
+                    source,
+                )
+                .await?;
+                complete_idempotent_request(conn, idempotency, &outcome).await?;
+                Ok(outcome)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
 
-                if from_wallet_balance < amount {
-                    return Err(diesel::result::Error::RollbackTransaction);
+    /// Moves `amount` between two of the user's open wallets in the same currency
+    pub async fn move_between_wallets(
+        &self,
+        user_id: i32,
+        from_wallet_id: i32,
+        to_wallet_id: i32,
+        amount: BigDecimal,
+        idempotency: Option<IdempotentRequest<'_, Transaction>>,
+    ) -> Result<Transaction, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                if from_wallet_id == to_wallet_id {
+                    return Err(Error::SelfTransfer);
                 }
+                lock_active_user(conn, user_id).await?;
 
-                let now = Utc::now();
-                // deduct from sender
-                diesel::update(wallet::table.find(from_wallet_id))
-                    .set((
-                        wallet::balance.eq(from_wallet_balance - &amount),
-                        wallet::updated_at.eq(now),
-                    ))
-                    .execute(conn)
-                    .await?;
+                // lock in id order so opposite moves can't deadlock
+                let mut ids = [from_wallet_id, to_wallet_id];
+                ids.sort_unstable();
+                let mut locked = Vec::with_capacity(2);
+                for id in ids {
+                    let wallet = WalletRef::Id { id, currency: None };
+                    locked.push(lock_wallet(conn, user_id, &wallet).await?);
+                }
+                let (from_wallet, to_wallet) = if locked[0].id == from_wallet_id {
+                    (&locked[0], &locked[1])
+                } else {
+                    (&locked[1], &locked[0])
+                };
 
-                // add to receiver
-                diesel::update(wallet::table.find(to_wallet_id))
-                    .set((
-                        wallet::balance.eq(to_wallet_balance + &amount),
-                        wallet::updated_at.eq(now),
-                    ))
-                    .execute(conn)
-                    .await?;
+                if from_wallet.currency != to_wallet.currency {
+                    return Err(Error::CurrencyMismatch);
+                }
+                if from_wallet.status != AccountStatus::Active
+                    || to_wallet.status != AccountStatus::Active
+                {
+                    return Err(Error::WalletFrozen);
+                }
+                let currency = &from_wallet.currency;
+                let amount = currency::scale_amount(conn, currency, &amount).await?;
+                if available_balance(conn, from_wallet).await? < amount {
+                    return Err(Error::InsufficientFunds);
+                }
 
-                // make transaction
-                diesel::insert_into(transaction::table)
+                let transaction: Transaction = diesel::insert_into(transaction::table)
                     .values((
-                        transaction::from_wallet.eq(from_wallet_id),
-                        transaction::to_wallet.eq(to_wallet_id),
+                        transaction::from_wallet.eq(from_wallet.id),
+                        transaction::to_wallet.eq(to_wallet.id),
                         transaction::amount.eq(&amount),
+                        transaction::currency.eq(currency),
+                        transaction::kind.eq(TransactionKind::Move),
                     ))
                     .returning(Transaction::as_returning())
                     .get_result(conn)
-                    .await
+                    .await?;
+
+                ledger::post_journal_entry(
+                    conn,
+                    Some(transaction.id),
+                    "Move between wallets",
+                    currency,
+                    &[
+                        (Account::Wallet(from_wallet.id), -amount.clone()),
+                        (Account::Wallet(to_wallet.id), amount),
+                    ],
+                )
+                .await?;
+
+                complete_idempotent_request(conn, idempotency, &transaction).await?;
+                Ok(transaction)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Converts the amount of the user's FX quote into the quote's target currency and credits
+    /// it to `to_username`'s default wallet in it, which can be the user themselves. The amount
+    /// is taken from `from_wallet_id`, or the user's default wallet in the quote's currency. The
+    /// quote is used up, even if `risk` holds a conversion to another user for review or blocks
+    /// it.
+    pub async fn insert_conversion(
+        &self,
+        from_user_id: i32,
+        quote_id: i32,
+        from_wallet_id: Option<i32>,
+        to_username: &str,
+        risk: &dyn AssessRisk,
+        idempotency: Option<IdempotentRequest<'_, PaymentOutcome>>,
+    ) -> Result<PaymentOutcome, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let quote = fx::use_quote(conn, from_user_id, quote_id).await?;
+                let transfer =
+                    check_conversion(conn, from_user_id, from_wallet_id, to_username, &quote)
+                        .await?;
+                // converting between the user's own wallets isn't a transfer to anyone
+                let held = if transfer.to_user_id != from_user_id {
+                    let source = TransferSource::Conversion(quote.id);
+                    assess_transfer(conn, &transfer, risk, source).await?
+                } else {
+                    None
+                };
+                let outcome = match held {
+                    Some(outcome) => outcome,
+                    None => PaymentOutcome::Paid(make_conversion(conn, transfer, &quote).await?),
+                };
+                complete_idempotent_request(conn, idempotency, &outcome).await?;
+                Ok(outcome)
             }
             .scope_boxed()
         })
         .await
-        .map_err(handle_duplicate_error)
+        .map_err(handle_transaction_error)
     }
 
+    /// Returns the transaction with the usernames of the sending and receiving wallets' owners,
+    /// if `user_id` is a party to it. Deposits have no sender and withdrawals no receiver.
     pub async fn get_transaction(
         &self,
         user_id: i32,
         transaction_id: i32,
-    ) -> Result<Option<(Transaction, String, String)>, Error> {
+    ) -> Result<Option<(Transaction, Option<String>, Option<String>)>, Error> {
         let mut conn = self.get_conn().await?;
 
-        let transaction: Transaction = transaction::table
+        let Some(transaction): Option<Transaction> = transaction::table
             .select(Transaction::as_select())
             .filter(transaction::id.eq(transaction_id))
             .get_result(&mut conn)
-            .await?;
-
-        let from_user: (i32, Option<String>) = wallet::table
-            .left_join(users::table.on(wallet::user_id.eq(users::id)))
-            .select((wallet::user_id, users::username.nullable()))
-            .filter(wallet::id.eq(transaction.from_wallet))
-            .get_result(&mut conn)
-            .await?;
+            .await
+            .optional()?
+        else {
+            return Ok(None);
+        };
 
-        let to_user: (i32, Option<String>) = wallet::table
-            .left_join(users::table.on(wallet::user_id.eq(users::id)))
-            .select((wallet::user_id, users::username.nullable()))
-            .filter(wallet::id.eq(transaction.to_wallet))
-            .get_result(&mut conn)
-            .await?;
+        let from_user = match transaction.from_wallet {
+            Some(wallet_id) => Some(wallet_owner(&mut conn, wallet_id).await?),
+            None => None,
+        };
+        let to_user = match transaction.to_wallet {
+            Some(wallet_id) => Some(wallet_owner(&mut conn, wallet_id).await?),
+            None => None,
+        };
 
-        if from_user.0 != user_id && to_user.0 != user_id {
+        let is_party = [&from_user, &to_user]
+            .into_iter()
+            .flatten()
+            .any(|(id, _)| *id == user_id);
+        if !is_party {
             return Ok(None);
         }
 
-        Ok(from_user
-            .1
-            .zip(to_user.1)
-            .map(|(from_user, to_user)| (transaction, from_user, to_user)))
+        Ok(Some((
+            transaction,
+            from_user.map(|(_, username)| username),
+            to_user.map(|(_, username)| username),
+        )))
     }
 
-    pub async fn list_transactions(&self, user_id: i32) -> Result<Vec<Transaction>, Error> {
+    /// Returns up to `limit` of the user's transactions matching `filter`, newest first, starting
+    /// after `cursor`
+    pub async fn list_transactions(
+        &self,
+        user_id: i32,
+        filter: &TransactionFilter,
+        cursor: Option<TransactionCursor>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, Error> {
         let mut conn = self.get_conn().await?;
+        let mut wallets_query = wallet::table
+            .filter(wallet::user_id.eq(user_id))
+            .select(wallet::id)
+            .into_boxed();
+        if let Some(wallet_id) = filter.wallet_id {
+            wallets_query = wallets_query.filter(wallet::id.eq(wallet_id));
+        }
+        let wallets: Vec<i32> = wallets_query
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
 
-        let wallet = self.get_wallet(user_id).await?;
-
-        transaction::table
+        let mut query = transaction::table
             .select(Transaction::as_select())
-            .filter(
+            .into_boxed();
+
+        query = match filter.direction {
+            Some(TransactionDirection::Sent) => {
+                query.filter(transaction::from_wallet.eq_any(wallets))
+            }
+            Some(TransactionDirection::Received) => {
+                query.filter(transaction::to_wallet.eq_any(wallets))
+            }
+            None => query.filter(
+                transaction::from_wallet
+                    .eq_any(wallets.clone())
+                    .or(transaction::to_wallet.eq_any(wallets)),
+            ),
+        };
+
+        if let Some(counterparty) = &filter.counterparty {
+            let counterparty_wallets: Vec<i32> = wallet::table
+                .inner_join(users::table.on(wallet::user_id.eq(users::id)))
+                .filter(users::username.eq(counterparty))
+                .select(wallet::id)
+                .load(&mut conn)
+                .await
+                .map_err(handle_duplicate_error)?;
+            query = query.filter(
                 transaction::from_wallet
-                    .eq(wallet.id)
-                    .or(transaction::to_wallet.eq(wallet.id)),
-            )
+                    .eq_any(counterparty_wallets.clone())
+                    .or(transaction::to_wallet.eq_any(counterparty_wallets)),
+            );
+        }
+        if let Some(currency) = &filter.currency {
+            query = query.filter(transaction::currency.eq(currency.clone()));
+        }
+        if let Some(min_amount) = &filter.min_amount {
+            query = query.filter(transaction::amount.ge(min_amount.clone()));
+        }
+        if let Some(max_amount) = &filter.max_amount {
+            query = query.filter(transaction::amount.le(max_amount.clone()));
+        }
+        if let Some(from) = filter.from {
+            query = query.filter(transaction::created_at.ge(from));
+        }
+        if let Some(to) = filter.to {
+            query = query.filter(transaction::created_at.lt(to));
+        }
+        if let Some(cursor) = cursor {
+            query = query.filter(
+                transaction::created_at
+                    .lt(cursor.created_at)
+                    .or(transaction::created_at
+                        .eq(cursor.created_at)
+                        .and(transaction::id.lt(cursor.id))),
+            );
+        }
+
+        query
+            .order((transaction::created_at.desc(), transaction::id.desc()))
+            .limit(limit)
             .get_results(&mut conn)
             .await
             .map_err(handle_duplicate_error)
     }
 }
+
+/// Returns the id and username of the user owning the wallet
+pub(super) async fn wallet_owner(
+    conn: &mut AsyncPgConnection,
+    wallet_id: i32,
+) -> Result<(i32, String), diesel::result::Error> {
+    wallet::table
+        .inner_join(users::table.on(wallet::user_id.eq(users::id)))
+        .select((wallet::user_id, users::username))
+        .filter(wallet::id.eq(wallet_id))
+        .get_result(conn)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips_in_microseconds() {
+        let created_at: DateTime<Utc> = "2026-10-18T09:15:30.123456Z".parse().unwrap();
+        let cursor = TransactionCursor { created_at, id: 42 };
+        let encoded = cursor.to_string();
+        assert_eq!(encoded, "1792314930123456.42");
+
+        let decoded: TransactionCursor = encoded.parse().unwrap();
+        assert_eq!(decoded.created_at, created_at);
+        assert_eq!(decoded.id, 42);
+    }
+
+    #[test]
+    fn cursor_rejects_malformed_input() {
+        for input in ["", "123", "abc.1", "123.abc", "123.", ".1", "1.2.3"] {
+            assert!(
+                input.parse::<TransactionCursor>().is_err(),
+                "{input:?} should not parse"
+            );
+        }
+    }
+}
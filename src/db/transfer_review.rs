@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, Utc};
+use diesel::{
+    dsl, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+
+use super::{
+    fx, handle_duplicate_error, handle_transaction_error, hold,
+    models::{ReviewStatus, Transaction, TransactionKind, TransferReview},
+    payment_request, scheduled_payment,
+    schema::{transaction, transfer_review, users, wallet},
+    transaction::{
+        check_conversion, check_transfer, make_conversion, make_transfer, CheckedTransfer,
+    },
+    Error, SmplDB, WalletRef,
+};
+
+/// What the risk engine knows about a transfer when scoring it
+#[derive(Debug)]
+pub struct TransferRisk {
+    /// In `currency`, the sender's wallet's
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub at: DateTime<Utc>,
+    /// When the sender signed up
+    pub account_created_at: Option<DateTime<Utc>>,
+    /// Whether the sender has paid the recipient before
+    pub known_recipient: bool,
+    /// When the sender's transfers in the last day were made
+    pub recent_transfers: Vec<DateTime<Utc>>,
+}
+
+/// What to do with a transfer after scoring it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskDecision {
+    Allow,
+    /// Hold it for an admin to approve or reject
+    Review,
+    Block,
+}
+
+/// The risk engine's verdict on a transfer
+#[derive(Debug)]
+pub struct RiskAssessment {
+    pub score: i32,
+    /// Rules that scored the transfer
+    pub rules: Vec<String>,
+    pub decision: RiskDecision,
+}
+
+/// Decides whether a transfer is made, held for review or blocked
+pub trait AssessRisk: Sync {
+    fn assess(&self, transfer: &TransferRisk) -> RiskAssessment;
+}
+
+/// What a transfer is made for, recorded on its review so approving or rejecting it can finish it
+#[derive(Debug, Clone, Copy)]
+pub(super) enum TransferSource {
+    Transfer,
+    /// A conversion at the rate of the quote with the id
+    Conversion(i32),
+    PaymentRequest(i32),
+    Hold(i32),
+    ScheduledPayment(i32),
+}
+
+/// Result of asking for a transfer
+#[derive(Debug)]
+pub enum PaymentOutcome {
+    Paid(Transaction),
+    /// Waiting for review, with the amount reserved in the sender's wallet
+    Held(TransferReviewWithUsers),
+    Blocked(TransferReview),
+}
+
+/// A transfer review with the usernames of the sender and the recipient
+pub type TransferReviewWithUsers = (TransferReview, String, String);
+
+/// Gathers what the risk engine scores a checked transfer on. Conversions into other users'
+/// wallets count as transfers.
+async fn transfer_risk(
+    conn: &mut AsyncPgConnection,
+    transfer: &CheckedTransfer,
+) -> Result<TransferRisk, Error> {
+    let now = Utc::now();
+    let account_created_at: Option<DateTime<Utc>> = users::table
+        .find(transfer.from_user_id)
+        .select(users::created_at)
+        .first(conn)
+        .await?;
+
+    let sender_wallets = wallet::table
+        .filter(wallet::user_id.eq(transfer.from_user_id))
+        .select(wallet::id.nullable());
+    let recipient_wallets = wallet::table
+        .filter(wallet::user_id.eq(transfer.to_user_id))
+        .select(wallet::id.nullable());
+    let transfer_kinds = [TransactionKind::Transfer, TransactionKind::Conversion];
+    let known_recipient: bool = diesel::select(dsl::exists(
+        transaction::table
+            .filter(transaction::from_wallet.eq_any(sender_wallets))
+            .filter(transaction::to_wallet.eq_any(recipient_wallets))
+            .filter(transaction::kind.eq_any(transfer_kinds)),
+    ))
+    .get_result(conn)
+    .await?;
+
+    let recent_transfers: Vec<DateTime<Utc>> = transaction::table
+        .filter(transaction::from_wallet.eq_any(sender_wallets))
+        .filter(transaction::kind.eq_any(transfer_kinds))
+        .filter(transaction::created_at.gt(now - Duration::days(1)))
+        .select(transaction::created_at)
+        .load(conn)
+        .await?;
+
+    Ok(TransferRisk {
+        amount: transfer.amount.clone(),
+        currency: transfer.from_wallet.currency.clone(),
+        at: now,
+        account_created_at,
+        known_recipient,
+        recent_transfers,
+    })
+}
+
+/// Scores a checked transfer, recording it for review if the risk engine holds or blocks it.
+/// Returns `None` if the transfer can be made.
+pub(super) async fn assess_transfer(
+    conn: &mut AsyncPgConnection,
+    transfer: &CheckedTransfer,
+    risk: &dyn AssessRisk,
+    source: TransferSource,
+) -> Result<Option<PaymentOutcome>, Error> {
+    let assessment = risk.assess(&transfer_risk(conn, transfer).await?);
+    let status = match assessment.decision {
+        RiskDecision::Allow => return Ok(None),
+        RiskDecision::Review => ReviewStatus::Pending,
+        RiskDecision::Block => ReviewStatus::Blocked,
+    };
+    let review = insert_review(conn, transfer, &assessment, status, source).await?;
+    Ok(Some(match status {
+        ReviewStatus::Pending => PaymentOutcome::Held(find_review(conn, None, review.id).await?),
+        _ => PaymentOutcome::Blocked(review),
+    }))
+}
+
+/// Records a transfer the risk engine held or blocked
+async fn insert_review(
+    conn: &mut AsyncPgConnection,
+    transfer: &CheckedTransfer,
+    assessment: &RiskAssessment,
+    status: ReviewStatus,
+    source: TransferSource,
+) -> Result<TransferReview, Error> {
+    let (mut fx_quote_id, mut payment_request_id, mut hold_id, mut scheduled_payment_id) =
+        (None, None, None, None);
+    match source {
+        TransferSource::Transfer => {}
+        TransferSource::Conversion(id) => fx_quote_id = Some(id),
+        TransferSource::PaymentRequest(id) => payment_request_id = Some(id),
+        TransferSource::Hold(id) => hold_id = Some(id),
+        TransferSource::ScheduledPayment(id) => scheduled_payment_id = Some(id),
+    }
+    Ok(diesel::insert_into(transfer_review::table)
+        .values((
+            transfer_review::user_id.eq(transfer.from_user_id),
+            transfer_review::wallet_id.eq(transfer.from_wallet.id),
+            transfer_review::recipient_id.eq(transfer.to_user_id),
+            transfer_review::amount.eq(&transfer.amount),
+            transfer_review::currency.eq(&transfer.from_wallet.currency),
+            transfer_review::score.eq(assessment.score),
+            transfer_review::rules.eq(&assessment.rules),
+            transfer_review::status.eq(status),
+            transfer_review::fx_quote_id.eq(fx_quote_id),
+            transfer_review::payment_request_id.eq(payment_request_id),
+            transfer_review::hold_id.eq(hold_id),
+            transfer_review::scheduled_payment_id.eq(scheduled_payment_id),
+        ))
+        .returning(TransferReview::as_returning())
+        .get_result(conn)
+        .await?)
+}
+
+/// Total of the wallet's transfers waiting for review
+pub(super) async fn pending_balance(
+    conn: &mut AsyncPgConnection,
+    wallet_id: i32,
+) -> Result<BigDecimal, diesel::result::Error> {
+    let pending: Option<BigDecimal> = transfer_review::table
+        .filter(transfer_review::wallet_id.eq(wallet_id))
+        .filter(transfer_review::status.eq(ReviewStatus::Pending))
+        .select(dsl::sum(transfer_review::amount))
+        .first(conn)
+        .await?;
+    Ok(pending.unwrap_or_default())
+}
+
+/// Totals of the transfers waiting for review from each of the wallets that has any
+pub(super) async fn pending_balances(
+    conn: &mut AsyncPgConnection,
+    wallet_ids: &[i32],
+) -> Result<HashMap<i32, BigDecimal>, diesel::result::Error> {
+    let pending: Vec<(i32, Option<BigDecimal>)> = transfer_review::table
+        .filter(transfer_review::wallet_id.eq_any(wallet_ids))
+        .filter(transfer_review::status.eq(ReviewStatus::Pending))
+        .group_by(transfer_review::wallet_id)
+        .select((
+            transfer_review::wallet_id,
+            dsl::sum(transfer_review::amount),
+        ))
+        .load(conn)
+        .await?;
+    Ok(pending
+        .into_iter()
+        .map(|(wallet_id, amount)| (wallet_id, amount.unwrap_or_default()))
+        .collect())
+}
+
+/// Locks a transfer review waiting for a decision for update
+async fn lock_pending_review(
+    conn: &mut AsyncPgConnection,
+    review_id: i32,
+) -> Result<TransferReview, Error> {
+    let review: TransferReview = transfer_review::table
+        .find(review_id)
+        .select(TransferReview::as_select())
+        .for_update()
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::TransferReviewNotFound)?;
+    if review.status != ReviewStatus::Pending {
+        return Err(Error::TransferReviewNotPending);
+    }
+    Ok(review)
+}
+
+/// Records an admin's decision on a pending review
+async fn decide(
+    conn: &mut AsyncPgConnection,
+    review_id: i32,
+    reviewer_id: i32,
+    status: ReviewStatus,
+    note: Option<&str>,
+) -> Result<(), Error> {
+    let now = Utc::now();
+    diesel::update(transfer_review::table.find(review_id))
+        .set((
+            transfer_review::status.eq(status),
+            transfer_review::reviewer_id.eq(reviewer_id),
+            transfer_review::note.eq(note),
+            transfer_review::reviewed_at.eq(now),
+            transfer_review::updated_at.eq(now),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Returns the review, if it is of one of `user_id`'s transfers or `user_id` is `None`
+async fn find_review(
+    conn: &mut AsyncPgConnection,
+    user_id: Option<i32>,
+    review_id: i32,
+) -> Result<TransferReviewWithUsers, Error> {
+    let (sender, recipient) = diesel::alias!(users as sender, users as recipient);
+    let mut query = transfer_review::table
+        .inner_join(sender.on(transfer_review::user_id.eq(sender.field(users::id))))
+        .inner_join(recipient.on(transfer_review::recipient_id.eq(recipient.field(users::id))))
+        .filter(transfer_review::id.eq(review_id))
+        .select((
+            TransferReview::as_select(),
+            sender.field(users::username),
+            recipient.field(users::username),
+        ))
+        .into_boxed();
+    if let Some(user_id) = user_id {
+        query = query.filter(transfer_review::user_id.eq(user_id));
+    }
+    query
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::TransferReviewNotFound)
+}
+
+/// Finishes the request, hold or scheduled payment a held transfer was made for, with the
+/// transfer if it was approved
+async fn resolve_source(
+    conn: &mut AsyncPgConnection,
+    review: &TransferReview,
+    transaction_id: Option<i32>,
+) -> Result<(), Error> {
+    if let Some(request_id) = review.payment_request_id {
+        payment_request::resolve_held_request(conn, request_id, transaction_id).await?;
+    }
+    if let Some(hold_id) = review.hold_id {
+        hold::resolve_held_capture(conn, hold_id, transaction_id).await?;
+    }
+    if let Some(schedule_id) = review.scheduled_payment_id {
+        scheduled_payment::resolve_held_schedule(conn, schedule_id, transaction_id).await?;
+    }
+    Ok(())
+}
+
+impl SmplDB {
+    /// Makes a held transfer, releasing the amount it reserved. A held conversion is made at its
+    /// quote's rate, even if the quote has expired since.
+    pub async fn approve_transfer_review(
+        &self,
+        reviewer_id: i32,
+        review_id: i32,
+        note: Option<&str>,
+    ) -> Result<TransferReview, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let review = lock_pending_review(conn, review_id).await?;
+                // decided first so the reserved amount no longer counts against the transfer
+                decide(conn, review.id, reviewer_id, ReviewStatus::Approved, note).await?;
+
+                let recipient: String = users::table
+                    .find(review.recipient_id)
+                    .select(users::username)
+                    .first(conn)
+                    .await?;
+                let transaction = match review.fx_quote_id {
+                    Some(quote_id) => {
+                        let quote = fx::get_quote(conn, quote_id).await?;
+                        let transfer = check_conversion(
+                            conn,
+                            review.user_id,
+                            Some(review.wallet_id),
+                            &recipient,
+                            &quote,
+                        )
+                        .await?;
+                        make_conversion(conn, transfer, &quote).await?
+                    }
+                    None => {
+                        let from_wallet = WalletRef::Id {
+                            id: review.wallet_id,
+                            currency: Some(review.currency.clone()),
+                        };
+                        let transfer = check_transfer(
+                            conn,
+                            review.user_id,
+                            &from_wallet,
+                            &recipient,
+                            &review.amount,
+                        )
+                        .await?;
+                        make_transfer(conn, transfer).await?
+                    }
+                };
+                resolve_source(conn, &review, Some(transaction.id)).await?;
+
+                Ok(diesel::update(transfer_review::table.find(review.id))
+                    .set(transfer_review::transaction_id.eq(transaction.id))
+                    .returning(TransferReview::as_returning())
+                    .get_result(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Cancels a held transfer, releasing the amount it reserved. A held payment request goes
+    /// back to pending, a held capture voids its hold and a held scheduled payment skips the
+    /// payment.
+    pub async fn reject_transfer_review(
+        &self,
+        reviewer_id: i32,
+        review_id: i32,
+        note: Option<&str>,
+    ) -> Result<TransferReview, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let review = lock_pending_review(conn, review_id).await?;
+                decide(conn, review.id, reviewer_id, ReviewStatus::Rejected, note).await?;
+                resolve_source(conn, &review, None).await?;
+                Ok(transfer_review::table
+                    .find(review.id)
+                    .select(TransferReview::as_select())
+                    .first(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Returns the review, if it is of one of `user_id`'s transfers or `user_id` is `None`
+    pub async fn get_transfer_review(
+        &self,
+        user_id: Option<i32>,
+        review_id: i32,
+    ) -> Result<TransferReviewWithUsers, Error> {
+        let mut conn = self.get_conn().await?;
+        find_review(&mut conn, user_id, review_id).await
+    }
+
+    /// Returns the user's held and blocked transfers, newest first
+    pub async fn list_transfer_reviews(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<TransferReviewWithUsers>, Error> {
+        let mut conn = self.get_conn().await?;
+        let (sender, recipient) = diesel::alias!(users as sender, users as recipient);
+        transfer_review::table
+            .inner_join(sender.on(transfer_review::user_id.eq(sender.field(users::id))))
+            .inner_join(recipient.on(transfer_review::recipient_id.eq(recipient.field(users::id))))
+            .filter(transfer_review::user_id.eq(user_id))
+            .select((
+                TransferReview::as_select(),
+                sender.field(users::username),
+                recipient.field(users::username),
+            ))
+            .order((
+                transfer_review::created_at.desc(),
+                transfer_review::id.desc(),
+            ))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Returns everyone's reviews in `status`, oldest first
+    pub async fn transfer_review_queue(
+        &self,
+        status: ReviewStatus,
+    ) -> Result<Vec<TransferReviewWithUsers>, Error> {
+        let mut conn = self.get_conn().await?;
+        let (sender, recipient) = diesel::alias!(users as sender, users as recipient);
+        transfer_review::table
+            .inner_join(sender.on(transfer_review::user_id.eq(sender.field(users::id))))
+            .inner_join(recipient.on(transfer_review::recipient_id.eq(recipient.field(users::id))))
+            .filter(transfer_review::status.eq(status))
+            .select((
+                TransferReview::as_select(),
+                sender.field(users::username),
+                recipient.field(users::username),
+            ))
+            .order((transfer_review::created_at, transfer_review::id))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+}
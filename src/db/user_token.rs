@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+use super::{
+    handle_duplicate_error,
+    schema::{user_token, users},
+    Error, SmplDB,
+};
+
+/// What a single-use user token can be redeemed for
+#[derive(Debug, Clone, Copy)]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::EmailVerification => "email_verification",
+        }
+    }
+}
+
+impl SmplDB {
+    pub async fn create_user_token(
+        &self,
+        user_id: i32,
+        purpose: TokenPurpose,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        diesel::insert_into(user_token::table)
+            .values((
+                user_token::user_id.eq(user_id),
+                user_token::purpose.eq(purpose.as_str()),
+                user_token::token_hash.eq(token_hash),
+                user_token::expires_at.eq(expires_at),
+            ))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
+
+    /// Redeems an unused, unexpired password reset token and sets the user's password. Every
+    /// other outstanding reset token of the user is used up too. Returns the user id, or `None`
+    /// if the token is not valid.
+    pub async fn reset_password(
+        &self,
+        token_hash: &str,
+        password: &str,
+    ) -> Result<Option<i32>, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let now = Utc::now();
+                let purpose = TokenPurpose::PasswordReset.as_str();
+                let user_id: Option<i32> = diesel::update(user_token::table)
+                    .filter(user_token::token_hash.eq(token_hash))
+                    .filter(user_token::purpose.eq(purpose))
+                    .filter(user_token::used_at.is_null())
+                    .filter(user_token::expires_at.gt(now))
+                    .set(user_token::used_at.eq(now))
+                    .returning(user_token::user_id)
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                let Some(user_id) = user_id else {
+                    return Ok(None);
+                };
+
+                diesel::update(user_token::table)
+                    .filter(user_token::user_id.eq(user_id))
+                    .filter(user_token::purpose.eq(purpose))
+                    .filter(user_token::used_at.is_null())
+                    .set(user_token::used_at.eq(now))
+                    .execute(conn)
+                    .await?;
+
+                diesel::update(users::table.find(user_id))
+                    .set((users::password.eq(password), users::updated_at.eq(now)))
+                    .execute(conn)
+                    .await?;
+
+                Ok(Some(user_id))
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+
+    /// Redeems an unused, unexpired email verification token and marks the user's email as
+    /// verified, using up the user's other verification tokens. Returns the user id, or `None` if
+    /// the token is not valid.
+    pub async fn verify_email(&self, token_hash: &str) -> Result<Option<i32>, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let now = Utc::now();
+                let purpose = TokenPurpose::EmailVerification.as_str();
+                let user_id: Option<i32> = diesel::update(user_token::table)
+                    .filter(user_token::token_hash.eq(token_hash))
+                    .filter(user_token::purpose.eq(purpose))
+                    .filter(user_token::used_at.is_null())
+                    .filter(user_token::expires_at.gt(now))
+                    .set(user_token::used_at.eq(now))
+                    .returning(user_token::user_id)
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                let Some(user_id) = user_id else {
+                    return Ok(None);
+                };
+
+                diesel::update(user_token::table)
+                    .filter(user_token::user_id.eq(user_id))
+                    .filter(user_token::purpose.eq(purpose))
+                    .filter(user_token::used_at.is_null())
+                    .set(user_token::used_at.eq(now))
+                    .execute(conn)
+                    .await?;
+
+                diesel::update(users::table.find(user_id))
+                    .filter(users::email_verified_at.is_null())
+                    .set((users::email_verified_at.eq(now), users::updated_at.eq(now)))
+                    .execute(conn)
+                    .await?;
+
+                Ok(Some(user_id))
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_duplicate_error)
+    }
+}
@@ -1,8 +1,16 @@
 use chrono::Utc;
-use diesel::{ExpressionMethods, Insertable, OptionalExtension, QueryDsl, SelectableHelper};
-use diesel_async::RunQueryDsl;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, Insertable, OptionalExtension,
+    PgTextExpressionMethods, QueryDsl, SelectableHelper,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 
-use super::{handle_duplicate_error, models::User, schema::users, Error, SmplDB};
+use super::{
+    handle_duplicate_error,
+    models::{AccountStatus, User},
+    schema::users,
+    Error, SmplDB,
+};
 
 #[derive(Insertable)]
 #[diesel(table_name = users)]
@@ -10,7 +18,54 @@ struct NewUser<'a> {
     pub username: &'a str,
     pub email: &'a str,
     pub password: &'a str,
-    pub status: bool,
+    pub status: AccountStatus,
+    pub legal_name: Option<&'a str>,
+}
+
+/// Checks that the user can send or receive money, holding a share lock on their row so they
+/// can't be frozen until the transaction ends
+pub(super) async fn lock_active_user(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+) -> Result<(), Error> {
+    let status: AccountStatus = users::table
+        .find(user_id)
+        .select(users::status)
+        .for_share()
+        .first(conn)
+        .await?;
+    match status {
+        AccountStatus::Active => Ok(()),
+        AccountStatus::Frozen => Err(Error::AccountFrozen),
+        AccountStatus::Closed => Err(Error::AccountClosed),
+    }
+}
+
+/// Checks that a user with `status` can be sent money
+pub(super) fn check_recipient_status(status: AccountStatus) -> Result<(), Error> {
+    match status {
+        AccountStatus::Active => Ok(()),
+        AccountStatus::Frozen => Err(Error::RecipientFrozen),
+        AccountStatus::Closed => Err(Error::RecipientNotFound),
+    }
+}
+
+/// Returns the id of the user with the username, checking that they can be sent money and holding
+/// a share lock on their row so they can't be frozen until the transaction ends
+pub(super) async fn lock_recipient(
+    conn: &mut AsyncPgConnection,
+    username: &str,
+) -> Result<i32, Error> {
+    let (id, status): (i32, AccountStatus) = users::table
+        .filter(users::username.eq(username))
+        .select((users::id, users::status))
+        .for_share()
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::RecipientNotFound)?;
+    check_recipient_status(status)?;
+    Ok(id)
 }
 
 impl SmplDB {
@@ -19,12 +74,14 @@ impl SmplDB {
         username: &str,
         email: &str,
         password: &str,
+        legal_name: Option<&str>,
     ) -> Result<User, Error> {
         let user = NewUser {
             username,
             email,
             password,
-            status: true,
+            status: AccountStatus::Active,
+            legal_name,
         };
 
         let mut conn = self.get_conn().await?;
@@ -58,6 +115,57 @@ impl SmplDB {
             .map_err(handle_duplicate_error)
     }
 
+    pub async fn get_user_status(&self, id: i32) -> Result<Option<AccountStatus>, Error> {
+        let mut conn = self.get_conn().await?;
+        users::table
+            .find(id)
+            .select(users::status)
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)
+    }
+
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error> {
+        let mut conn = self.get_conn().await?;
+        users::table
+            .select(User::as_select())
+            .filter(users::username.eq(username))
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(handle_duplicate_error)
+    }
+
+    /// Returns users whose username or email contains `query`, ignoring case, oldest first
+    pub async fn search_users(
+        &self,
+        query: &str,
+        after_id: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<User>, Error> {
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = format!("%{escaped}%");
+
+        let mut conn = self.get_conn().await?;
+        users::table
+            .select(User::as_select())
+            .filter(
+                users::username
+                    .ilike(&pattern)
+                    .or(users::email.ilike(&pattern)),
+            )
+            .filter(users::id.gt(after_id.unwrap_or(0)))
+            .order(users::id.asc())
+            .limit(limit)
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
+    }
+
     pub async fn update_username(&self, id: i32, username: &str) -> Result<Option<User>, Error> {
         let mut conn = self.get_conn().await?;
         let now = Utc::now();
@@ -73,4 +181,19 @@ impl SmplDB {
             .optional()
             .map_err(handle_duplicate_error)
     }
+
+    pub async fn update_password(&self, id: i32, password: &str) -> Result<(), Error> {
+        let mut conn = self.get_conn().await?;
+        let now = Utc::now();
+        diesel::update(users::table)
+            .filter(users::id.eq(id))
+            .set((
+                users::password.eq(password),
+                users::updated_at.eq(Some(now)),
+            ))
+            .execute(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)?;
+        Ok(())
+    }
 }
@@ -1,53 +1,274 @@
-use bigdecimal::{BigDecimal, FromPrimitive};
+use bigdecimal::{BigDecimal, FromPrimitive, Zero};
 use chrono::Utc;
-use diesel::{prelude::AsChangeset, ExpressionMethods, Insertable, QueryDsl, SelectableHelper};
-use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use diesel::{
+    pg::Pg, prelude::AsChangeset, ExpressionMethods, Insertable, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
 
-use super::{handle_duplicate_error, models::Wallet, schema::wallet, Error, SmplDB};
+use super::{
+    currency, handle_duplicate_error, handle_transaction_error,
+    hold::{available_balance, held_balance},
+    idempotency::{complete_idempotent_request, IdempotentRequest},
+    ledger::{self, Account, SystemAccount},
+    models::{AccountStatus, Transaction, TransactionKind, Wallet},
+    schema::{transaction, wallet},
+    screening::has_confirmed_hit,
+    spending_limit::check_spending_limits,
+    users::lock_active_user,
+    Error, SmplDB,
+};
+
+/// A wallet with the total of its holds and transfers waiting for review
+pub type WalletWithHeld = (Wallet, BigDecimal);
+
+/// Which of a user's open wallets to use
+#[derive(Debug, Clone)]
+pub enum WalletRef {
+    /// The wallet with the id. When `currency` is given the wallet must be in it.
+    Id { id: i32, currency: Option<String> },
+    /// The user's default wallet in the currency
+    Default(String),
+}
+
+impl WalletRef {
+    fn query(&self, user_id: i32) -> wallet::BoxedQuery<'static, Pg> {
+        let query = wallet::table
+            .filter(wallet::user_id.eq(user_id))
+            .filter(wallet::closed_at.is_null())
+            .into_boxed();
+        match self {
+            WalletRef::Id { id, .. } => query.filter(wallet::id.eq(*id)),
+            WalletRef::Default(currency) => query
+                .filter(wallet::currency.eq(currency.clone()))
+                .filter(wallet::is_default),
+        }
+    }
+
+    fn check_currency(&self, wallet: &Wallet) -> Result<(), Error> {
+        match self {
+            WalletRef::Id {
+                currency: Some(currency),
+                ..
+            } if *currency != wallet.currency => Err(Error::CurrencyMismatch),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Which way a manual adjustment moves money
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentDirection {
+    /// Into the wallet
+    Credit,
+    /// Out of the wallet
+    Debit,
+}
+
+/// Locks the user's open wallet for update
+pub(super) async fn lock_wallet(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    wallet: &WalletRef,
+) -> Result<Wallet, Error> {
+    // locking clauses can't be boxed
+    let query = wallet::table
+        .filter(wallet::user_id.eq(user_id))
+        .filter(wallet::closed_at.is_null())
+        .select(Wallet::as_select())
+        .for_update();
+    let found = match wallet {
+        WalletRef::Id { id, .. } => query.filter(wallet::id.eq(*id)).first(conn).await,
+        WalletRef::Default(currency) => {
+            query
+                .filter(wallet::currency.eq(currency))
+                .filter(wallet::is_default)
+                .first(conn)
+                .await
+        }
+    }
+    .optional()?
+    .ok_or(Error::WalletNotFound)?;
+    wallet.check_currency(&found)?;
+    Ok(found)
+}
+
+/// Whether the user has a default wallet in the currency
+pub(super) async fn has_default_wallet(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    currency: &str,
+) -> Result<bool, diesel::result::Error> {
+    diesel::select(diesel::dsl::exists(
+        WalletRef::Default(currency.to_string()).query(user_id),
+    ))
+    .get_result(conn)
+    .await
+}
+
+/// Returns the user's open wallet without locking it
+pub(super) async fn find_wallet(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    wallet: &WalletRef,
+) -> Result<Wallet, Error> {
+    let found = wallet
+        .query(user_id)
+        .select(Wallet::as_select())
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(Error::WalletNotFound)?;
+    wallet.check_currency(&found)?;
+    Ok(found)
+}
 
 #[derive(Insertable, AsChangeset)]
 #[diesel(table_name = wallet)]
-pub struct NewWallet {
+pub struct NewWallet<'a> {
     user_id: i32,
+    name: &'a str,
+    currency: &'a str,
+    is_default: bool,
     balance: BigDecimal,
-    status: bool,
+    status: AccountStatus,
 }
 
 impl SmplDB {
-    pub async fn create_wallet(&self, user_id: i32) -> Result<Wallet, Error> {
-        let wallet = NewWallet {
-            user_id,
-            balance: BigDecimal::from_u8(0).expect("couldn't create BigDecimal 0"),
-            status: true,
-        };
+    /// Opens a named wallet in `currency` for the user. The user's first open wallet in a currency
+    /// becomes their default wallet in it.
+    pub async fn create_wallet(
+        &self,
+        user_id: i32,
+        currency: &str,
+        name: &str,
+    ) -> Result<Wallet, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let minor_units = currency::minor_units(conn, currency).await?;
+                let has_default = has_default_wallet(conn, user_id, currency).await?;
+                let wallet = NewWallet {
+                    user_id,
+                    name,
+                    currency,
+                    is_default: !has_default,
+                    balance: BigDecimal::from_u8(0)
+                        .expect("couldn't create BigDecimal 0")
+                        .with_scale(minor_units.into()),
+                    status: AccountStatus::Active,
+                };
+
+                let wallet = diesel::insert_into(wallet::table)
+                    .values(&wallet)
+                    .returning(Wallet::as_returning())
+                    .get_result(conn)
+                    .await
+                    .map_err(handle_duplicate_error)?;
+
+                ledger::open_wallet_account(conn, wallet.id, currency).await?;
+                Ok(wallet)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
 
+    /// Returns [`Error::Duplicate`] if the user has another open wallet with the name
+    pub async fn rename_wallet(
+        &self,
+        user_id: i32,
+        wallet_id: i32,
+        name: &str,
+    ) -> Result<Wallet, Error> {
         let mut conn = self.get_conn().await?;
-        diesel::insert_into(wallet::table)
-            .values(&wallet)
+        diesel::update(wallet::table)
+            .filter(wallet::id.eq(wallet_id))
+            .filter(wallet::user_id.eq(user_id))
+            .filter(wallet::closed_at.is_null())
+            .set(wallet::name.eq(name))
             .returning(Wallet::as_returning())
             .get_result(&mut conn)
             .await
-            .map_err(handle_duplicate_error)
+            .optional()
+            .map_err(handle_duplicate_error)?
+            .ok_or(Error::WalletNotFound)
     }
 
-    pub async fn deposit(&self, user_id: i32, amount: BigDecimal) -> Result<Wallet, Error> {
+    /// Makes the wallet the user's default wallet in its currency, receiving their transfers
+    pub async fn set_default_wallet(&self, user_id: i32, wallet_id: i32) -> Result<Wallet, Error> {
         let mut conn = self.get_conn().await?;
         conn.transaction(|conn| {
             async move {
-                // Lock the wallet row for update
-                let (id, balance): (i32, BigDecimal) = wallet::table
+                let wallet = WalletRef::Id {
+                    id: wallet_id,
+                    currency: None,
+                };
+                let found = lock_wallet(conn, user_id, &wallet).await?;
+                if found.is_default {
+                    return Ok(found);
+                }
+
+                diesel::update(wallet::table)
                     .filter(wallet::user_id.eq(user_id))
-                    .select((wallet::id, wallet::balance))
-                    .for_update() // Lock the row
-                    .first(conn)
+                    .filter(wallet::currency.eq(&found.currency))
+                    .filter(wallet::is_default)
+                    .set(wallet::is_default.eq(false))
+                    .execute(conn)
                     .await?;
+                Ok(diesel::update(wallet::table.find(found.id))
+                    .set(wallet::is_default.eq(true))
+                    .returning(Wallet::as_returning())
+                    .get_result(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    /// Closes an empty wallet. A default wallet can only be closed when it is the user's last open
+    /// wallet in its currency.
+    pub async fn close_wallet(&self, user_id: i32, wallet_id: i32) -> Result<Wallet, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                let wallet = WalletRef::Id {
+                    id: wallet_id,
+                    currency: None,
+                };
+                let found = lock_wallet(conn, user_id, &wallet).await?;
+                if found.status != AccountStatus::Active {
+                    return Err(Error::WalletFrozen);
+                }
+                if !found.balance.is_zero() {
+                    return Err(Error::WalletNotEmpty);
+                }
+                if found.is_default {
+                    let others: i64 = wallet::table
+                        .filter(wallet::user_id.eq(user_id))
+                        .filter(wallet::currency.eq(&found.currency))
+                        .filter(wallet::closed_at.is_null())
+                        .filter(wallet::id.ne(found.id))
+                        .count()
+                        .get_result(conn)
+                        .await?;
+                    if others > 0 {
+                        return Err(Error::DefaultWalletInUse);
+                    }
+                }
 
-                // Update the balance
-                let now = Utc::now();
-                Ok(diesel::update(wallet::table.find(id))
+                Ok(diesel::update(wallet::table.find(found.id))
                     .set((
-                        wallet::balance.eq(balance + amount),
-                        wallet::updated_at.eq(now),
+                        wallet::status.eq(AccountStatus::Closed),
+                        wallet::closed_at.eq(Utc::now()),
+                        wallet::is_default.eq(false),
                     ))
                     .returning(Wallet::as_returning())
                     .get_result(conn)
@@ -56,55 +277,269 @@ impl SmplDB {
             .scope_boxed()
         })
         .await
+        .map_err(handle_transaction_error)
     }
 
-    pub async fn withdraw(&self, user_id: i32, amount: BigDecimal) -> Result<Wallet, Error> {
+    /// Freezes or unfreezes any user's wallet. Wallets are closed by their owner.
+    pub async fn set_wallet_status(
+        &self,
+        wallet_id: i32,
+        status: AccountStatus,
+    ) -> Result<Wallet, Error> {
         let mut conn = self.get_conn().await?;
         conn.transaction(|conn| {
             async move {
-                // Lock the wallet row for update
-                let (id, balance): (i32, BigDecimal) = wallet::table
-                    .filter(wallet::user_id.eq(user_id))
-                    .select((wallet::id, wallet::balance))
-                    .for_update() // Lock the row
+                let current: AccountStatus = wallet::table
+                    .find(wallet_id)
+                    .select(wallet::status)
+                    .for_update()
                     .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(Error::WalletNotFound)?;
+                if status == AccountStatus::Closed || !current.can_become(status) {
+                    return Err(Error::InvalidStatusTransition);
+                }
+
+                Ok(diesel::update(wallet::table.find(wallet_id))
+                    .set(wallet::status.eq(status))
+                    .returning(Wallet::as_returning())
+                    .get_result(conn)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
+
+    pub async fn deposit(
+        &self,
+        user_id: i32,
+        wallet: &WalletRef,
+        amount: BigDecimal,
+        idempotency: Option<IdempotentRequest<'_, WalletWithHeld>>,
+    ) -> Result<WalletWithHeld, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                lock_active_user(conn, user_id).await?;
+                // Lock the wallet row for update
+                let Wallet {
+                    id,
+                    currency,
+                    status,
+                    ..
+                } = lock_wallet(conn, user_id, wallet).await?;
+                let amount = currency::scale_amount(conn, &currency, &amount).await?;
+
+                if status != AccountStatus::Active {
+                    return Err(Error::WalletFrozen);
+                }
+
+                let transaction_id: i32 = diesel::insert_into(transaction::table)
+                    .values((
+                        transaction::to_wallet.eq(id),
+                        transaction::amount.eq(&amount),
+                        transaction::currency.eq(&currency),
+                        transaction::kind.eq(TransactionKind::Deposit),
+                    ))
+                    .returning(transaction::id)
+                    .get_result(conn)
+                    .await?;
+
+                ledger::post_journal_entry(
+                    conn,
+                    Some(transaction_id),
+                    "Deposit",
+                    &currency,
+                    &[
+                        (Account::Wallet(id), amount.clone()),
+                        (Account::System(SystemAccount::ExternalCashIn), -amount),
+                    ],
+                )
+                .await?;
+
+                let wallet: Wallet = wallet::table
+                    .find(id)
+                    .select(Wallet::as_select())
+                    .get_result(conn)
                     .await?;
+                let updated = (wallet, held_balance(conn, id).await?);
+
+                complete_idempotent_request(conn, idempotency, &updated).await?;
+                Ok(updated)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(handle_transaction_error)
+    }
 
-                if balance < amount {
-                    return Err(diesel::result::Error::RollbackTransaction);
+    pub async fn withdraw(
+        &self,
+        user_id: i32,
+        wallet: &WalletRef,
+        amount: BigDecimal,
+        idempotency: Option<IdempotentRequest<'_, WalletWithHeld>>,
+    ) -> Result<WalletWithHeld, Error> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(|conn| {
+            async move {
+                lock_active_user(conn, user_id).await?;
+                // Lock the wallet row for update
+                let found = lock_wallet(conn, user_id, wallet).await?;
+                let amount = currency::scale_amount(conn, &found.currency, &amount).await?;
+
+                if has_confirmed_hit(conn, &[user_id]).await? {
+                    return Err(Error::ScreeningMatch);
+                }
+                if found.status != AccountStatus::Active {
+                    return Err(Error::WalletFrozen);
                 }
+                if available_balance(conn, &found).await? < amount {
+                    return Err(Error::InsufficientFunds);
+                }
+                check_spending_limits(conn, user_id, &found.currency, &amount).await?;
 
-                // Update the balance
-                let now = Utc::now();
-                diesel::update(wallet::table.find(id))
-                    .set((
-                        wallet::balance.eq(balance - amount),
-                        wallet::updated_at.eq(now),
+                let transaction_id: i32 = diesel::insert_into(transaction::table)
+                    .values((
+                        transaction::from_wallet.eq(found.id),
+                        transaction::amount.eq(&amount),
+                        transaction::currency.eq(&found.currency),
+                        transaction::kind.eq(TransactionKind::Withdrawal),
                     ))
-                    .returning(Wallet::as_returning())
+                    .returning(transaction::id)
                     .get_result(conn)
-                    .await
+                    .await?;
+
+                ledger::post_journal_entry(
+                    conn,
+                    Some(transaction_id),
+                    "Withdrawal",
+                    &found.currency,
+                    &[
+                        (Account::Wallet(found.id), -amount.clone()),
+                        (Account::System(SystemAccount::ExternalCashOut), amount),
+                    ],
+                )
+                .await?;
+
+                let wallet: Wallet = wallet::table
+                    .find(found.id)
+                    .select(Wallet::as_select())
+                    .get_result(conn)
+                    .await?;
+                let updated = (wallet, held_balance(conn, found.id).await?);
+
+                complete_idempotent_request(conn, idempotency, &updated).await?;
+                Ok(updated)
             }
             .scope_boxed()
         })
         .await
-        .map_err(handle_duplicate_error)
+        .map_err(handle_transaction_error)
     }
 
-    pub async fn get_wallet(&self, user_id: i32) -> Result<Wallet, Error> {
+    /// Credits or debits any user's open wallet, frozen or not, against the `manual_adjustment`
+    /// system account. The admin and their reason are recorded on the transaction.
+    pub async fn adjust_balance(
+        &self,
+        admin_id: i32,
+        wallet_id: i32,
+        direction: AdjustmentDirection,
+        amount: BigDecimal,
+        reason: &str,
+        idempotency: Option<IdempotentRequest<'_, Transaction>>,
+    ) -> Result<Transaction, Error> {
         let mut conn = self.get_conn().await?;
         conn.transaction(|conn| {
             async move {
-                // Lock the wallet row for reading
-                Ok(wallet::table
-                    .filter(wallet::user_id.eq(user_id))
+                // Lock the wallet row for update
+                let adjusted: Wallet = wallet::table
+                    .find(wallet_id)
+                    .filter(wallet::closed_at.is_null())
                     .select(Wallet::as_select())
-                    .for_no_key_update() // Lock the row for reading
+                    .for_update()
                     .first(conn)
-                    .await?)
+                    .await
+                    .optional()?
+                    .ok_or(Error::WalletNotFound)?;
+                let amount = currency::scale_amount(conn, &adjusted.currency, &amount).await?;
+
+                let (from_wallet, to_wallet, change) = match direction {
+                    AdjustmentDirection::Credit => (None, Some(adjusted.id), amount.clone()),
+                    AdjustmentDirection::Debit => {
+                        // held funds stay reserved for the hold or the transfer under review
+                        if available_balance(conn, &adjusted).await? < amount {
+                            return Err(Error::InsufficientFunds);
+                        }
+                        (Some(adjusted.id), None, -amount.clone())
+                    }
+                };
+
+                let transaction: Transaction = diesel::insert_into(transaction::table)
+                    .values((
+                        transaction::from_wallet.eq(from_wallet),
+                        transaction::to_wallet.eq(to_wallet),
+                        transaction::amount.eq(&amount),
+                        transaction::currency.eq(&adjusted.currency),
+                        transaction::kind.eq(TransactionKind::Adjustment),
+                        transaction::admin_id.eq(admin_id),
+                        transaction::reason.eq(reason),
+                    ))
+                    .returning(Transaction::as_returning())
+                    .get_result(conn)
+                    .await?;
+
+                ledger::post_journal_entry(
+                    conn,
+                    Some(transaction.id),
+                    "Manual adjustment",
+                    &adjusted.currency,
+                    &[
+                        (Account::Wallet(adjusted.id), change.clone()),
+                        (Account::System(SystemAccount::ManualAdjustment), -change),
+                    ],
+                )
+                .await?;
+
+                complete_idempotent_request(conn, idempotency, &transaction).await?;
+                Ok(transaction)
             }
             .scope_boxed()
         })
         .await
+        .map_err(handle_transaction_error)
+    }
+
+    pub async fn get_wallet(&self, user_id: i32, wallet: &WalletRef) -> Result<Wallet, Error> {
+        let mut conn = self.get_conn().await?;
+        find_wallet(&mut conn, user_id, wallet).await
+    }
+
+    /// Returns any user's wallet, open or closed
+    pub async fn get_wallet_by_id(&self, wallet_id: i32) -> Result<Wallet, Error> {
+        let mut conn = self.get_conn().await?;
+        wallet::table
+            .find(wallet_id)
+            .select(Wallet::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?
+            .ok_or(Error::WalletNotFound)
+    }
+
+    /// Returns the user's open wallets, ordered by currency
+    pub async fn list_wallets(&self, user_id: i32) -> Result<Vec<Wallet>, Error> {
+        let mut conn = self.get_conn().await?;
+        wallet::table
+            .filter(wallet::user_id.eq(user_id))
+            .filter(wallet::closed_at.is_null())
+            .select(Wallet::as_select())
+            .order((wallet::currency.asc(), wallet::id.asc()))
+            .load(&mut conn)
+            .await
+            .map_err(handle_duplicate_error)
     }
 }
@@ -0,0 +1,508 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Duration;
+use serde::Serialize;
+
+use crate::{db, permission::Permission};
+
+/// Error returned by handlers, rendered as an RFC 7807 `application/problem+json` body.
+///
+/// Every variant has a stable `code` that clients can match on. The `title` is for humans and may
+/// change.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// The body, query string or path could not be parsed
+    #[error("Malformed request")]
+    MalformedRequest { status: StatusCode, detail: String },
+    #[error("Invalid email address")]
+    InvalidEmail,
+    #[error("Username cannot be empty")]
+    EmptyUsername,
+    #[error("Password cannot be empty")]
+    EmptyPassword,
+    #[error("Amount must be greater than zero")]
+    InvalidAmount,
+    #[error("limit must be between 1 and 100")]
+    InvalidLimit,
+    #[error("Invalid cursor")]
+    InvalidCursor,
+    #[error("Invalid `{0}` header")]
+    InvalidHeader(&'static str),
+    /// A password reset or email verification token
+    #[error("Invalid or expired token")]
+    InvalidOrExpiredToken,
+    #[error("Currency is not supported")]
+    UnsupportedCurrency,
+    #[error("Amount has more than {minor_units} decimal places for the currency")]
+    InvalidPrecision { minor_units: i16 },
+    #[error("Wallet name must be 1 to 60 characters")]
+    InvalidWalletName,
+    #[error("Legal name must be at most 255 characters")]
+    InvalidLegalName,
+    #[error(
+        "Rate must be greater than zero, spread between 0 and 1, and the currencies different"
+    )]
+    InvalidFxRate,
+    #[error("Reason cannot be empty")]
+    EmptyReason,
+    #[error("Note must be at most 255 characters")]
+    InvalidNote,
+    #[error("Invalid cron pattern")]
+    InvalidCronPattern,
+    #[error("Schedule must start in the future and end after its first payment")]
+    InvalidSchedule,
+    #[error("Spending limits must be greater than zero, and counts at least zero")]
+    InvalidSpendingLimit,
+
+    #[error("`Authorization` header is missing")]
+    MissingAuthorization,
+    #[error("Invalid token")]
+    InvalidToken,
+    #[error("Token expired")]
+    TokenExpired,
+    #[error("Token revoked")]
+    TokenRevoked,
+    #[error("Incorrect email or password")]
+    InvalidCredentials,
+    #[error("Invalid refresh token")]
+    InvalidRefreshToken,
+    #[error("Refresh token expired")]
+    RefreshTokenExpired,
+    #[error("Refresh token already used")]
+    RefreshTokenReused,
+    #[error("Invalid MFA token")]
+    InvalidMfaToken,
+    #[error("MFA token expired")]
+    MfaTokenExpired,
+    #[error("Invalid two-factor code")]
+    InvalidMfaCode,
+    #[error("`X-TOTP-Code` header is required")]
+    MfaCodeRequired,
+
+    #[error("Missing the `{}` permission", .0.as_str())]
+    MissingPermission(Permission),
+    #[error("Email not verified")]
+    EmailNotVerified,
+    #[error("Account is frozen")]
+    AccountFrozen,
+    #[error("Account is closed")]
+    AccountClosed,
+    #[error("Two-factor authentication must be enabled to send this amount")]
+    MfaEnrolmentRequired,
+    #[error("Blocked by sanctions screening")]
+    ScreeningMatch,
+
+    #[error("Transaction not found")]
+    TransactionNotFound,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Recipient not found")]
+    RecipientNotFound,
+    #[error("Wallet not found")]
+    WalletNotFound,
+    #[error("No exchange rate for this currency pair")]
+    FxRateNotFound,
+    #[error("FX quote not found")]
+    FxQuoteNotFound,
+    #[error("Payment request not found")]
+    PaymentRequestNotFound,
+    #[error("Scheduled payment not found")]
+    ScheduledPaymentNotFound,
+    #[error("Hold not found")]
+    HoldNotFound,
+    #[error("Transfer review not found")]
+    TransferReviewNotFound,
+    #[error("Screening hit not found")]
+    ScreeningHitNotFound,
+
+    #[error("Username taken")]
+    UsernameTaken,
+    #[error("Username or email taken")]
+    UsernameOrEmailTaken,
+    #[error("A wallet with this name already exists")]
+    WalletAlreadyExists,
+    #[error("Email already verified")]
+    EmailAlreadyVerified,
+    #[error("Two-factor authentication already enabled")]
+    MfaAlreadyEnabled,
+    #[error("No two-factor enrolment to confirm")]
+    NoPendingMfaEnrolment,
+    #[error("Status can't change to the requested one")]
+    InvalidStatusTransition,
+    #[error("Payment request is no longer pending")]
+    PaymentRequestNotPending,
+    #[error("Hold is no longer active")]
+    HoldNotActive,
+    #[error("Transfer was already reviewed")]
+    TransferReviewNotPending,
+    #[error("Idempotency-Key was already used for a different request")]
+    IdempotencyKeyReused,
+    #[error("A request with this Idempotency-Key is still being processed")]
+    IdempotencyKeyInProgress,
+
+    #[error("Insufficient funds")]
+    InsufficientFunds,
+    #[error("Recipient has not verified their email")]
+    RecipientNotVerified,
+    #[error("Cannot send money to yourself")]
+    SelfTransfer,
+    #[error("Wallet is frozen")]
+    WalletFrozen,
+    #[error("Recipient's account is frozen")]
+    RecipientFrozen,
+    #[error("Recipient's wallet is frozen")]
+    RecipientWalletFrozen,
+    #[error("Recipient has no wallet in this currency")]
+    RecipientWalletNotFound,
+    #[error("Wallet is in a different currency")]
+    CurrencyMismatch,
+    #[error("Wallet still has a balance")]
+    WalletNotEmpty,
+    #[error("Choose another default wallet in this currency before closing this one")]
+    DefaultWalletInUse,
+    #[error("Converted amount is too small")]
+    ConversionTooSmall,
+    #[error("FX quote expired")]
+    FxQuoteExpired,
+    #[error("FX quote already used")]
+    FxQuoteUsed,
+    #[error("Only transfers can be sent back, and only by their recipient")]
+    NotRefundable,
+    #[error("Amount is more than is left to refund")]
+    RefundExceedsPayment,
+    #[error("Payment request expired")]
+    PaymentRequestExpired,
+    #[error("Hold expired")]
+    HoldExpired,
+    #[error("Amount is more than the hold")]
+    CaptureExceedsHold,
+    #[error("Spending limit exceeded")]
+    LimitExceeded(db::Limit),
+    #[error("Transfer was blocked as likely fraud")]
+    TransferBlocked,
+
+    #[error("Account temporarily locked")]
+    AccountLocked { retry_after: Duration },
+    #[error("Too many failed sign in attempts")]
+    TooManyAttempts { retry_after: Duration },
+
+    /// Logged, but not shown to the client
+    #[error("Internal server error")]
+    Internal(anyhow::Error),
+}
+
+#[derive(Debug, Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        use ApiError::*;
+        match self {
+            MalformedRequest { status, .. } => *status,
+            InvalidEmail
+            | EmptyUsername
+            | EmptyPassword
+            | InvalidAmount
+            | InvalidLimit
+            | InvalidCursor
+            | InvalidHeader(_)
+            | InvalidOrExpiredToken
+            | UnsupportedCurrency
+            | InvalidPrecision { .. }
+            | InvalidWalletName
+            | InvalidLegalName
+            | InvalidFxRate
+            | EmptyReason
+            | InvalidNote
+            | InvalidCronPattern
+            | InvalidSchedule
+            | InvalidSpendingLimit => StatusCode::BAD_REQUEST,
+            MissingAuthorization | InvalidToken | TokenExpired | TokenRevoked
+            | InvalidCredentials | InvalidRefreshToken | RefreshTokenExpired
+            | RefreshTokenReused | InvalidMfaToken | MfaTokenExpired | InvalidMfaCode
+            | MfaCodeRequired => StatusCode::UNAUTHORIZED,
+            MissingPermission(_) | EmailNotVerified | MfaEnrolmentRequired | AccountFrozen
+            | AccountClosed | ScreeningMatch => StatusCode::FORBIDDEN,
+            TransactionNotFound
+            | UserNotFound
+            | RecipientNotFound
+            | WalletNotFound
+            | FxRateNotFound
+            | FxQuoteNotFound
+            | PaymentRequestNotFound
+            | ScheduledPaymentNotFound
+            | HoldNotFound
+            | TransferReviewNotFound
+            | ScreeningHitNotFound => StatusCode::NOT_FOUND,
+            UsernameTaken
+            | UsernameOrEmailTaken
+            | WalletAlreadyExists
+            | EmailAlreadyVerified
+            | MfaAlreadyEnabled
+            | NoPendingMfaEnrolment
+            | InvalidStatusTransition
+            | PaymentRequestNotPending
+            | HoldNotActive
+            | TransferReviewNotPending
+            | IdempotencyKeyReused
+            | IdempotencyKeyInProgress => StatusCode::CONFLICT,
+            InsufficientFunds
+            | RecipientNotVerified
+            | SelfTransfer
+            | RecipientFrozen
+            | WalletFrozen
+            | RecipientWalletFrozen
+            | RecipientWalletNotFound
+            | CurrencyMismatch
+            | WalletNotEmpty
+            | DefaultWalletInUse
+            | ConversionTooSmall
+            | FxQuoteExpired
+            | FxQuoteUsed
+            | NotRefundable
+            | RefundExceedsPayment
+            | PaymentRequestExpired
+            | HoldExpired
+            | CaptureExceedsHold
+            | LimitExceeded(_)
+            | TransferBlocked => StatusCode::UNPROCESSABLE_ENTITY,
+            AccountLocked { .. } => StatusCode::LOCKED,
+            TooManyAttempts { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine readable error code. Never change these, clients depend on them.
+    pub fn code(&self) -> &'static str {
+        use ApiError::*;
+        match self {
+            MalformedRequest { .. } => "malformed_request",
+            InvalidEmail => "invalid_email",
+            EmptyUsername => "empty_username",
+            EmptyPassword => "empty_password",
+            InvalidAmount => "invalid_amount",
+            InvalidLimit => "invalid_limit",
+            InvalidCursor => "invalid_cursor",
+            InvalidHeader(_) => "invalid_header",
+            InvalidOrExpiredToken => "invalid_or_expired_token",
+            UnsupportedCurrency => "unsupported_currency",
+            InvalidPrecision { .. } => "invalid_precision",
+            InvalidWalletName => "invalid_wallet_name",
+            InvalidLegalName => "invalid_legal_name",
+            InvalidFxRate => "invalid_fx_rate",
+            EmptyReason => "empty_reason",
+            InvalidNote => "invalid_note",
+            InvalidCronPattern => "invalid_cron_pattern",
+            InvalidSchedule => "invalid_schedule",
+            InvalidSpendingLimit => "invalid_spending_limit",
+            MissingAuthorization => "missing_authorization",
+            InvalidToken => "invalid_token",
+            TokenExpired => "token_expired",
+            TokenRevoked => "token_revoked",
+            InvalidCredentials => "invalid_credentials",
+            InvalidRefreshToken => "invalid_refresh_token",
+            RefreshTokenExpired => "refresh_token_expired",
+            RefreshTokenReused => "refresh_token_reused",
+            InvalidMfaToken => "invalid_mfa_token",
+            MfaTokenExpired => "mfa_token_expired",
+            InvalidMfaCode => "invalid_mfa_code",
+            MfaCodeRequired => "mfa_code_required",
+            MissingPermission(_) => "missing_permission",
+            EmailNotVerified => "email_not_verified",
+            MfaEnrolmentRequired => "mfa_enrolment_required",
+            ScreeningMatch => "screening_match",
+            AccountFrozen => "account_frozen",
+            AccountClosed => "account_closed",
+            TransactionNotFound => "transaction_not_found",
+            UserNotFound => "user_not_found",
+            RecipientNotFound => "recipient_not_found",
+            WalletNotFound => "wallet_not_found",
+            FxRateNotFound => "fx_rate_not_found",
+            FxQuoteNotFound => "fx_quote_not_found",
+            PaymentRequestNotFound => "payment_request_not_found",
+            ScheduledPaymentNotFound => "scheduled_payment_not_found",
+            HoldNotFound => "hold_not_found",
+            TransferReviewNotFound => "transfer_review_not_found",
+            ScreeningHitNotFound => "screening_hit_not_found",
+            UsernameTaken => "username_taken",
+            UsernameOrEmailTaken => "username_or_email_taken",
+            WalletAlreadyExists => "wallet_already_exists",
+            EmailAlreadyVerified => "email_already_verified",
+            MfaAlreadyEnabled => "mfa_already_enabled",
+            NoPendingMfaEnrolment => "no_pending_mfa_enrolment",
+            InvalidStatusTransition => "invalid_status_transition",
+            PaymentRequestNotPending => "payment_request_not_pending",
+            HoldNotActive => "hold_not_active",
+            TransferReviewNotPending => "transfer_review_not_pending",
+            IdempotencyKeyReused => "idempotency_key_reused",
+            IdempotencyKeyInProgress => "idempotency_key_in_progress",
+            InsufficientFunds => "insufficient_funds",
+            RecipientNotVerified => "recipient_not_verified",
+            SelfTransfer => "self_transfer",
+            RecipientFrozen => "recipient_frozen",
+            WalletFrozen => "wallet_frozen",
+            RecipientWalletFrozen => "recipient_wallet_frozen",
+            RecipientWalletNotFound => "recipient_wallet_not_found",
+            CurrencyMismatch => "currency_mismatch",
+            WalletNotEmpty => "wallet_not_empty",
+            DefaultWalletInUse => "default_wallet_in_use",
+            ConversionTooSmall => "conversion_too_small",
+            FxQuoteExpired => "fx_quote_expired",
+            FxQuoteUsed => "fx_quote_used",
+            NotRefundable => "not_refundable",
+            RefundExceedsPayment => "refund_exceeds_payment",
+            PaymentRequestExpired => "payment_request_expired",
+            HoldExpired => "hold_expired",
+            CaptureExceedsHold => "capture_exceeds_hold",
+            LimitExceeded(_) => "limit_exceeded",
+            TransferBlocked => "transfer_blocked",
+            AccountLocked { .. } => "account_locked",
+            TooManyAttempts { .. } => "too_many_attempts",
+            Internal(_) => "internal_error",
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::AccountLocked { retry_after } | ApiError::TooManyAttempts { retry_after } => {
+                Some(*retry_after)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(e) = &self {
+            tracing::error!(error = ?e, "Internal server error");
+        }
+
+        // round up so clients don't retry a moment too early
+        let retry_after = self.retry_after().map(|d| d.num_seconds() + 1);
+        let detail = match &self {
+            ApiError::MalformedRequest { detail, .. } => Some(detail.clone()),
+            ApiError::LimitExceeded(limit) => Some(format!("Payment would go over the {limit}")),
+            _ => retry_after.map(|secs| format!("Try again in {secs} seconds")),
+        };
+        let status = self.status();
+        let problem = Problem {
+            problem_type: format!("urn:smpl-payments:problem:{}", self.code()),
+            title: self.to_string(),
+            status: status.as_u16(),
+            code: self.code(),
+            detail,
+        };
+
+        let mut response = (status, Json(problem)).into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let Some(secs) = retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+impl From<db::Error> for ApiError {
+    fn from(e: db::Error) -> Self {
+        match e {
+            db::Error::IdempotencyKeyExpired => ApiError::IdempotencyKeyInProgress,
+            db::Error::InsufficientFunds => ApiError::InsufficientFunds,
+            db::Error::RecipientNotFound => ApiError::RecipientNotFound,
+            db::Error::SelfTransfer => ApiError::SelfTransfer,
+            db::Error::UserNotFound => ApiError::UserNotFound,
+            db::Error::InvalidStatusTransition => ApiError::InvalidStatusTransition,
+            db::Error::AccountFrozen => ApiError::AccountFrozen,
+            db::Error::AccountClosed => ApiError::AccountClosed,
+            db::Error::RecipientFrozen => ApiError::RecipientFrozen,
+            db::Error::WalletFrozen => ApiError::WalletFrozen,
+            db::Error::RecipientWalletFrozen => ApiError::RecipientWalletFrozen,
+            db::Error::UnsupportedCurrency => ApiError::UnsupportedCurrency,
+            db::Error::InvalidPrecision { minor_units } => {
+                ApiError::InvalidPrecision { minor_units }
+            }
+            db::Error::WalletNotFound => ApiError::WalletNotFound,
+            db::Error::RecipientWalletNotFound => ApiError::RecipientWalletNotFound,
+            db::Error::CurrencyMismatch => ApiError::CurrencyMismatch,
+            db::Error::WalletNotEmpty => ApiError::WalletNotEmpty,
+            db::Error::DefaultWalletInUse => ApiError::DefaultWalletInUse,
+            db::Error::FxRateNotFound => ApiError::FxRateNotFound,
+            db::Error::ConversionTooSmall => ApiError::ConversionTooSmall,
+            db::Error::FxQuoteNotFound => ApiError::FxQuoteNotFound,
+            db::Error::FxQuoteExpired => ApiError::FxQuoteExpired,
+            db::Error::FxQuoteUsed => ApiError::FxQuoteUsed,
+            db::Error::TransactionNotFound => ApiError::TransactionNotFound,
+            db::Error::NotRefundable => ApiError::NotRefundable,
+            db::Error::RefundExceedsPayment => ApiError::RefundExceedsPayment,
+            db::Error::PaymentRequestNotFound => ApiError::PaymentRequestNotFound,
+            db::Error::PaymentRequestNotPending => ApiError::PaymentRequestNotPending,
+            db::Error::PaymentRequestExpired => ApiError::PaymentRequestExpired,
+            db::Error::ScheduledPaymentNotFound => ApiError::ScheduledPaymentNotFound,
+            db::Error::InvalidCronPattern => ApiError::InvalidCronPattern,
+            db::Error::NoScheduledRuns => ApiError::InvalidSchedule,
+            db::Error::HoldNotFound => ApiError::HoldNotFound,
+            db::Error::HoldNotActive => ApiError::HoldNotActive,
+            db::Error::HoldExpired => ApiError::HoldExpired,
+            db::Error::CaptureExceedsHold => ApiError::CaptureExceedsHold,
+            db::Error::LimitExceeded(limit) => ApiError::LimitExceeded(limit),
+            db::Error::TransferReviewNotFound => ApiError::TransferReviewNotFound,
+            db::Error::TransferReviewNotPending => ApiError::TransferReviewNotPending,
+            db::Error::TransferBlocked => ApiError::TransferBlocked,
+            db::Error::ScreeningHitNotFound => ApiError::ScreeningHitNotFound,
+            db::Error::ScreeningMatch => ApiError::ScreeningMatch,
+            e => ApiError::Internal(e.into()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(e)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::MalformedRequest {
+            status: rejection.status(),
+            detail: rejection.body_text(),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::MalformedRequest {
+            status: rejection.status(),
+            detail: rejection.body_text(),
+        }
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::MalformedRequest {
+            status: rejection.status(),
+            detail: rejection.body_text(),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Response, Json};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        models::{AccountStatus, Role, Transaction, User, Wallet},
+        AdjustmentDirection, Refunder,
+    },
+    error::ApiError,
+    permission::{
+        CanAdjustBalances, CanFreezeAccounts, CanManageRoles, CanReverseTransactions, CanViewUsers,
+        CanViewWallets,
+    },
+    utils::{ApiJson, ApiPath, ApiQuery, IdempotencyKey, OptionalApiJson, RequirePermission},
+    AppState,
+};
+
+use super::{
+    idempotency::{self, Begun},
+    transaction::{transaction_page, ListTransactions, TransactionPage},
+    wallet::{with_balances, WalletBalances, WalletLedger},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchUsers {
+    /// Part of a username or email, matched ignoring case
+    #[serde(default)]
+    q: String,
+    /// Id of the last user on the previous page
+    after_id: Option<i32>,
+    limit: Option<i64>,
+}
+
+/// finds users by username or email, oldest first
+pub async fn search_users(
+    _: RequirePermission<CanViewUsers>,
+    State(state): State<AppState>,
+    ApiQuery(SearchUsers { q, after_id, limit }): ApiQuery<SearchUsers>,
+) -> Result<Json<Vec<User>>, ApiError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::InvalidLimit);
+    }
+
+    let users = state
+        .smpldb
+        .search_users(q.trim(), after_id, limit)
+        .await
+        .context("Failed to search users")?;
+    Ok(Json(users))
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserDetails {
+    #[serde(flatten)]
+    user: User,
+    /// Open wallets
+    wallets: Vec<WalletBalances>,
+}
+
+pub async fn get_user(
+    _: RequirePermission<CanViewUsers>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+) -> Result<Json<UserDetails>, ApiError> {
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .ok_or(ApiError::UserNotFound)?;
+    let wallets = state
+        .smpldb
+        .list_wallets(user_id)
+        .await
+        .context("Failed to list wallets for user")?;
+    let wallets = with_balances(&state, wallets).await?;
+    Ok(Json(UserDetails { user, wallets }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRole {
+    role: Role,
+}
+
+/// gives the user another role, signing them out everywhere so it takes effect at once
+pub async fn set_user_role(
+    RequirePermission(admin_id, _): RequirePermission<CanManageRoles>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    ApiJson(SetRole { role }): ApiJson<SetRole>,
+) -> Result<Json<User>, ApiError> {
+    let user = state.smpldb.set_user_role(user_id, role).await?;
+    tracing::info!(admin_id, user_id, role = role.as_str(), "Changed user role");
+
+    // tokens carry the role they were issued with
+    let valid_after = state
+        .smpldb
+        .revoke_all_tokens(user_id)
+        .await
+        .context("Failed to revoke all tokens for user")?;
+    state.revocations.mark_all_revoked(user_id, valid_after);
+    Ok(Json(user))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StatusChange {
+    /// Recorded in `security_event`
+    reason: Option<String>,
+}
+
+async fn set_user_status(
+    state: &AppState,
+    admin_id: i32,
+    user_id: i32,
+    status: AccountStatus,
+    StatusChange { reason }: StatusChange,
+) -> Result<Json<User>, ApiError> {
+    let user = state
+        .smpldb
+        .set_user_status(user_id, status, reason.as_deref())
+        .await?;
+    tracing::info!(
+        admin_id,
+        user_id,
+        status = status.as_str(),
+        reason,
+        "Changed user status"
+    );
+
+    if status == AccountStatus::Closed {
+        let valid_after = state
+            .smpldb
+            .revoke_all_tokens(user_id)
+            .await
+            .context("Failed to revoke all tokens for user")?;
+        state.revocations.mark_all_revoked(user_id, valid_after);
+    }
+    Ok(Json(user))
+}
+
+/// stops the user sending or receiving money
+pub async fn freeze_user(
+    RequirePermission(admin_id, _): RequirePermission<CanFreezeAccounts>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<StatusChange>,
+) -> Result<Json<User>, ApiError> {
+    set_user_status(&state, admin_id, user_id, AccountStatus::Frozen, request).await
+}
+
+pub async fn unfreeze_user(
+    RequirePermission(admin_id, _): RequirePermission<CanFreezeAccounts>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<StatusChange>,
+) -> Result<Json<User>, ApiError> {
+    set_user_status(&state, admin_id, user_id, AccountStatus::Active, request).await
+}
+
+/// closes the user's account for good, signing them out everywhere
+pub async fn close_user(
+    RequirePermission(admin_id, _): RequirePermission<CanFreezeAccounts>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<StatusChange>,
+) -> Result<Json<User>, ApiError> {
+    set_user_status(&state, admin_id, user_id, AccountStatus::Closed, request).await
+}
+
+pub async fn freeze_wallet(
+    RequirePermission(admin_id, _): RequirePermission<CanFreezeAccounts>,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+) -> Result<Json<Wallet>, ApiError> {
+    let wallet = state
+        .smpldb
+        .set_wallet_status(wallet_id, AccountStatus::Frozen)
+        .await?;
+    tracing::info!(admin_id, wallet_id, "Froze wallet");
+    Ok(Json(wallet))
+}
+
+pub async fn unfreeze_wallet(
+    RequirePermission(admin_id, _): RequirePermission<CanFreezeAccounts>,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+) -> Result<Json<Wallet>, ApiError> {
+    let wallet = state
+        .smpldb
+        .set_wallet_status(wallet_id, AccountStatus::Active)
+        .await?;
+    tracing::info!(admin_id, wallet_id, "Unfroze wallet");
+    Ok(Json(wallet))
+}
+
+/// returns any user's wallet, open or closed
+pub async fn get_wallet(
+    _: RequirePermission<CanViewWallets>,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+) -> Result<Json<WalletBalances>, ApiError> {
+    let wallet = state.smpldb.get_wallet_by_id(wallet_id).await?;
+    let mut wallets = with_balances(&state, vec![wallet]).await?;
+    Ok(Json(wallets.remove(0)))
+}
+
+pub async fn get_wallet_ledger(
+    _: RequirePermission<CanViewWallets>,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+) -> Result<Json<WalletLedger>, ApiError> {
+    let wallet = state.smpldb.get_wallet_by_id(wallet_id).await?;
+    let postings = state
+        .smpldb
+        .get_wallet_postings(wallet_id)
+        .await
+        .context("Failed to get wallet postings")?;
+    Ok(Json(WalletLedger::new(wallet, postings)))
+}
+
+/// lists the transactions of any user's wallet, with the filters of `GET /transactions`
+pub async fn list_wallet_transactions(
+    _: RequirePermission<CanViewWallets>,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+    ApiQuery(query): ApiQuery<ListTransactions>,
+) -> Result<Json<TransactionPage>, ApiError> {
+    let wallet = state.smpldb.get_wallet_by_id(wallet_id).await?;
+    let page = transaction_page(&state, wallet.user_id, query.in_wallet(wallet_id)).await?;
+    Ok(Json(page))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateAdjustment {
+    direction: AdjustmentDirection,
+    amount: BigDecimal,
+    /// Why the balance is being corrected, e.g. a support ticket
+    reason: String,
+}
+
+/// credits or debits a wallet to correct its balance
+pub async fn create_adjustment(
+    RequirePermission(admin_id, _): RequirePermission<CanAdjustBalances>,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<CreateAdjustment>,
+) -> Result<Response, ApiError> {
+    if request.amount <= BigDecimal::from(0) {
+        return Err(ApiError::InvalidAmount);
+    }
+    if request.reason.trim().is_empty() {
+        return Err(ApiError::EmptyReason);
+    }
+
+    let key = idempotency_key.as_deref();
+    let route = format!("POST /admin/wallets/{wallet_id}/adjustments");
+    let reservation = match idempotency::begin(&state, admin_id, key, &route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let CreateAdjustment {
+        direction,
+        amount,
+        reason,
+    } = request;
+    let respond =
+        |transaction: &Transaction| Some(idempotency::json(StatusCode::CREATED, transaction));
+    match state
+        .smpldb
+        .adjust_balance(
+            admin_id,
+            wallet_id,
+            direction,
+            amount,
+            reason.trim(),
+            reservation.store(&respond),
+        )
+        .await
+    {
+        Ok(transaction) => {
+            tracing::info!(
+                admin_id,
+                wallet_id,
+                transaction_id = transaction.id,
+                reason,
+                "Adjusted wallet balance"
+            );
+            Ok(idempotency::response(idempotency::json(
+                StatusCode::CREATED,
+                &transaction,
+            )))
+        }
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateReversal {
+    /// Defaults to everything not yet refunded
+    amount: Option<BigDecimal>,
+    reason: String,
+}
+
+/// sends all or part of any transfer back to its sender, even between frozen accounts
+pub async fn reverse_transaction(
+    RequirePermission(admin_id, _): RequirePermission<CanReverseTransactions>,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    State(state): State<AppState>,
+    ApiPath(transaction_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<CreateReversal>,
+) -> Result<Response, ApiError> {
+    if request
+        .amount
+        .as_ref()
+        .is_some_and(|a| *a <= BigDecimal::from(0))
+    {
+        return Err(ApiError::InvalidAmount);
+    }
+    if request.reason.trim().is_empty() {
+        return Err(ApiError::EmptyReason);
+    }
+
+    let key = idempotency_key.as_deref();
+    let route = format!("POST /admin/transactions/{transaction_id}/reversals");
+    let reservation = match idempotency::begin(&state, admin_id, key, &route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let CreateReversal { amount, reason } = request;
+    let respond =
+        |transaction: &Transaction| Some(idempotency::json(StatusCode::CREATED, transaction));
+    match state
+        .smpldb
+        .refund_transaction(
+            Refunder::Admin(admin_id),
+            transaction_id,
+            amount,
+            Some(reason.trim()),
+            reservation.store(&respond),
+        )
+        .await
+    {
+        Ok(transaction) => {
+            tracing::info!(
+                admin_id,
+                transaction_id,
+                reversal_id = transaction.id,
+                reason,
+                "Reversed transaction"
+            );
+            Ok(idempotency::response(idempotency::json(
+                StatusCode::CREATED,
+                &transaction,
+            )))
+        }
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
+    }
+}
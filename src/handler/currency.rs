@@ -0,0 +1,10 @@
+use axum::{extract::State, Json};
+
+use crate::{db::models::Currency, error::ApiError, AppState};
+
+/// lists the currencies wallets can be opened in
+pub async fn list_currencies(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Currency>>, ApiError> {
+    Ok(Json(state.smpldb.list_currencies().await?))
+}
@@ -0,0 +1,100 @@
+use axum::{extract::State, http::StatusCode, Json};
+use bigdecimal::{BigDecimal, One, Zero};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    db::models::{FxQuote, FxRate},
+    error::ApiError,
+    permission::CanManageFxRates,
+    utils::{ApiJson, RequirePermission, ValidateAuth},
+    AppState,
+};
+
+/// lists the exchange rates in effect now
+pub async fn list_fx_rates(State(state): State<AppState>) -> Result<Json<Vec<FxRate>>, ApiError> {
+    Ok(Json(state.smpldb.list_fx_rates().await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFxQuote {
+    from_currency: String,
+    to_currency: String,
+    /// In `from_currency`
+    amount: BigDecimal,
+}
+
+/// quotes a conversion, locking the rate for `FX_QUOTE_TTL_SECS`
+pub async fn create_fx_quote(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<CreateFxQuote>,
+) -> Result<(StatusCode, Json<FxQuote>), ApiError> {
+    if request.amount <= BigDecimal::zero() {
+        return Err(ApiError::InvalidAmount);
+    }
+
+    let quote = state
+        .smpldb
+        .create_fx_quote(
+            user_id,
+            &request.from_currency.to_ascii_uppercase(),
+            &request.to_currency.to_ascii_uppercase(),
+            &request.amount,
+            state.config.fx_quote_ttl,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(quote)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetFxRate {
+    base_currency: String,
+    quote_currency: String,
+    rate: BigDecimal,
+    /// Fraction of `rate` kept by the platform, e.g. `0.01` for 1%
+    #[serde(default)]
+    spread: BigDecimal,
+    /// Defaults to now
+    effective_at: Option<DateTime<Utc>>,
+}
+
+/// adds an exchange rate, replacing the current one for the pair from `effective_at`
+pub async fn set_fx_rate(
+    RequirePermission(admin_id, _): RequirePermission<CanManageFxRates>,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<SetFxRate>,
+) -> Result<(StatusCode, Json<FxRate>), ApiError> {
+    if request.rate <= BigDecimal::zero()
+        || request.spread < BigDecimal::zero()
+        || request.spread >= BigDecimal::one()
+    {
+        return Err(ApiError::InvalidFxRate);
+    }
+
+    let base = request.base_currency.to_ascii_uppercase();
+    let quote = request.quote_currency.to_ascii_uppercase();
+    if base == quote {
+        return Err(ApiError::InvalidFxRate);
+    }
+
+    let rate = state
+        .smpldb
+        .set_fx_rate(
+            &base,
+            &quote,
+            &request.rate,
+            &request.spread,
+            request.effective_at.unwrap_or_else(Utc::now),
+        )
+        .await?;
+    tracing::info!(
+        admin_id,
+        base,
+        quote,
+        rate = %rate.rate,
+        spread = %rate.spread,
+        "Set FX rate"
+    );
+    Ok((StatusCode::CREATED, Json(rate)))
+}
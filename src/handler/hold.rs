@@ -0,0 +1,279 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Response, Json};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::MoneyMovement,
+    db::{
+        models::{Hold, HoldStatus},
+        HoldParty, HoldWithUsers, PaymentOutcome, StoredResponse,
+    },
+    error::ApiError,
+    utils::{ApiJson, ApiPath, ApiQuery, IdempotencyKey, OptionalApiJson, TotpCode, ValidateAuth},
+    AppState,
+};
+
+use super::{
+    idempotency::{self, Begun},
+    mfa::require_step_up,
+    require_verified_email, require_verified_recipient,
+    screening::screen_transfer,
+    transfer_review::{log_outcome, respond_to_payment},
+    wallet_ref,
+};
+
+const MAX_NOTE_LENGTH: usize = 255;
+
+#[derive(Debug, Serialize)]
+pub struct FormattedHold {
+    id: i32,
+    payer_username: String,
+    merchant_username: String,
+    wallet_id: i32,
+    amount: BigDecimal,
+    currency: String,
+    note: Option<String>,
+    status: HoldStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captured_amount: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_id: Option<i32>,
+    expires_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<HoldWithUsers> for FormattedHold {
+    fn from((hold, payer_username, merchant_username): HoldWithUsers) -> Self {
+        let Hold {
+            id,
+            wallet_id,
+            amount,
+            currency,
+            note,
+            status,
+            captured_amount,
+            transaction_id,
+            expires_at,
+            created_at,
+            updated_at,
+            ..
+        } = hold;
+        Self {
+            id,
+            payer_username,
+            merchant_username,
+            wallet_id,
+            amount,
+            currency,
+            note,
+            status,
+            captured_amount,
+            transaction_id,
+            expires_at,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthorizeHold {
+    merchant_username: String,
+    amount: BigDecimal,
+    /// Defaults to the currency of `from_wallet_id`, or `DEFAULT_CURRENCY`. The merchant needs a
+    /// wallet in it.
+    currency: Option<String>,
+    /// Defaults to the user's default wallet in `currency`
+    from_wallet_id: Option<i32>,
+    note: Option<String>,
+}
+
+/// reserves funds in the user's wallet for a merchant to capture later
+pub async fn authorize_hold(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    TotpCode(totp_code): TotpCode,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<AuthorizeHold>,
+) -> Result<Response, ApiError> {
+    if request.amount <= BigDecimal::from(0) {
+        return Err(ApiError::InvalidAmount);
+    }
+    let note = request
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if note.is_some_and(|n| n.chars().count() > MAX_NOTE_LENGTH) {
+        return Err(ApiError::InvalidNote);
+    }
+
+    let key = idempotency_key.as_deref();
+    let reservation =
+        match idempotency::begin(&state, user_id, key, "POST /holds", &request).await? {
+            Begun::Replay(replay) => return Ok(replay),
+            Begun::Reserved(reservation) => reservation,
+        };
+
+    let from_wallet = wallet_ref(&state, request.from_wallet_id, request.currency.clone());
+    let checked = async {
+        require_verified_email(&state, user_id, MoneyMovement::Send).await?;
+        require_verified_recipient(&state, &request.merchant_username).await?;
+        let sender = state
+            .smpldb
+            .get_user_by_id(user_id)
+            .await?
+            .with_context(|| format!("Failed to find user with id {user_id}"))?;
+        screen_transfer(&state, &sender, &request.merchant_username).await?;
+        // authorising lets the merchant take the funds, so it needs the same step up as paying
+        // them
+        require_step_up(
+            &state,
+            user_id,
+            &from_wallet,
+            &request.amount,
+            totp_code.as_deref(),
+        )
+        .await
+    }
+    .await;
+    if let Err(e) = checked {
+        reservation.abort(&state).await;
+        return Err(e);
+    }
+    let expires_at = Utc::now() + state.config.hold_ttl;
+    let respond = |hold: &HoldWithUsers| {
+        let hold = FormattedHold::from(hold.clone());
+        Some(idempotency::json(StatusCode::CREATED, &hold))
+    };
+    match state
+        .smpldb
+        .authorize_hold(
+            user_id,
+            &from_wallet,
+            &request.merchant_username,
+            request.amount.clone(),
+            note,
+            expires_at,
+            reservation.store(&respond),
+        )
+        .await
+    {
+        Ok(hold) => Ok(idempotency::response(idempotency::json(
+            StatusCode::CREATED,
+            &FormattedHold::from(hold),
+        ))),
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListHolds {
+    /// `payer` for holds on the user's wallets, `merchant` for holds they can capture. Defaults
+    /// to both.
+    party: Option<HoldParty>,
+    status: Option<HoldStatus>,
+}
+
+/// lists holds on the user's wallets and holds they can capture, newest first
+pub async fn list_holds(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ListHolds>,
+) -> Result<Json<Vec<FormattedHold>>, ApiError> {
+    let holds = state
+        .smpldb
+        .list_holds(user_id, query.party, query.status)
+        .await?;
+    Ok(Json(holds.into_iter().map(Into::into).collect()))
+}
+
+pub async fn get_hold(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(hold_id): ApiPath<i32>,
+) -> Result<Json<FormattedHold>, ApiError> {
+    Ok(Json(state.smpldb.get_hold(user_id, hold_id).await?.into()))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CaptureHold {
+    /// Defaults to the whole hold
+    amount: Option<BigDecimal>,
+}
+
+/// pays all or part of a hold to the merchant, releasing the rest. A capture held for review
+/// returns `202 Accepted` with the captured hold, which is paid once the review approves it.
+pub async fn capture_hold(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    State(state): State<AppState>,
+    ApiPath(hold_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<CaptureHold>,
+) -> Result<Response, ApiError> {
+    if request
+        .amount
+        .as_ref()
+        .is_some_and(|a| *a <= BigDecimal::from(0))
+    {
+        return Err(ApiError::InvalidAmount);
+    }
+
+    let key = idempotency_key.as_deref();
+    let route = format!("POST /holds/{hold_id}/capture");
+    let reservation = match idempotency::begin(&state, user_id, key, &route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    match state
+        .smpldb
+        .capture_hold(
+            user_id,
+            hold_id,
+            request.amount,
+            state.risk.as_ref(),
+            reservation.store(&respond_to_capture),
+        )
+        .await
+    {
+        Ok(captured) => {
+            log_outcome(user_id, &captured.0);
+            respond_to_capture(&captured)
+                .map(idempotency::response)
+                .ok_or(ApiError::TransferBlocked)
+        }
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
+    }
+}
+
+/// Responds to a capture like a transfer, except a capture held for review responds with the
+/// captured hold
+fn respond_to_capture((outcome, hold): &(PaymentOutcome, HoldWithUsers)) -> Option<StoredResponse> {
+    match outcome {
+        PaymentOutcome::Held(_) => {
+            let hold = FormattedHold::from(hold.clone());
+            Some(idempotency::json(StatusCode::ACCEPTED, &hold))
+        }
+        _ => respond_to_payment(outcome),
+    }
+}
+
+/// releases a hold without paying the merchant
+pub async fn void_hold(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(hold_id): ApiPath<i32>,
+) -> Result<Json<FormattedHold>, ApiError> {
+    state.smpldb.void_hold(user_id, hold_id).await?;
+    Ok(Json(state.smpldb.get_hold(user_id, hold_id).await?.into()))
+}
@@ -0,0 +1,111 @@
+use anyhow::Context;
+use axum::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::{models::IdempotencyRecord, IdempotentRequest, KeyReservation, StoredResponse},
+    error::ApiError,
+    AppState,
+};
+
+/// Hashes the route and request body so a key cannot be reused for a different request
+fn fingerprint<T: Serialize>(route: &str, request: &T) -> String {
+    let body = serde_json::to_string(request).unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(route.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// The key reserved for a request, if it was made with one
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Reservation(Option<i32>);
+
+impl Reservation {
+    /// Passed to the DB so the response is stored in the same transaction as the request's
+    /// changes
+    pub(super) fn store<T>(
+        self,
+        respond: &(dyn Fn(&T) -> Option<StoredResponse> + Sync),
+    ) -> Option<IdempotentRequest<'_, T>> {
+        self.0.map(|key_id| IdempotentRequest { key_id, respond })
+    }
+
+    /// Releases the key after a failed request so it can be retried
+    pub(super) async fn abort(self, state: &AppState) {
+        let Some(key_id) = self.0 else {
+            return;
+        };
+        if let Err(e) = state.smpldb.release_idempotency_key(key_id).await {
+            tracing::error!(?e, key_id, "Failed to release idempotency key");
+        }
+    }
+}
+
+/// Whether the handler should run the request
+pub(super) enum Begun {
+    /// The request was already made, with this response
+    Replay(Response),
+    /// The handler should go ahead and run the request
+    Reserved(Reservation),
+}
+
+/// Reserves the key, if there is one, for the request to `route`. Handlers call this before
+/// checking the users, e.g. that they are verified, so a retry of a request that went through
+/// gets its stored response even if a check would now fail.
+pub(super) async fn begin<T: Serialize>(
+    state: &AppState,
+    user_id: i32,
+    key: Option<&str>,
+    route: &str,
+    request: &T,
+) -> Result<Begun, ApiError> {
+    let Some(key) = key else {
+        return Ok(Begun::Reserved(Reservation(None)));
+    };
+    let request_hash = fingerprint(route, request);
+    let reservation = state
+        .smpldb
+        .reserve_idempotency_key(
+            user_id,
+            key,
+            &request_hash,
+            state.config.idempotency_reservation_ttl,
+        )
+        .await
+        .context("Failed to reserve idempotency key")?;
+    match reservation {
+        KeyReservation::Reserved(key_id) => Ok(Begun::Reserved(Reservation(Some(key_id)))),
+        KeyReservation::Taken(record) if record.request_hash != request_hash => {
+            Err(ApiError::IdempotencyKeyReused)
+        }
+        KeyReservation::Taken(IdempotencyRecord {
+            response_status: Some(status),
+            response_body: Some(body),
+            ..
+        }) => Ok(Begun::Replay(response(StoredResponse { status, body }))),
+        KeyReservation::Taken(_) => Err(ApiError::IdempotencyKeyInProgress),
+    }
+}
+
+/// Serializes the JSON response to a request
+pub(super) fn json<T: Serialize>(status: StatusCode, value: &T) -> StoredResponse {
+    StoredResponse {
+        status: status.as_u16().into(),
+        body: serde_json::to_string(value).expect("responses serialize to JSON"),
+    }
+}
+
+/// Sends a stored response
+pub(super) fn response(stored: StoredResponse) -> Response {
+    let status = u16::try_from(stored.status)
+        .ok()
+        .and_then(|s| StatusCode::from_u16(s).ok())
+        .unwrap_or(StatusCode::OK);
+    (status, [(CONTENT_TYPE, "application/json")], stored.body).into_response()
+}
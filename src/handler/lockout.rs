@@ -0,0 +1,149 @@
+use anyhow::Context;
+use chrono::{Duration, Utc};
+
+use crate::{
+    db::{models::User, SecurityEventKind},
+    error::ApiError,
+    AppState,
+};
+
+/// Longest the user has to wait after a failed sign in attempt, before the lockout kicks in
+const MAX_DELAY_SECS: i64 = 30;
+
+/// Rejects sign in attempts from an IP address with too many recent failures
+pub(super) async fn check_ip(state: &AppState, ip_address: &str) -> Result<(), ApiError> {
+    let window = state.config.sign_in_failure_window;
+    let failures = state
+        .smpldb
+        .recent_ip_failures(ip_address, Utc::now() - window)
+        .await
+        .context("Failed to count sign in failures for IP address")?;
+    if failures >= state.config.sign_in_max_ip_failures {
+        return Err(ApiError::TooManyAttempts {
+            retry_after: window,
+        });
+    }
+    Ok(())
+}
+
+/// Rejects sign in attempts for a locked account, or made too soon after a failed attempt. The wait
+/// doubles with each failure.
+pub(super) async fn check_account(state: &AppState, user: &User) -> Result<(), ApiError> {
+    let now = Utc::now();
+    if let Some(locked_until) = user.locked_until.filter(|t| *t > now) {
+        return Err(ApiError::AccountLocked {
+            retry_after: locked_until - now,
+        });
+    }
+
+    let failures = state
+        .smpldb
+        .recent_account_failures(user.id, now - state.config.sign_in_failure_window)
+        .await
+        .context("Failed to count sign in failures for user")?;
+    let Some(last_failed_at) = failures.last_failed_at else {
+        return Ok(());
+    };
+    let delay = Duration::seconds(
+        2_i64
+            .saturating_pow((failures.count - 1).clamp(0, 31) as u32)
+            .min(MAX_DELAY_SECS),
+    );
+    let retry_at = last_failed_at + delay;
+    if now < retry_at {
+        return Err(ApiError::TooManyAttempts {
+            retry_after: retry_at - now,
+        });
+    }
+    Ok(())
+}
+
+/// Records a failed sign in attempt, locking the account or blocking the IP address once they
+/// have failed too often. Returns `error`, or [`ApiError::AccountLocked`] if the account was
+/// locked.
+pub(super) async fn record_failure(
+    state: &AppState,
+    ip_address: &str,
+    email: &str,
+    user_id: Option<i32>,
+    error: ApiError,
+) -> ApiError {
+    match try_record_failure(state, ip_address, email, user_id).await {
+        Ok(Some(lockout)) => ApiError::AccountLocked {
+            retry_after: lockout,
+        },
+        Ok(None) => error,
+        Err(e) => e.into(),
+    }
+}
+
+async fn try_record_failure(
+    state: &AppState,
+    ip_address: &str,
+    email: &str,
+    user_id: Option<i32>,
+) -> anyhow::Result<Option<Duration>> {
+    state
+        .smpldb
+        .record_sign_in_attempt(user_id, email, ip_address, false)
+        .await
+        .context("Failed to record sign in attempt")?;
+
+    let window_start = Utc::now() - state.config.sign_in_failure_window;
+    let ip_failures = state
+        .smpldb
+        .recent_ip_failures(ip_address, window_start)
+        .await
+        .context("Failed to count sign in failures for IP address")?;
+    // only record the attempt that crosses the limit, rather than every one after it
+    if ip_failures == state.config.sign_in_max_ip_failures {
+        tracing::warn!(ip_address, ip_failures, "Blocking sign in from IP address");
+        let detail = format!("{ip_failures} failed sign in attempts");
+        state
+            .smpldb
+            .record_security_event(
+                None,
+                SecurityEventKind::IpBlocked,
+                Some(ip_address),
+                Some(&detail),
+            )
+            .await
+            .context("Failed to record security event")?;
+    }
+
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+    let failures = state
+        .smpldb
+        .recent_account_failures(user_id, window_start)
+        .await
+        .context("Failed to count sign in failures for user")?;
+    if failures.count < state.config.sign_in_max_failures {
+        return Ok(None);
+    }
+
+    tracing::warn!(user_id, failures = failures.count, "Locking account");
+    let lockout = state.config.sign_in_lockout;
+    let detail = format!("{} failed sign in attempts", failures.count);
+    state
+        .smpldb
+        .lock_user(user_id, Utc::now() + lockout, ip_address, &detail)
+        .await
+        .context("Failed to lock account")?;
+    Ok(Some(lockout))
+}
+
+/// Records a successful sign in, which resets the account's failure count
+pub(super) async fn record_success(
+    state: &AppState,
+    ip_address: &str,
+    user: &User,
+) -> Result<(), ApiError> {
+    state
+        .smpldb
+        .record_sign_in_attempt(Some(user.id), &user.email, ip_address, true)
+        .await
+        .context("Failed to record sign in attempt")?;
+    Ok(())
+}
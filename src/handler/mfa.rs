@@ -0,0 +1,333 @@
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{
+    db::WalletRef,
+    error::ApiError,
+    utils::{hash_token, issue_mfa_challenge, validate_mfa_challenge, ApiJson, ValidateAuth},
+    AppState,
+};
+
+use super::{lockout, token::issue_token_pair};
+
+/// Shown next to the account in authenticator apps
+const ISSUER: &str = "SmplPayments";
+const STEP_SECS: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn totp(secret: Vec<u8>, account: &str) -> anyhow::Result<TOTP> {
+    // `:` separates the issuer from the account in the otpauth URI
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP_SECS,
+        secret,
+        Some(ISSUER.to_string()),
+        account.replace(':', ""),
+    )
+    .map_err(|e| anyhow::anyhow!("Invalid TOTP parameters: {e}"))
+}
+
+/// Returns the time step of the previous, current or next code if it matches `code`, allowing for
+/// clock drift between the server and the authenticator
+fn matching_step(secret: &str, code: &str) -> anyhow::Result<Option<i64>> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP secret: {e:?}"))?;
+    let totp = totp(secret, "")?;
+    let current = Utc::now().timestamp() as u64 / STEP_SECS;
+    Ok((current - 1..=current + 1)
+        .find(|step| totp.check(code, step * STEP_SECS))
+        .map(|step| step as i64))
+}
+
+/// Random 10 character code, grouped in fives for readability
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{}-{}", &hex[..5], &hex[5..])
+}
+
+/// Hashes a recovery code, ignoring case and grouping
+fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&code)
+}
+
+/// Checks `code` against the user's confirmed TOTP secret, or their recovery codes if
+/// `allow_recovery`. A code is used up once it has been accepted.
+async fn verify_second_factor(
+    state: &AppState,
+    user_id: i32,
+    code: &str,
+    allow_recovery: bool,
+) -> Result<bool, ApiError> {
+    let secret = match state
+        .smpldb
+        .get_totp(user_id)
+        .await
+        .context("Failed to get TOTP")?
+    {
+        Some(totp) if totp.confirmed_at.is_some() => totp.secret,
+        _ => return Ok(false),
+    };
+
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        let Some(step) = matching_step(&secret, code)? else {
+            return Ok(false);
+        };
+        let used = state
+            .smpldb
+            .use_totp_step(user_id, step)
+            .await
+            .context("Failed to use TOTP step")?;
+        return Ok(used);
+    }
+
+    if !allow_recovery {
+        return Ok(false);
+    }
+    let used = state
+        .smpldb
+        .use_recovery_code(user_id, &hash_recovery_code(code))
+        .await
+        .context("Failed to use recovery code")?;
+    if used {
+        tracing::info!(user_id, "Recovery code used");
+    }
+    Ok(used)
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaRequired {
+    mfa_required: bool,
+    /// Exchanged along with a code at `/sign_in/mfa` for an access token
+    mfa_token: String,
+    /// Seconds until `mfa_token` expires
+    expires_in: i64,
+}
+
+/// Response to a correct password from a user with two-factor authentication enabled
+pub(super) fn mfa_challenge(state: &AppState, user_id: i32) -> Result<Response, ApiError> {
+    let ttl = state.config.mfa_challenge_ttl;
+    let mfa_token = issue_mfa_challenge(&state.config.jwt_keys, user_id, ttl)?;
+    let challenge = MfaRequired {
+        mfa_required: true,
+        mfa_token,
+        expires_in: ttl.num_seconds(),
+    };
+    Ok((StatusCode::OK, Json(challenge)).into_response())
+}
+
+/// Rejects a transfer of `amount` from `from_wallet` unless it is under the step up threshold in
+/// the wallet's currency or `code` is a valid TOTP code. Handlers check this after reserving the
+/// idempotency key, so a retry of a request that went through gets its stored response without a
+/// fresh code.
+pub(super) async fn require_step_up(
+    state: &AppState,
+    user_id: i32,
+    from_wallet: &WalletRef,
+    amount: &BigDecimal,
+    code: Option<&str>,
+) -> Result<(), ApiError> {
+    let currency = match from_wallet {
+        WalletRef::Id { currency: None, .. } => {
+            state
+                .smpldb
+                .get_wallet(user_id, from_wallet)
+                .await?
+                .currency
+        }
+        WalletRef::Id {
+            currency: Some(currency),
+            ..
+        }
+        | WalletRef::Default(currency) => currency.clone(),
+    };
+    let Some(threshold) = state.config.step_up_threshold.get(&currency) else {
+        return Ok(());
+    };
+    if amount <= threshold {
+        return Ok(());
+    }
+
+    let totp = state
+        .smpldb
+        .get_totp(user_id)
+        .await
+        .context("Failed to get TOTP")?;
+    if totp.is_none_or(|totp| totp.confirmed_at.is_none()) {
+        return Err(ApiError::MfaEnrolmentRequired);
+    }
+
+    let code = code.ok_or(ApiError::MfaCodeRequired)?;
+    if !verify_second_factor(state, user_id, code, false).await? {
+        return Err(ApiError::InvalidMfaCode);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrolment {
+    /// Base32 encoded secret, for entering into an authenticator by hand
+    secret: String,
+    /// `otpauth://` URI to render as a QR code
+    otpauth_uri: String,
+}
+
+/// generates a TOTP secret for the signed in user, enabled once confirmed with a code
+pub async fn enrol_totp(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+) -> Result<Json<TotpEnrolment>, ApiError> {
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {user_id}"))?;
+
+    let mut secret = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let totp = totp(secret, &user.email)?;
+    let enrolment = TotpEnrolment {
+        secret: totp.get_secret_base32(),
+        otpauth_uri: totp.get_url(),
+    };
+
+    let started = state
+        .smpldb
+        .start_totp_enrolment(user_id, &enrolment.secret)
+        .await
+        .context("Failed to store TOTP secret")?;
+    if !started {
+        return Err(ApiError::MfaAlreadyEnabled);
+    }
+    Ok(Json(enrolment))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCode {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    /// Single use codes accepted in place of a TOTP code when signing in. Only shown once.
+    recovery_codes: Vec<String>,
+}
+
+/// enables two-factor authentication once the user proves their authenticator works
+pub async fn confirm_totp(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiJson(MfaCode { code }): ApiJson<MfaCode>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    let secret = match state
+        .smpldb
+        .get_totp(user_id)
+        .await
+        .context("Failed to get TOTP")?
+    {
+        Some(totp) if totp.confirmed_at.is_none() => totp.secret,
+        _ => return Err(ApiError::NoPendingMfaEnrolment),
+    };
+
+    let step = matching_step(&secret, code.trim())?.ok_or(ApiError::InvalidMfaCode)?;
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+    let confirmed = state
+        .smpldb
+        .confirm_totp(user_id, step, &hashes)
+        .await
+        .context("Failed to confirm TOTP")?;
+    if !confirmed {
+        return Err(ApiError::NoPendingMfaEnrolment);
+    }
+    tracing::info!(user_id, "Two-factor authentication enabled");
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// disables two-factor authentication, given a TOTP or recovery code
+pub async fn disable_totp(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiJson(MfaCode { code }): ApiJson<MfaCode>,
+) -> Result<StatusCode, ApiError> {
+    if !verify_second_factor(&state, user_id, &code, true).await? {
+        return Err(ApiError::InvalidMfaCode);
+    }
+
+    state
+        .smpldb
+        .disable_totp(user_id)
+        .await
+        .context("Failed to disable TOTP")?;
+    tracing::info!(user_id, "Two-factor authentication disabled");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignInMfa {
+    mfa_token: String,
+    /// TOTP or recovery code
+    code: String,
+}
+
+/// second step of signing in for users with two-factor authentication
+pub async fn sign_in_mfa(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    ApiJson(SignInMfa { mfa_token, code }): ApiJson<SignInMfa>,
+) -> Result<Response, ApiError> {
+    let user_id = validate_mfa_challenge(&state.config.jwt_keys, &mfa_token)?;
+
+    let ip_address = addr.ip().to_string();
+    lockout::check_ip(&state, &ip_address).await?;
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {user_id}"))?;
+    lockout::check_account(&state, &user).await?;
+
+    if !verify_second_factor(&state, user_id, &code, true).await? {
+        tracing::info!(user_id, "Failed two-factor attempt");
+        let error = ApiError::InvalidMfaCode;
+        return Err(lockout::record_failure(
+            &state,
+            &ip_address,
+            &user.email,
+            Some(user_id),
+            error,
+        )
+        .await);
+    }
+
+    lockout::record_success(&state, &ip_address, &user).await?;
+    issue_token_pair(&state, &user).await
+}
@@ -1,36 +1,107 @@
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-};
+use anyhow::Context;
 use email_address::EmailAddress;
 
+use crate::{config::MoneyMovement, db::WalletRef, error::ApiError, AppState};
+
+pub mod admin;
+pub mod currency;
+pub mod fx;
+pub mod hold;
+mod idempotency;
+mod lockout;
+pub mod mfa;
+pub mod password;
+pub mod payment_request;
 pub mod profile;
+pub mod scheduled_payment;
+pub mod screening;
 pub mod sign_in;
+pub mod sign_out;
 pub mod sign_up;
+pub mod spending_limit;
+pub mod token;
 pub mod transaction;
+pub mod transfer_review;
+pub mod verify_email;
 pub mod wallet;
 
-fn validate_email(email: &str) -> Option<Response> {
+fn validate_email(email: &str) -> Result<(), ApiError> {
     if !EmailAddress::is_valid(email) {
-        return Some((StatusCode::BAD_REQUEST, "Invalid Email Address").into_response());
+        return Err(ApiError::InvalidEmail);
     }
-    None
+    Ok(())
 }
 
-fn validate_n_hash_password(password: &str) -> Result<String, Response> {
+/// Rejects moving money if the user hasn't verified their email and the unverified account policy
+/// doesn't allow it
+async fn require_verified_email(
+    state: &AppState,
+    user_id: i32,
+    movement: MoneyMovement,
+) -> Result<(), ApiError> {
+    if state.config.unverified_policy.allows(movement) {
+        return Ok(());
+    }
+
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {user_id}"))?;
+    if user.email_verified_at.is_none() {
+        return Err(ApiError::EmailNotVerified);
+    }
+    Ok(())
+}
+
+/// Rejects moving money to `username` if they haven't verified their email and the unverified
+/// account policy doesn't allow them to receive it. Unknown users are left for the DB to reject.
+async fn require_verified_recipient(state: &AppState, username: &str) -> Result<(), ApiError> {
+    if state
+        .config
+        .unverified_policy
+        .allows(MoneyMovement::Receive)
+    {
+        return Ok(());
+    }
+
+    let recipient = state
+        .smpldb
+        .get_user_by_username(username)
+        .await
+        .context("Failed to get recipient")?;
+    if recipient.is_some_and(|r| r.email_verified_at.is_none()) {
+        return Err(ApiError::RecipientNotVerified);
+    }
+    Ok(())
+}
+
+/// The currency the request asked for, or `DEFAULT_CURRENCY` if it didn't
+fn currency_or_default(state: &AppState, currency: Option<String>) -> String {
+    currency
+        .map(|c| c.to_ascii_uppercase())
+        .unwrap_or_else(|| state.config.default_currency.clone())
+}
+
+/// The user's wallet with `wallet_id`, which must be in `currency` if one is given, or else their
+/// default wallet in `currency`
+fn wallet_ref(state: &AppState, wallet_id: Option<i32>, currency: Option<String>) -> WalletRef {
+    match wallet_id {
+        Some(id) => WalletRef::Id {
+            id,
+            currency: currency.map(|c| c.to_ascii_uppercase()),
+        },
+        None => WalletRef::Default(currency_or_default(state, currency)),
+    }
+}
+
+fn validate_n_hash_password(password: &str) -> Result<String, ApiError> {
     // validate password
     if password.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "Empty password not allowed").into_response());
+        return Err(ApiError::EmptyPassword);
     }
 
     // hash password
-    match pwhash::bcrypt::hash(password) {
-        Ok(p) => Ok(p),
-        Err(e) => {
-            tracing::error!(?e, "Error hashing password");
-            Err(
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-            )
-        }
-    }
+    let hash = pwhash::bcrypt::hash(password).context("Error hashing password")?;
+    Ok(hash)
 }
@@ -0,0 +1,140 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{
+    db::TokenPurpose,
+    error::ApiError,
+    utils::{generate_token, hash_token, ApiJson, ValidateAuth},
+    AppState,
+};
+
+use super::{validate_email, validate_n_hash_password};
+
+/// Revokes every access and refresh token of the user, so old sessions can't outlive a password
+async fn revoke_sessions(state: &AppState, user_id: i32) -> Result<(), ApiError> {
+    let valid_after = state
+        .smpldb
+        .revoke_all_tokens(user_id)
+        .await
+        .context("Failed to revoke all tokens for user")?;
+    state.revocations.mark_all_revoked(user_id, valid_after);
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    current_password: String,
+    new_password: String,
+}
+
+/// changes the password of the signed in user and signs them out everywhere
+pub async fn change_password(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiJson(ChangePassword {
+        current_password,
+        new_password,
+    }): ApiJson<ChangePassword>,
+) -> Result<StatusCode, ApiError> {
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {user_id}"))?;
+
+    if !pwhash::bcrypt::verify(current_password, &user.password) {
+        tracing::info!(user_id, "Failed password change attempt");
+        return Err(ApiError::InvalidCredentials);
+    }
+
+    let password = validate_n_hash_password(&new_password)?;
+    state
+        .smpldb
+        .update_password(user_id, &password)
+        .await
+        .context("Failed to update password")?;
+
+    revoke_sessions(&state, user_id).await?;
+    tracing::info!(user_id, "Password changed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestPasswordReset {
+    email: String,
+}
+
+/// sends a password reset token to the email, if it belongs to a user.
+/// Always accepted, so it can't be used to find out which emails are registered.
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    ApiJson(RequestPasswordReset { email }): ApiJson<RequestPasswordReset>,
+) -> Result<StatusCode, ApiError> {
+    validate_email(&email)?;
+
+    let Some(user) = state
+        .smpldb
+        .get_user(&email)
+        .await
+        .context("Failed to get user")?
+    else {
+        tracing::info!(email, "Password reset requested for unknown email");
+        return Ok(StatusCode::ACCEPTED);
+    };
+
+    let token = generate_token();
+    let expires_at = Utc::now() + state.config.password_reset_ttl;
+    state
+        .smpldb
+        .create_user_token(
+            user.id,
+            TokenPurpose::PasswordReset,
+            &hash_token(&token),
+            expires_at,
+        )
+        .await
+        .context("Failed to store password reset token")?;
+
+    let body = format!(
+        "Use this token to reset your password, it expires in {} minutes:\n\n{token}\n\n\
+        If you did not ask to reset your password you can ignore this message.",
+        state.config.password_reset_ttl.num_minutes()
+    );
+    state
+        .notifier
+        .send(&user.email, "Reset your password", &body)
+        .await
+        .context("Failed to send password reset token")?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmPasswordReset {
+    token: String,
+    new_password: String,
+}
+
+/// sets a new password with a password reset token and signs the user out everywhere
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    ApiJson(ConfirmPasswordReset {
+        token,
+        new_password,
+    }): ApiJson<ConfirmPasswordReset>,
+) -> Result<StatusCode, ApiError> {
+    let password = validate_n_hash_password(&new_password)?;
+
+    let user_id = state
+        .smpldb
+        .reset_password(&hash_token(&token), &password)
+        .await
+        .context("Failed to reset password")?
+        .ok_or(ApiError::InvalidOrExpiredToken)?;
+
+    revoke_sessions(&state, user_id).await?;
+    tracing::info!(user_id, "Password reset");
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,266 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Response, Json};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::MoneyMovement,
+    db::{
+        models::{PaymentRequest, PaymentRequestStatus},
+        PaymentRequestWithUsers, RequestDirection,
+    },
+    error::ApiError,
+    utils::{ApiJson, ApiPath, ApiQuery, IdempotencyKey, OptionalApiJson, TotpCode, ValidateAuth},
+    AppState,
+};
+
+use super::{
+    currency_or_default,
+    idempotency::{self, Begun},
+    mfa::require_step_up,
+    require_verified_email, require_verified_recipient,
+    screening::screen_transfer,
+    transfer_review::{log_outcome, respond_to_payment},
+    wallet_ref,
+};
+
+const MAX_NOTE_LENGTH: usize = 255;
+
+#[derive(Debug, Serialize)]
+pub struct FormattedPaymentRequest {
+    id: i32,
+    requester_username: String,
+    payer_username: String,
+    amount: BigDecimal,
+    currency: String,
+    note: Option<String>,
+    status: PaymentRequestStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_id: Option<i32>,
+    expires_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<PaymentRequestWithUsers> for FormattedPaymentRequest {
+    fn from((request, requester_username, payer_username): PaymentRequestWithUsers) -> Self {
+        let PaymentRequest {
+            id,
+            amount,
+            currency,
+            note,
+            status,
+            transaction_id,
+            expires_at,
+            created_at,
+            updated_at,
+            ..
+        } = request;
+        Self {
+            id,
+            requester_username,
+            payer_username,
+            amount,
+            currency,
+            note,
+            status,
+            transaction_id,
+            expires_at,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePaymentRequest {
+    payer_username: String,
+    amount: BigDecimal,
+    /// Defaults to `DEFAULT_CURRENCY`. The requester needs a wallet in it.
+    currency: Option<String>,
+    note: Option<String>,
+}
+
+/// asks another user to pay the user
+pub async fn create_payment_request(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<CreatePaymentRequest>,
+) -> Result<(StatusCode, Json<FormattedPaymentRequest>), ApiError> {
+    if request.amount <= BigDecimal::from(0) {
+        return Err(ApiError::InvalidAmount);
+    }
+    let note = request
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if note.is_some_and(|n| n.chars().count() > MAX_NOTE_LENGTH) {
+        return Err(ApiError::InvalidNote);
+    }
+
+    let currency = currency_or_default(&state, request.currency);
+    let expires_at = Utc::now() + state.config.payment_request_ttl;
+    let created = state
+        .smpldb
+        .create_payment_request(
+            user_id,
+            &request.payer_username,
+            request.amount,
+            &currency,
+            note,
+            expires_at,
+        )
+        .await?;
+
+    let request = state
+        .smpldb
+        .get_payment_request(user_id, created.id)
+        .await?;
+    Ok((StatusCode::CREATED, Json(request.into())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListPaymentRequests {
+    direction: Option<RequestDirection>,
+    status: Option<PaymentRequestStatus>,
+}
+
+/// lists requests the user made or was asked to pay, newest first
+pub async fn list_payment_requests(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ListPaymentRequests>,
+) -> Result<Json<Vec<FormattedPaymentRequest>>, ApiError> {
+    let requests = state
+        .smpldb
+        .list_payment_requests(user_id, query.direction, query.status)
+        .await?;
+    Ok(Json(requests.into_iter().map(Into::into).collect()))
+}
+
+pub async fn get_payment_request(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(request_id): ApiPath<i32>,
+) -> Result<Json<FormattedPaymentRequest>, ApiError> {
+    let request = state
+        .smpldb
+        .get_payment_request(user_id, request_id)
+        .await?;
+    Ok(Json(request.into()))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AcceptPaymentRequest {
+    /// Defaults to the user's default wallet in the request's currency
+    from_wallet_id: Option<i32>,
+}
+
+/// pays a request addressed to the user
+pub async fn accept_payment_request(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    TotpCode(totp_code): TotpCode,
+    State(state): State<AppState>,
+    ApiPath(request_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<AcceptPaymentRequest>,
+) -> Result<Response, ApiError> {
+    let key = idempotency_key.as_deref();
+    let route = format!("POST /payment_requests/{request_id}/accept");
+    let reservation = match idempotency::begin(&state, user_id, key, &route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let result = async {
+        require_verified_email(&state, user_id, MoneyMovement::Send).await?;
+        let (payment_request, requester_username, _) = state
+            .smpldb
+            .get_payment_request(user_id, request_id)
+            .await?;
+        require_verified_recipient(&state, &requester_username).await?;
+        let sender = state
+            .smpldb
+            .get_user_by_id(user_id)
+            .await?
+            .with_context(|| format!("Failed to find user with id {user_id}"))?;
+        screen_transfer(&state, &sender, &requester_username).await?;
+
+        let from_wallet = wallet_ref(
+            &state,
+            request.from_wallet_id,
+            Some(payment_request.currency),
+        );
+        require_step_up(
+            &state,
+            user_id,
+            &from_wallet,
+            &payment_request.amount,
+            totp_code.as_deref(),
+        )
+        .await?;
+
+        Ok::<_, ApiError>(
+            state
+                .smpldb
+                .accept_payment_request(
+                    user_id,
+                    request_id,
+                    &from_wallet,
+                    state.risk.as_ref(),
+                    reservation.store(&respond_to_payment),
+                )
+                .await?,
+        )
+    }
+    .await;
+
+    match result {
+        Ok(outcome) => {
+            log_outcome(user_id, &outcome);
+            respond_to_payment(&outcome)
+                .map(idempotency::response)
+                .ok_or(ApiError::TransferBlocked)
+        }
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e)
+        }
+    }
+}
+
+/// refuses a request addressed to the user
+pub async fn decline_payment_request(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(request_id): ApiPath<i32>,
+) -> Result<Json<FormattedPaymentRequest>, ApiError> {
+    state
+        .smpldb
+        .decline_payment_request(user_id, request_id)
+        .await?;
+    let request = state
+        .smpldb
+        .get_payment_request(user_id, request_id)
+        .await?;
+    Ok(Json(request.into()))
+}
+
+/// withdraws a request the user made
+pub async fn cancel_payment_request(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(request_id): ApiPath<i32>,
+) -> Result<Json<FormattedPaymentRequest>, ApiError> {
+    state
+        .smpldb
+        .cancel_payment_request(user_id, request_id)
+        .await?;
+    let request = state
+        .smpldb
+        .get_payment_request(user_id, request_id)
+        .await?;
+    Ok(Json(request.into()))
+}
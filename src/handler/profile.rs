@@ -1,27 +1,24 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use anyhow::Context;
+use axum::{extract::State, Json};
 use serde::Deserialize;
 
-use crate::{db, utils::ValidateAuth, AppState};
+use crate::{
+    db::{self, models::User},
+    error::ApiError,
+    utils::{ApiJson, ValidateAuth},
+    AppState,
+};
 
 pub async fn get_profile(
     ValidateAuth(user_id): ValidateAuth,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    match state.smpldb.get_user_by_id(user_id).await {
-        Ok(Some(user)) => (StatusCode::OK, Json(user)).into_response(),
-        Ok(None) => {
-            tracing::error!(user_id, "Failed to find user with id");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "INTERNAL SERVER ERROR: user not found",
-            )
-                .into_response()
-        }
-        Err(e) => {
-            tracing::error!(?e, "Failed to get user");
-            (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL SERVER ERROR").into_response()
-        }
-    }
+) -> Result<Json<User>, ApiError> {
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {user_id}"))?;
+    Ok(Json(user))
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,26 +29,16 @@ pub struct UpdateProfile {
 pub async fn update_profile(
     ValidateAuth(user_id): ValidateAuth,
     State(state): State<AppState>,
-    Json(UpdateProfile { username }): Json<UpdateProfile>,
-) -> impl IntoResponse {
+    ApiJson(UpdateProfile { username }): ApiJson<UpdateProfile>,
+) -> Result<Json<User>, ApiError> {
     if username.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Username cannot be empty").into_response();
+        return Err(ApiError::EmptyUsername);
     }
 
     match state.smpldb.update_username(user_id, &username).await {
-        Ok(Some(user)) => (StatusCode::OK, Json(user)).into_response(),
-        Ok(None) => {
-            tracing::error!(user_id, "Failed to find user with id");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "INTERNAL SERVER ERROR: user not found",
-            )
-                .into_response()
-        }
-        Err(db::Error::Duplicate) => (StatusCode::BAD_REQUEST, "Username Taken").into_response(),
-        Err(e) => {
-            tracing::error!(?e, "Failed to get user");
-            (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL SERVER ERROR").into_response()
-        }
+        Ok(Some(user)) => Ok(Json(user)),
+        Ok(None) => Err(anyhow::anyhow!("Failed to find user with id {user_id}").into()),
+        Err(db::Error::Duplicate) => Err(ApiError::UsernameTaken),
+        Err(e) => Err(e.into()),
     }
 }
@@ -0,0 +1,269 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Response, Json};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::MoneyMovement,
+    db::{
+        models::{ScheduleFrequency, ScheduleStatus, ScheduledPayment},
+        Schedule, ScheduledPaymentWithRecipient,
+    },
+    error::ApiError,
+    utils::{ApiJson, ApiPath, ApiQuery, IdempotencyKey, TotpCode, ValidateAuth},
+    AppState,
+};
+
+use super::{
+    idempotency::{self, Begun},
+    mfa::require_step_up,
+    require_verified_email, require_verified_recipient,
+    screening::screen_transfer,
+    wallet_ref,
+};
+
+#[derive(Debug, Serialize)]
+pub struct FormattedScheduledPayment {
+    id: i32,
+    to_username: String,
+    wallet_id: i32,
+    amount: BigDecimal,
+    currency: String,
+    frequency: ScheduleFrequency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cron: Option<String>,
+    status: ScheduleStatus,
+    starts_at: DateTime<Utc>,
+    ends_at: Option<DateTime<Utc>>,
+    next_run_at: DateTime<Utc>,
+    failed_attempts: i32,
+    last_run_at: Option<DateTime<Utc>>,
+    last_transaction_id: Option<i32>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<ScheduledPaymentWithRecipient> for FormattedScheduledPayment {
+    fn from((schedule, to_username): ScheduledPaymentWithRecipient) -> Self {
+        let ScheduledPayment {
+            id,
+            wallet_id,
+            amount,
+            currency,
+            frequency,
+            cron,
+            status,
+            starts_at,
+            ends_at,
+            next_run_at,
+            failed_attempts,
+            last_run_at,
+            last_transaction_id,
+            last_error,
+            created_at,
+            updated_at,
+            ..
+        } = schedule;
+        Self {
+            id,
+            to_username,
+            wallet_id,
+            amount,
+            currency,
+            frequency,
+            cron,
+            status,
+            starts_at,
+            ends_at,
+            next_run_at,
+            failed_attempts,
+            last_run_at,
+            last_transaction_id,
+            last_error,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateScheduledPayment {
+    to_username: String,
+    amount: BigDecimal,
+    /// Defaults to the currency of `from_wallet_id`, or `DEFAULT_CURRENCY`
+    currency: Option<String>,
+    /// Defaults to the user's default wallet in `currency`
+    from_wallet_id: Option<i32>,
+    frequency: ScheduleFrequency,
+    /// Five field cron pattern, in UTC, for a `cron` schedule
+    cron: Option<String>,
+    /// Defaults to now
+    starts_at: Option<DateTime<Utc>>,
+    ends_at: Option<DateTime<Utc>>,
+}
+
+/// schedules a transfer for later, or a recurring one
+pub async fn create_scheduled_payment(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    TotpCode(totp_code): TotpCode,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<CreateScheduledPayment>,
+) -> Result<Response, ApiError> {
+    if request.amount <= BigDecimal::from(0) {
+        return Err(ApiError::InvalidAmount);
+    }
+    let now = Utc::now();
+    let starts_at = request.starts_at.unwrap_or(now);
+    if request.starts_at.is_some_and(|s| s < now) || request.ends_at.is_some_and(|e| e < starts_at)
+    {
+        return Err(ApiError::InvalidSchedule);
+    }
+
+    let key = idempotency_key.as_deref();
+    let route = "POST /scheduled_payments";
+    let reservation = match idempotency::begin(&state, user_id, key, route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let CreateScheduledPayment {
+        to_username,
+        amount,
+        currency,
+        from_wallet_id,
+        frequency,
+        cron,
+        ends_at,
+        ..
+    } = request;
+    let from_wallet = wallet_ref(&state, from_wallet_id, currency);
+    let checked = async {
+        require_verified_email(&state, user_id, MoneyMovement::Send).await?;
+        require_verified_recipient(&state, &to_username).await?;
+        let sender = state
+            .smpldb
+            .get_user_by_id(user_id)
+            .await?
+            .with_context(|| format!("Failed to find user with id {user_id}"))?;
+        screen_transfer(&state, &sender, &to_username).await?;
+        // scheduling authorises the payments, so it needs the same step up as making one
+        require_step_up(&state, user_id, &from_wallet, &amount, totp_code.as_deref()).await
+    }
+    .await;
+    if let Err(e) = checked {
+        reservation.abort(&state).await;
+        return Err(e);
+    }
+    let schedule = Schedule {
+        frequency,
+        cron: cron.map(|c| c.trim().to_string()),
+        starts_at,
+        ends_at,
+    };
+    let respond = |created: &ScheduledPayment| {
+        let schedule = FormattedScheduledPayment::from((created.clone(), to_username.clone()));
+        Some(idempotency::json(StatusCode::CREATED, &schedule))
+    };
+    match state
+        .smpldb
+        .create_scheduled_payment(
+            user_id,
+            &from_wallet,
+            &to_username,
+            amount,
+            &schedule,
+            reservation.store(&respond),
+        )
+        .await
+    {
+        Ok(created) => {
+            let schedule = FormattedScheduledPayment::from((created, to_username));
+            Ok(idempotency::response(idempotency::json(
+                StatusCode::CREATED,
+                &schedule,
+            )))
+        }
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListScheduledPayments {
+    status: Option<ScheduleStatus>,
+}
+
+/// lists the user's scheduled payments, newest first
+pub async fn list_scheduled_payments(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ListScheduledPayments>,
+) -> Result<Json<Vec<FormattedScheduledPayment>>, ApiError> {
+    let schedules = state
+        .smpldb
+        .list_scheduled_payments(user_id, query.status)
+        .await?;
+    Ok(Json(schedules.into_iter().map(Into::into).collect()))
+}
+
+pub async fn get_scheduled_payment(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(schedule_id): ApiPath<i32>,
+) -> Result<Json<FormattedScheduledPayment>, ApiError> {
+    let schedule = state
+        .smpldb
+        .get_scheduled_payment(user_id, schedule_id)
+        .await?;
+    Ok(Json(schedule.into()))
+}
+
+/// sets the status of a scheduled payment and returns it
+async fn set_status(
+    state: &AppState,
+    user_id: i32,
+    schedule_id: i32,
+    status: ScheduleStatus,
+) -> Result<Json<FormattedScheduledPayment>, ApiError> {
+    state
+        .smpldb
+        .set_scheduled_payment_status(user_id, schedule_id, status)
+        .await?;
+    let schedule = state
+        .smpldb
+        .get_scheduled_payment(user_id, schedule_id)
+        .await?;
+    Ok(Json(schedule.into()))
+}
+
+/// stops making payments until resumed
+pub async fn pause_scheduled_payment(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(schedule_id): ApiPath<i32>,
+) -> Result<Json<FormattedScheduledPayment>, ApiError> {
+    set_status(&state, user_id, schedule_id, ScheduleStatus::Paused).await
+}
+
+/// starts making payments again, skipping any that were due while paused
+pub async fn resume_scheduled_payment(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(schedule_id): ApiPath<i32>,
+) -> Result<Json<FormattedScheduledPayment>, ApiError> {
+    set_status(&state, user_id, schedule_id, ScheduleStatus::Active).await
+}
+
+/// stops making payments for good
+pub async fn cancel_scheduled_payment(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(schedule_id): ApiPath<i32>,
+) -> Result<Json<FormattedScheduledPayment>, ApiError> {
+    set_status(&state, user_id, schedule_id, ScheduleStatus::Cancelled).await
+}
@@ -0,0 +1,214 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        models::{ScreeningContext, ScreeningHit, ScreeningStatus, User},
+        ScreeningHitWithUser, WatchlistMatch,
+    },
+    error::ApiError,
+    permission::CanReviewScreening,
+    utils::{ApiPath, ApiQuery, OptionalApiJson, RequirePermission},
+    AppState,
+};
+
+const MAX_NOTE_LENGTH: usize = 255;
+
+/// Screens the user's username and legal name against the watchlist, recording any hits. Users
+/// with a confirmed hit are stopped when the transfer is made.
+pub(super) async fn screen_user(
+    state: &AppState,
+    user: &User,
+    context: ScreeningContext,
+) -> Result<(), ApiError> {
+    let mut names = vec![user.username.as_str()];
+    names.extend(user.legal_name.as_deref());
+    let matches = state.watchlist.screen(&names);
+    record_hits(state, user.id, context, &matches).await
+}
+
+/// Screens both parties to a transfer again, as the watchlist may have changed since they signed
+/// up. Confirmed hits stop the transfer when it is made.
+pub(super) async fn screen_transfer(
+    state: &AppState,
+    sender: &User,
+    to_username: &str,
+) -> Result<(), ApiError> {
+    screen_user(state, sender, ScreeningContext::Transfer).await?;
+    if to_username == sender.username {
+        return Ok(());
+    }
+    let recipient = state
+        .smpldb
+        .get_user_by_username(to_username)
+        .await
+        .context("Failed to get recipient")?;
+    if let Some(recipient) = &recipient {
+        screen_user(state, recipient, ScreeningContext::Transfer).await?;
+    }
+    Ok(())
+}
+
+/// Records the matches against the user
+pub(super) async fn record_hits(
+    state: &AppState,
+    user_id: i32,
+    context: ScreeningContext,
+    matches: &[WatchlistMatch],
+) -> Result<(), ApiError> {
+    let hits = state
+        .smpldb
+        .record_screening_hits(Some(user_id), context, matches)
+        .await?;
+    for hit in hits {
+        tracing::warn!(
+            user_id,
+            hit_id = hit.id,
+            entry_id = hit.entry_id,
+            status = hit.status.as_str(),
+            "Watchlist screening hit"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct FormattedScreeningHit {
+    id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    context: ScreeningContext,
+    screened_name: String,
+    entry_id: String,
+    entry_name: String,
+    score: f64,
+    status: ScreeningStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewer_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewed_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<ScreeningHitWithUser> for FormattedScreeningHit {
+    fn from((hit, username): ScreeningHitWithUser) -> Self {
+        let ScreeningHit {
+            id,
+            user_id,
+            context,
+            screened_name,
+            entry_id,
+            entry_name,
+            score,
+            status,
+            reviewer_id,
+            note,
+            reviewed_at,
+            created_at,
+            updated_at,
+        } = hit;
+        Self {
+            id,
+            user_id,
+            username,
+            context,
+            screened_name,
+            entry_id,
+            entry_name,
+            score,
+            status,
+            reviewer_id,
+            note,
+            reviewed_at,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListScreeningHits {
+    /// Defaults to `pending`
+    status: Option<ScreeningStatus>,
+}
+
+/// lists screening hits in a status, oldest first
+pub async fn list_screening_hits(
+    _: RequirePermission<CanReviewScreening>,
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ListScreeningHits>,
+) -> Result<Json<Vec<FormattedScreeningHit>>, ApiError> {
+    let status = query.status.unwrap_or(ScreeningStatus::Pending);
+    let hits = state.smpldb.screening_hit_queue(status).await?;
+    Ok(Json(hits.into_iter().map(Into::into).collect()))
+}
+
+pub async fn get_screening_hit(
+    _: RequirePermission<CanReviewScreening>,
+    State(state): State<AppState>,
+    ApiPath(hit_id): ApiPath<i32>,
+) -> Result<Json<FormattedScreeningHit>, ApiError> {
+    Ok(Json(state.smpldb.get_screening_hit(hit_id).await?.into()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReviewScreeningHit {
+    note: Option<String>,
+}
+
+/// records that the user is the listed party, stopping their transfers
+pub async fn confirm_screening_hit(
+    RequirePermission(admin_id, _): RequirePermission<CanReviewScreening>,
+    State(state): State<AppState>,
+    ApiPath(hit_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<ReviewScreeningHit>,
+) -> Result<Json<FormattedScreeningHit>, ApiError> {
+    review(state, admin_id, hit_id, ScreeningStatus::Confirmed, request).await
+}
+
+/// records that the hit is a false positive, allowing the user's transfers again if it was
+/// confirmed
+pub async fn dismiss_screening_hit(
+    RequirePermission(admin_id, _): RequirePermission<CanReviewScreening>,
+    State(state): State<AppState>,
+    ApiPath(hit_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<ReviewScreeningHit>,
+) -> Result<Json<FormattedScreeningHit>, ApiError> {
+    review(state, admin_id, hit_id, ScreeningStatus::Dismissed, request).await
+}
+
+async fn review(
+    state: AppState,
+    admin_id: i32,
+    hit_id: i32,
+    status: ScreeningStatus,
+    request: ReviewScreeningHit,
+) -> Result<Json<FormattedScreeningHit>, ApiError> {
+    let note = request
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if note.is_some_and(|n| n.chars().count() > MAX_NOTE_LENGTH) {
+        return Err(ApiError::InvalidNote);
+    }
+
+    state
+        .smpldb
+        .review_screening_hit(admin_id, hit_id, status, note)
+        .await?;
+    tracing::info!(
+        admin_id,
+        hit_id,
+        status = status.as_str(),
+        "Reviewed screening hit"
+    );
+    Ok(Json(state.smpldb.get_screening_hit(hit_id).await?.into()))
+}
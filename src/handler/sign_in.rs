@@ -1,9 +1,15 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, State},
+    response::Response,
+};
 use serde::Deserialize;
 
-use crate::{handler::validate_n_hash_password, utils::issue_new_jwt, AppState};
+use crate::{db::models::AccountStatus, error::ApiError, utils::ApiJson, AppState};
 
-use super::validate_email;
+use super::{lockout, mfa::mfa_challenge, token::issue_token_pair, validate_email};
 
 #[derive(Debug, Deserialize)]
 pub struct SignIn {
@@ -12,36 +18,55 @@ pub struct SignIn {
 }
 
 pub async fn sign_in(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
-    Json(SignIn { email, password }): Json<SignIn>,
-) -> impl IntoResponse {
+    ApiJson(SignIn { email, password }): ApiJson<SignIn>,
+) -> Result<Response, ApiError> {
     // validate email
-    if let Some(r) = validate_email(&email) {
-        return r;
-    };
+    validate_email(&email)?;
 
-    let password = match validate_n_hash_password(&password) {
-        Ok(h) => h,
-        Err(r) => return r,
-    };
+    if password.is_empty() {
+        return Err(ApiError::EmptyPassword);
+    }
+
+    let ip_address = addr.ip().to_string();
+    lockout::check_ip(&state, &ip_address).await?;
 
     // check db
-    let user = match state.smpldb.get_user(&email).await {
-        Ok(Some(u)) => u,
-        Ok(None) => return (StatusCode::GONE, "Incorrect email or password").into_response(),
-        Err(e) => {
-            tracing::error!(?e, "Failed to get user");
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
-        }
+    let Some(user) = state
+        .smpldb
+        .get_user(&email)
+        .await
+        .context("Failed to get user")?
+    else {
+        let error = ApiError::InvalidCredentials;
+        return Err(lockout::record_failure(&state, &ip_address, &email, None, error).await);
     };
 
-    if pwhash::bcrypt::verify(password, &user.password) {
+    lockout::check_account(&state, &user).await?;
+
+    if !pwhash::bcrypt::verify(password, &user.password) {
         tracing::info!(email, "Failed password attempt");
-        return (StatusCode::UNAUTHORIZED, "Incorrect email or Password").into_response();
+        let error = ApiError::InvalidCredentials;
+        return Err(
+            lockout::record_failure(&state, &ip_address, &email, Some(user.id), error).await,
+        );
+    }
+
+    if user.status == AccountStatus::Closed {
+        return Err(ApiError::AccountClosed);
     }
 
-    match issue_new_jwt(user.id) {
-        Ok(token) => (StatusCode::OK, token).into_response(),
-        Err(r) => r,
+    let totp = state
+        .smpldb
+        .get_totp(user.id)
+        .await
+        .context("Failed to get TOTP")?;
+    if totp.is_some_and(|totp| totp.confirmed_at.is_some()) {
+        // the attempt is recorded once the second factor is checked
+        return mfa_challenge(&state, user.id);
     }
+
+    lockout::record_success(&state, &ip_address, &user).await?;
+    issue_token_pair(&state, &user).await
 }
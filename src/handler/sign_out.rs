@@ -0,0 +1,60 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    error::ApiError,
+    utils::{hash_token, AuthToken, OptionalApiJson},
+    AppState,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SignOut {
+    /// Refresh token issued alongside the access token, revoked with it
+    refresh_token: Option<String>,
+    /// Revoke every token issued to the user, on every device
+    #[serde(default)]
+    everywhere: bool,
+}
+
+/// revokes the presented access token, or all of the user's tokens
+pub async fn sign_out(
+    claims: AuthToken,
+    State(state): State<AppState>,
+    OptionalApiJson(SignOut {
+        refresh_token,
+        everywhere,
+    }): OptionalApiJson<SignOut>,
+) -> Result<StatusCode, ApiError> {
+    let user_id = claims.id;
+
+    if everywhere {
+        let valid_after = state
+            .smpldb
+            .revoke_all_tokens(user_id)
+            .await
+            .context("Failed to revoke all tokens for user")?;
+        state.revocations.mark_all_revoked(user_id, valid_after);
+        tracing::info!(user_id, "Signed out everywhere");
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let expires_at = DateTime::from_timestamp(claims.expires_at, 0).unwrap_or_else(Utc::now);
+    state
+        .smpldb
+        .revoke_token(user_id, &claims.jti, expires_at)
+        .await
+        .context("Failed to revoke token")?;
+    state.revocations.mark_revoked(&claims.jti);
+
+    if let Some(refresh_token) = refresh_token {
+        state
+            .smpldb
+            .revoke_refresh_token_family(user_id, &hash_token(&refresh_token))
+            .await
+            .context("Failed to revoke refresh token")?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,76 +1,103 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, http::StatusCode, Json};
 
 use serde::Deserialize;
 
-use crate::{handler::validate_email, AppState};
+use crate::{
+    db::{
+        self,
+        models::{ScreeningContext, User},
+    },
+    error::ApiError,
+    handler::validate_email,
+    utils::ApiJson,
+    AppState,
+};
+
+use super::{screening::record_hits, verify_email::send_verification_email};
+
+const MAX_LEGAL_NAME_LENGTH: usize = 255;
 
 #[derive(Debug, Deserialize)]
 pub struct CreateUser {
     pub username: String,
     pub email: String,
     pub password: String,
+    /// Screened against the watchlist along with the username
+    pub legal_name: Option<String>,
 }
 
 /// creates a new user
 pub async fn sign_up(
     State(state): State<AppState>,
-    Json(CreateUser {
+    ApiJson(CreateUser {
         username,
         email,
-        mut password,
-    }): Json<CreateUser>,
-) -> impl IntoResponse {
+        password,
+        legal_name,
+    }): ApiJson<CreateUser>,
+) -> Result<(StatusCode, Json<User>), ApiError> {
     // validate email
-    if let Some(r) = validate_email(&email) {
-        return r;
-    };
+    validate_email(&email)?;
 
     // validate username
     if username.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Empty username not allowed").into_response();
+        return Err(ApiError::EmptyUsername);
     }
 
-    // validate password
-    if password.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Empty password not allowed").into_response();
+    let legal_name = legal_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if legal_name.is_some_and(|n| n.chars().count() > MAX_LEGAL_NAME_LENGTH) {
+        return Err(ApiError::InvalidLegalName);
     }
 
-    // hash password
-    password = match pwhash::bcrypt::hash(password) {
-        Ok(p) => p,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-        }
-    };
+    // validate and hash password
+    let password = super::validate_n_hash_password(&password)?;
+
+    // confirmed matches are blocked before the account exists, others are recorded once it does
+    let mut names = vec![username.as_str()];
+    names.extend(legal_name);
+    let matches = state.watchlist.screen(&names);
+    if matches.iter().any(|m| m.confirmed) {
+        state
+            .smpldb
+            .record_screening_hits(None, ScreeningContext::SignUp, &matches)
+            .await?;
+        tracing::warn!(username, email, "Blocked sign up matching the watchlist");
+        return Err(ApiError::ScreeningMatch);
+    }
 
     tracing::info!(username, email, "Creating user");
     // insert to db
     let user = match state
         .smpldb
-        .sign_up_user(&username, &email, &password)
+        .sign_up_user(&username, &email, &password, legal_name)
         .await
     {
         Ok(u) => {
             tracing::info!(username, email, "Created user");
             u
         }
-        Err(crate::db::Error::Duplicate) => {
-            return (StatusCode::BAD_REQUEST, "Username or Email Taken").into_response();
-        }
-        Err(e) => {
-            tracing::error!(?e, username, email, "Error creating user");
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#?}")).into_response();
-        }
+        Err(db::Error::Duplicate) => return Err(ApiError::UsernameOrEmailTaken),
+        Err(e) => return Err(e.into()),
     };
 
-    match state.smpldb.create_wallet(user.id).await {
-        Ok(_wallet) => {
-            tracing::info!(username, email, "Wallet created");
-            (StatusCode::CREATED, Json(user)).into_response()
-        }
-        Err(e) => {
-            tracing::error!(?e, username, email, "Error creating user");
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#?}")).into_response()
-        }
+    state
+        .smpldb
+        .create_wallet(
+            user.id,
+            &state.config.default_currency,
+            &state.config.default_currency,
+        )
+        .await?;
+    tracing::info!(username, email, "Wallet created");
+
+    record_hits(&state, user.id, ScreeningContext::SignUp, &matches).await?;
+
+    // the user can ask for another email if this one fails
+    if let Err(e) = send_verification_email(&state, &user).await {
+        tracing::error!(?e, username, email, "Failed to send verification email");
     }
+    Ok((StatusCode::CREATED, Json(user)))
 }
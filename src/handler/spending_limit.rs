@@ -0,0 +1,141 @@
+use axum::{extract::State, Json};
+use bigdecimal::{BigDecimal, Zero};
+use serde::Deserialize;
+
+use crate::{
+    db::{
+        models::{SpendingLimit, Tier, User},
+        Allowance, Limits,
+    },
+    error::ApiError,
+    permission::{CanManageLimits, CanViewUsers},
+    utils::{ApiJson, ApiPath, ApiQuery, RequirePermission, ValidateAuth},
+    AppState,
+};
+
+use super::currency_or_default;
+
+#[derive(Debug, Deserialize)]
+pub struct GetLimits {
+    /// Defaults to `DEFAULT_CURRENCY`
+    currency: Option<String>,
+}
+
+/// shows the user's spending limits in a currency and what is left of them
+pub async fn get_limits(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<GetLimits>,
+) -> Result<Json<Allowance>, ApiError> {
+    let currency = currency_or_default(&state, query.currency);
+    Ok(Json(state.smpldb.get_allowance(user_id, &currency).await?))
+}
+
+/// shows any user's spending limits in a currency and what is left of them
+pub async fn get_user_limits(
+    _: RequirePermission<CanViewUsers>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    ApiQuery(query): ApiQuery<GetLimits>,
+) -> Result<Json<Allowance>, ApiError> {
+    let currency = currency_or_default(&state, query.currency);
+    Ok(Json(state.smpldb.get_allowance(user_id, &currency).await?))
+}
+
+/// lists the limits of every tier
+pub async fn list_tier_limits(
+    _: RequirePermission<CanViewUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SpendingLimit>>, ApiError> {
+    Ok(Json(state.smpldb.list_tier_limits().await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetLimits {
+    /// Defaults to `DEFAULT_CURRENCY`
+    currency: Option<String>,
+    /// Limits left out are removed
+    #[serde(flatten)]
+    limits: Limits,
+}
+
+impl SetLimits {
+    fn validate(&self) -> Result<(), ApiError> {
+        let Limits {
+            per_transaction,
+            daily_amount,
+            weekly_amount,
+            monthly_amount,
+            daily_count,
+            weekly_count,
+            monthly_count,
+        } = &self.limits;
+        let amounts = [per_transaction, daily_amount, weekly_amount, monthly_amount];
+        let counts = [daily_count, weekly_count, monthly_count];
+        if amounts
+            .into_iter()
+            .flatten()
+            .any(|a| *a <= BigDecimal::zero())
+            || counts.into_iter().flatten().any(|c| *c < 0)
+        {
+            return Err(ApiError::InvalidSpendingLimit);
+        }
+        Ok(())
+    }
+}
+
+/// replaces a tier's limits in a currency
+pub async fn set_tier_limits(
+    RequirePermission(admin_id, _): RequirePermission<CanManageLimits>,
+    State(state): State<AppState>,
+    ApiPath(tier): ApiPath<Tier>,
+    ApiJson(request): ApiJson<SetLimits>,
+) -> Result<Json<SpendingLimit>, ApiError> {
+    request.validate()?;
+    let currency = currency_or_default(&state, request.currency);
+    let limits = state
+        .smpldb
+        .set_tier_limits(tier, &currency, request.limits)
+        .await?;
+    tracing::info!(
+        admin_id,
+        tier = tier.as_str(),
+        currency,
+        "Set tier spending limits"
+    );
+    Ok(Json(limits))
+}
+
+/// replaces a user's own limits in a currency, which take the place of their tier's
+pub async fn set_user_limits(
+    RequirePermission(admin_id, _): RequirePermission<CanManageLimits>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<SetLimits>,
+) -> Result<Json<SpendingLimit>, ApiError> {
+    request.validate()?;
+    let currency = currency_or_default(&state, request.currency);
+    let limits = state
+        .smpldb
+        .set_user_limits(user_id, &currency, request.limits)
+        .await?;
+    tracing::info!(admin_id, user_id, currency, "Set user spending limits");
+    Ok(Json(limits))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetTier {
+    tier: Tier,
+}
+
+/// moves the user to another tier
+pub async fn set_user_tier(
+    RequirePermission(admin_id, _): RequirePermission<CanManageLimits>,
+    State(state): State<AppState>,
+    ApiPath(user_id): ApiPath<i32>,
+    ApiJson(SetTier { tier }): ApiJson<SetTier>,
+) -> Result<Json<User>, ApiError> {
+    let user = state.smpldb.set_user_tier(user_id, tier).await?;
+    tracing::info!(admin_id, user_id, tier = tier.as_str(), "Changed user tier");
+    Ok(Json(user))
+}
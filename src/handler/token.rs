@@ -0,0 +1,110 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{models::User, RefreshOutcome},
+    error::ApiError,
+    keys::JwkSet,
+    utils::{generate_token, hash_token, issue_new_jwt, ApiJson},
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    access_token: String,
+    token_type: &'static str,
+    /// Seconds until `access_token` expires
+    expires_in: i64,
+    refresh_token: String,
+}
+
+impl TokenPair {
+    fn new(state: &AppState, access_token: String, refresh_token: String) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer",
+            expires_in: state.config.access_token_ttl.num_seconds(),
+            refresh_token,
+        }
+    }
+}
+
+/// Issues an access token and starts a new refresh token family for the user
+pub(super) async fn issue_token_pair(state: &AppState, user: &User) -> Result<Response, ApiError> {
+    let user_id = user.id;
+    let access_token = issue_new_jwt(
+        &state.config.jwt_keys,
+        user_id,
+        user.role,
+        state.config.access_token_ttl,
+    )?;
+
+    let refresh_token = generate_token();
+    let family_id = generate_token();
+    let expires_at = Utc::now() + state.config.refresh_token_ttl;
+    state
+        .smpldb
+        .create_refresh_token(user_id, &family_id, &hash_token(&refresh_token), expires_at)
+        .await
+        .context("Failed to store refresh token")?;
+
+    let pair = TokenPair::new(state, access_token, refresh_token);
+    Ok((StatusCode::OK, Json(pair)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshToken {
+    refresh_token: String,
+}
+
+/// publishes the public keys tokens can be verified with
+pub async fn jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.config.jwt_keys.jwks())
+}
+
+/// exchanges a refresh token for a new access token and refresh token
+pub async fn refresh_token(
+    State(state): State<AppState>,
+    ApiJson(RefreshToken { refresh_token }): ApiJson<RefreshToken>,
+) -> Result<Json<TokenPair>, ApiError> {
+    let new_refresh_token = generate_token();
+    let expires_at = Utc::now() + state.config.refresh_token_ttl;
+
+    let outcome = state
+        .smpldb
+        .rotate_refresh_token(
+            &hash_token(&refresh_token),
+            &hash_token(&new_refresh_token),
+            expires_at,
+        )
+        .await
+        .context("Failed to rotate refresh token")?;
+    let (user_id, role) = match outcome {
+        RefreshOutcome::Rotated { user_id, role } => (user_id, role),
+        RefreshOutcome::Invalid => return Err(ApiError::InvalidRefreshToken),
+        RefreshOutcome::Expired => return Err(ApiError::RefreshTokenExpired),
+        RefreshOutcome::Reused => {
+            tracing::warn!("Refresh token reused, revoked its family");
+            return Err(ApiError::RefreshTokenReused);
+        }
+    };
+
+    let access_token = issue_new_jwt(
+        &state.config.jwt_keys,
+        user_id,
+        role,
+        state.config.access_token_ttl,
+    )?;
+    Ok(Json(TokenPair::new(
+        &state,
+        access_token,
+        new_refresh_token,
+    )))
+}
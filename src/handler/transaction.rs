@@ -1,85 +1,387 @@
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
-};
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Response, Json};
 use bigdecimal::BigDecimal;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::{utils::ValidateAuth, AppState};
+use crate::{
+    config::MoneyMovement,
+    db::{
+        models::{Transaction, TransactionKind},
+        Refunder, TransactionCursor, TransactionDirection, TransactionFilter,
+    },
+    error::ApiError,
+    utils::{ApiJson, ApiPath, ApiQuery, IdempotencyKey, OptionalApiJson, TotpCode, ValidateAuth},
+    AppState,
+};
 
-#[derive(Debug, Deserialize)]
+use super::{
+    idempotency::{self, Begun},
+    mfa::require_step_up,
+    require_verified_email, require_verified_recipient,
+    screening::screen_transfer,
+    transfer_review::{log_outcome, respond_to_payment},
+    wallet_ref,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CreateTransaction {
     to_username: String,
     amount: BigDecimal,
+    /// Defaults to the currency of `from_wallet_id`, or `DEFAULT_CURRENCY`. The recipient needs a
+    /// wallet in it.
+    currency: Option<String>,
+    /// Defaults to the user's default wallet in `currency`
+    from_wallet_id: Option<i32>,
 }
 
 pub async fn create_transaction(
     ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    TotpCode(totp_code): TotpCode,
     State(state): State<AppState>,
-    Json(CreateTransaction {
+    ApiJson(request): ApiJson<CreateTransaction>,
+) -> Result<Response, ApiError> {
+    if request.amount <= BigDecimal::from(0) {
+        return Err(ApiError::InvalidAmount);
+    }
+
+    let key = idempotency_key.as_deref();
+    let reservation =
+        match idempotency::begin(&state, user_id, key, "POST /transactions", &request).await? {
+            Begun::Replay(replay) => return Ok(replay),
+            Begun::Reserved(reservation) => reservation,
+        };
+
+    let CreateTransaction {
         to_username,
         amount,
-    }): Json<CreateTransaction>,
-) -> impl IntoResponse {
+        currency,
+        from_wallet_id,
+    } = request;
+    let from_wallet = wallet_ref(&state, from_wallet_id, currency);
+    let checked = async {
+        require_verified_email(&state, user_id, MoneyMovement::Send).await?;
+        require_verified_recipient(&state, &to_username).await?;
+        let sender = state
+            .smpldb
+            .get_user_by_id(user_id)
+            .await?
+            .with_context(|| format!("Failed to find user with id {user_id}"))?;
+        screen_transfer(&state, &sender, &to_username).await?;
+        require_step_up(&state, user_id, &from_wallet, &amount, totp_code.as_deref()).await
+    }
+    .await;
+    if let Err(e) = checked {
+        reservation.abort(&state).await;
+        return Err(e);
+    }
     match state
         .smpldb
-        .insert_payment(user_id, &to_username, amount)
+        .insert_payment(
+            user_id,
+            &from_wallet,
+            &to_username,
+            amount,
+            state.risk.as_ref(),
+            reservation.store(&respond_to_payment),
+        )
         .await
     {
-        Ok(transaction) => (StatusCode::CREATED, Json(transaction)).into_response(),
-        Err(crate::db::Error::RollbackTransaction) => {
-            (StatusCode::BAD_REQUEST, "Insufficient Funds").into_response()
+        Ok(outcome) => {
+            log_outcome(user_id, &outcome);
+            respond_to_payment(&outcome)
+                .map(idempotency::response)
+                .ok_or(ApiError::TransferBlocked)
+        }
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateConversion {
+    /// From `POST /fx/quotes`
+    quote_id: i32,
+    /// Defaults to the user, converting between their own wallets
+    to_username: Option<String>,
+    /// Defaults to the user's default wallet in the quote's currency
+    from_wallet_id: Option<i32>,
+}
+
+/// converts money at a quoted rate, into the user's own wallet or another user's
+pub async fn create_conversion(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    TotpCode(totp_code): TotpCode,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<CreateConversion>,
+) -> Result<Response, ApiError> {
+    let key = idempotency_key.as_deref();
+    let route = "POST /transactions/conversions";
+    let reservation = match idempotency::begin(&state, user_id, key, route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let result = async {
+        require_verified_email(&state, user_id, MoneyMovement::Send).await?;
+        let user = state
+            .smpldb
+            .get_user_by_id(user_id)
+            .await?
+            .with_context(|| format!("Failed to find user with id {user_id}"))?;
+        if let Some(to_username) = request
+            .to_username
+            .as_deref()
+            .filter(|&u| u != user.username)
+        {
+            require_verified_recipient(&state, to_username).await?;
+        }
+        let to_username = request.to_username.as_deref().unwrap_or(&user.username);
+        screen_transfer(&state, &user, to_username).await?;
+
+        let quote = state
+            .smpldb
+            .get_fx_quote(user_id, request.quote_id)
+            .await?
+            .ok_or(ApiError::FxQuoteNotFound)?;
+        let from_wallet = wallet_ref(
+            &state,
+            request.from_wallet_id,
+            Some(quote.from_currency.clone()),
+        );
+        require_step_up(
+            &state,
+            user_id,
+            &from_wallet,
+            &quote.from_amount,
+            totp_code.as_deref(),
+        )
+        .await?;
+
+        Ok::<_, ApiError>(
+            state
+                .smpldb
+                .insert_conversion(
+                    user_id,
+                    quote.id,
+                    request.from_wallet_id,
+                    to_username,
+                    state.risk.as_ref(),
+                    reservation.store(&respond_to_payment),
+                )
+                .await?,
+        )
+    }
+    .await;
+
+    match result {
+        Ok(outcome) => {
+            log_outcome(user_id, &outcome);
+            respond_to_payment(&outcome)
+                .map(idempotency::response)
+                .ok_or(ApiError::TransferBlocked)
         }
         Err(e) => {
-            tracing::error!(?e, user_id, "Failed to create transaction for user");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+            reservation.abort(&state).await;
+            Err(e)
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CreateRefund {
+    /// Defaults to everything not yet refunded
+    amount: Option<BigDecimal>,
+    reason: Option<String>,
+}
+
+/// sends all or part of a received transfer back to its sender
+pub async fn create_refund(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    State(state): State<AppState>,
+    ApiPath(transaction_id): ApiPath<i32>,
+    OptionalApiJson(request): OptionalApiJson<CreateRefund>,
+) -> Result<Response, ApiError> {
+    if request
+        .amount
+        .as_ref()
+        .is_some_and(|a| *a <= BigDecimal::from(0))
+    {
+        return Err(ApiError::InvalidAmount);
+    }
+
+    let key = idempotency_key.as_deref();
+    let route = format!("POST /transactions/{transaction_id}/refunds");
+    let reservation = match idempotency::begin(&state, user_id, key, &route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let CreateRefund { amount, reason } = request;
+    let reason = reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    let respond =
+        |transaction: &Transaction| Some(idempotency::json(StatusCode::CREATED, transaction));
+    match state
+        .smpldb
+        .refund_transaction(
+            Refunder::Recipient(user_id),
+            transaction_id,
+            amount,
+            reason,
+            reservation.store(&respond),
+        )
+        .await
+    {
+        Ok(transaction) => Ok(idempotency::response(idempotency::json(
+            StatusCode::CREATED,
+            &transaction,
+        ))),
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
         }
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct FormattedTransaction {
-    from_username: String,
-    to_username: String,
+    id: i32,
+    kind: TransactionKind,
+    from_username: Option<String>,
+    to_username: Option<String>,
     amount: BigDecimal,
+    currency: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_amount: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_currency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fx_rate: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_transaction_id: Option<i32>,
     created_at: DateTime<Utc>,
 }
 
 pub async fn get_transaction_by_id(
     ValidateAuth(user_id): ValidateAuth,
     State(state): State<AppState>,
-    Path(transaction_id): Path<i32>,
-) -> impl IntoResponse {
-    match state.smpldb.get_transaction(user_id, transaction_id).await {
-        Ok(Some((transaction, from_username, to_username))) => {
-            let t = FormattedTransaction {
-                from_username,
-                to_username,
-                amount: transaction.amount,
-                created_at: transaction.created_at.unwrap_or_default(),
-            };
-            (StatusCode::CREATED, Json(t)).into_response()
-        }
-        Ok(None) => (StatusCode::GONE, "Transaction Not Found").into_response(),
-        Err(e) => {
-            tracing::error!(?e, user_id, "Failed to get transaction for user");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+    ApiPath(transaction_id): ApiPath<i32>,
+) -> Result<Json<FormattedTransaction>, ApiError> {
+    let (transaction, from_username, to_username) = state
+        .smpldb
+        .get_transaction(user_id, transaction_id)
+        .await
+        .context("Failed to get transaction for user")?
+        .ok_or(ApiError::TransactionNotFound)?;
+    Ok(Json(FormattedTransaction {
+        id: transaction.id,
+        kind: transaction.kind,
+        from_username,
+        to_username,
+        amount: transaction.amount,
+        currency: transaction.currency,
+        to_amount: transaction.to_amount,
+        to_currency: transaction.to_currency,
+        fx_rate: transaction.fx_rate,
+        reason: transaction.reason,
+        original_transaction_id: transaction.original_transaction_id,
+        created_at: transaction.created_at,
+    }))
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ListTransactions {
+    cursor: Option<String>,
+    limit: Option<i64>,
+    direction: Option<TransactionDirection>,
+    counterparty: Option<String>,
+    min_amount: Option<BigDecimal>,
+    max_amount: Option<BigDecimal>,
+    currency: Option<String>,
+    wallet_id: Option<i32>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl ListTransactions {
+    /// Only lists transactions of the wallet
+    pub(super) fn in_wallet(self, wallet_id: i32) -> Self {
+        Self {
+            wallet_id: Some(wallet_id),
+            ..self
         }
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct TransactionPage {
+    transactions: Vec<Transaction>,
+    next_cursor: Option<String>,
+}
+
 pub async fn list_transactions(
     ValidateAuth(user_id): ValidateAuth,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    match state.smpldb.list_transactions(user_id).await {
-        Ok(transactions) => (StatusCode::CREATED, Json(transactions)).into_response(),
-        Err(e) => {
-            tracing::error!(?e, user_id, "Failed to get all transactions for user");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-        }
+    ApiQuery(query): ApiQuery<ListTransactions>,
+) -> Result<Json<TransactionPage>, ApiError> {
+    Ok(Json(transaction_page(&state, user_id, query).await?))
+}
+
+/// Returns a page of the user's transactions matching the query
+pub(super) async fn transaction_page(
+    state: &AppState,
+    user_id: i32,
+    query: ListTransactions,
+) -> Result<TransactionPage, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::InvalidLimit);
     }
+
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<TransactionCursor>)
+        .transpose()
+        .map_err(|()| ApiError::InvalidCursor)?;
+
+    let filter = TransactionFilter {
+        direction: query.direction,
+        counterparty: query.counterparty,
+        min_amount: query.min_amount,
+        max_amount: query.max_amount,
+        currency: query.currency.map(|c| c.to_ascii_uppercase()),
+        wallet_id: query.wallet_id,
+        from: query.from,
+        to: query.to,
+    };
+
+    // fetch one extra row to know whether there is another page
+    let mut transactions = state
+        .smpldb
+        .list_transactions(user_id, &filter, cursor, limit + 1)
+        .await
+        .context("Failed to get all transactions for user")?;
+    let next_cursor = if transactions.len() as i64 > limit {
+        transactions.truncate(limit as usize);
+        transactions
+            .last()
+            .map(|t| TransactionCursor::from(t).to_string())
+    } else {
+        None
+    };
+    Ok(TransactionPage {
+        transactions,
+        next_cursor,
+    })
 }
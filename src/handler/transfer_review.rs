@@ -0,0 +1,284 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, Json};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        models::{ReviewStatus, TransferReview},
+        PaymentOutcome, StoredResponse, TransferReviewWithUsers,
+    },
+    error::ApiError,
+    permission::CanReviewTransfers,
+    utils::{ApiPath, ApiQuery, OptionalApiJson, RequirePermission, ValidateAuth},
+    AppState,
+};
+
+use super::idempotency;
+
+const MAX_NOTE_LENGTH: usize = 255;
+
+#[derive(Debug, Serialize)]
+pub struct FormattedTransferReview {
+    id: i32,
+    username: String,
+    to_username: String,
+    wallet_id: i32,
+    amount: BigDecimal,
+    currency: String,
+    /// Only shown to admins, so senders can't learn what the rules look for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rules: Option<Vec<String>>,
+    status: ReviewStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewer_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_id: Option<i32>,
+    /// Set for a held conversion, payment request acceptance or hold capture
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fx_quote_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payment_request_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hold_id: Option<i32>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<TransferReviewWithUsers> for FormattedTransferReview {
+    fn from((review, username, to_username): TransferReviewWithUsers) -> Self {
+        let TransferReview {
+            id,
+            wallet_id,
+            amount,
+            currency,
+            score,
+            rules,
+            status,
+            reviewer_id,
+            note,
+            reviewed_at,
+            transaction_id,
+            created_at,
+            updated_at,
+            fx_quote_id,
+            payment_request_id,
+            hold_id,
+            ..
+        } = review;
+        Self {
+            id,
+            username,
+            to_username,
+            wallet_id,
+            amount,
+            currency,
+            score: Some(score),
+            rules: Some(rules),
+            status,
+            reviewer_id,
+            note,
+            reviewed_at,
+            transaction_id,
+            fx_quote_id,
+            payment_request_id,
+            hold_id,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+impl FormattedTransferReview {
+    /// Leaves out how the transfer was scored and who reviewed it
+    pub fn for_sender(self) -> Self {
+        Self {
+            score: None,
+            rules: None,
+            reviewer_id: None,
+            ..self
+        }
+    }
+}
+
+/// The response to a transfer: `201 Created` with the transaction, or `202 Accepted` with the
+/// review if the risk engine held it. A blocked transfer has none, so its key is released.
+pub(super) fn respond_to_payment(outcome: &PaymentOutcome) -> Option<StoredResponse> {
+    match outcome {
+        PaymentOutcome::Paid(transaction) => {
+            Some(idempotency::json(StatusCode::CREATED, transaction))
+        }
+        PaymentOutcome::Held(review) => {
+            let review = FormattedTransferReview::from(review.clone()).for_sender();
+            Some(idempotency::json(StatusCode::ACCEPTED, &review))
+        }
+        PaymentOutcome::Blocked(_) => None,
+    }
+}
+
+/// Logs a transfer the risk engine held or blocked
+pub(super) fn log_outcome(user_id: i32, outcome: &PaymentOutcome) {
+    match outcome {
+        PaymentOutcome::Paid(_) => {}
+        PaymentOutcome::Held((review, ..)) => tracing::info!(
+            user_id,
+            review_id = review.id,
+            score = review.score,
+            "Held transfer for review"
+        ),
+        PaymentOutcome::Blocked(review) => tracing::warn!(
+            user_id,
+            review_id = review.id,
+            score = review.score,
+            "Blocked transfer"
+        ),
+    }
+}
+
+/// lists the user's transfers that were held for review or blocked, newest first
+pub async fn list_transfer_reviews(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<FormattedTransferReview>>, ApiError> {
+    let reviews = state.smpldb.list_transfer_reviews(user_id).await?;
+    Ok(Json(
+        reviews
+            .into_iter()
+            .map(|r| FormattedTransferReview::from(r).for_sender())
+            .collect(),
+    ))
+}
+
+pub async fn get_transfer_review(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(review_id): ApiPath<i32>,
+) -> Result<Json<FormattedTransferReview>, ApiError> {
+    let review = state
+        .smpldb
+        .get_transfer_review(Some(user_id), review_id)
+        .await?;
+    Ok(Json(FormattedTransferReview::from(review).for_sender()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListReviewQueue {
+    /// Defaults to `pending`
+    status: Option<ReviewStatus>,
+}
+
+/// lists everyone's transfer reviews in a status, oldest first
+pub async fn transfer_review_queue(
+    _: RequirePermission<CanReviewTransfers>,
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ListReviewQueue>,
+) -> Result<Json<Vec<FormattedTransferReview>>, ApiError> {
+    let status = query.status.unwrap_or(ReviewStatus::Pending);
+    let reviews = state.smpldb.transfer_review_queue(status).await?;
+    Ok(Json(reviews.into_iter().map(Into::into).collect()))
+}
+
+pub async fn admin_get_transfer_review(
+    _: RequirePermission<CanReviewTransfers>,
+    State(state): State<AppState>,
+    ApiPath(review_id): ApiPath<i32>,
+) -> Result<Json<FormattedTransferReview>, ApiError> {
+    let review = state.smpldb.get_transfer_review(None, review_id).await?;
+    Ok(Json(review.into()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReviewDecision {
+    /// Shown to the sender
+    note: Option<String>,
+}
+
+impl ReviewDecision {
+    fn note(&self) -> Result<Option<&str>, ApiError> {
+        let note = self
+            .note
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty());
+        if note.is_some_and(|n| n.chars().count() > MAX_NOTE_LENGTH) {
+            return Err(ApiError::InvalidNote);
+        }
+        Ok(note)
+    }
+}
+
+/// makes a held transfer
+pub async fn approve_transfer_review(
+    RequirePermission(admin_id, _): RequirePermission<CanReviewTransfers>,
+    State(state): State<AppState>,
+    ApiPath(review_id): ApiPath<i32>,
+    OptionalApiJson(decision): OptionalApiJson<ReviewDecision>,
+) -> Result<Json<FormattedTransferReview>, ApiError> {
+    let review = state
+        .smpldb
+        .approve_transfer_review(admin_id, review_id, decision.note()?)
+        .await?;
+    tracing::info!(
+        admin_id,
+        review_id,
+        transaction_id = review.transaction_id,
+        "Approved held transfer"
+    );
+    let review = state.smpldb.get_transfer_review(None, review_id).await?;
+    notify_sender(&state, &review).await;
+    Ok(Json(review.into()))
+}
+
+/// cancels a held transfer, releasing the funds it reserved
+pub async fn reject_transfer_review(
+    RequirePermission(admin_id, _): RequirePermission<CanReviewTransfers>,
+    State(state): State<AppState>,
+    ApiPath(review_id): ApiPath<i32>,
+    OptionalApiJson(decision): OptionalApiJson<ReviewDecision>,
+) -> Result<Json<FormattedTransferReview>, ApiError> {
+    state
+        .smpldb
+        .reject_transfer_review(admin_id, review_id, decision.note()?)
+        .await?;
+    tracing::info!(admin_id, review_id, "Rejected held transfer");
+    let review = state.smpldb.get_transfer_review(None, review_id).await?;
+    notify_sender(&state, &review).await;
+    Ok(Json(review.into()))
+}
+
+/// tells the sender how their held transfer was decided. The decision stands if this fails.
+async fn notify_sender(state: &AppState, (review, _, to_username): &TransferReviewWithUsers) {
+    let result = async {
+        let sender = state
+            .smpldb
+            .get_user_by_id(review.user_id)
+            .await?
+            .context("Sender of transfer review not found")?;
+        let outcome = match review.status {
+            ReviewStatus::Approved => "was approved and has been sent",
+            _ => "was rejected and the funds are available again",
+        };
+        let mut body = format!(
+            "Your transfer of {} {} to {to_username} {outcome}.",
+            review.amount, review.currency
+        );
+        if let Some(note) = &review.note {
+            body.push_str(&format!("\n\n{note}"));
+        }
+        state
+            .notifier
+            .send(&sender.email, "Your held transfer was reviewed", &body)
+            .await
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::warn!(error = ?e, review_id = review.id, "Failed to notify sender of review");
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{
+    db::{models::User, TokenPurpose},
+    error::ApiError,
+    utils::{generate_token, hash_token, ApiJson, ValidateAuth},
+    AppState,
+};
+
+/// Issues an email verification token and sends it to the user's email
+pub(super) async fn send_verification_email(state: &AppState, user: &User) -> anyhow::Result<()> {
+    let token = generate_token();
+    let expires_at = Utc::now() + state.config.email_verification_ttl;
+    state
+        .smpldb
+        .create_user_token(
+            user.id,
+            TokenPurpose::EmailVerification,
+            &hash_token(&token),
+            expires_at,
+        )
+        .await?;
+
+    let body = format!(
+        "Welcome {}! Use this token to verify your email, it expires in {} hours:\n\n{token}",
+        user.username,
+        state.config.email_verification_ttl.num_hours()
+    );
+    state
+        .notifier
+        .send(&user.email, "Verify your email", &body)
+        .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmail {
+    token: String,
+}
+
+/// marks the email of the user the verification token was sent to as verified
+pub async fn verify_email(
+    State(state): State<AppState>,
+    ApiJson(VerifyEmail { token }): ApiJson<VerifyEmail>,
+) -> Result<StatusCode, ApiError> {
+    let user_id = state
+        .smpldb
+        .verify_email(&hash_token(&token))
+        .await
+        .context("Failed to verify email")?
+        .ok_or(ApiError::InvalidOrExpiredToken)?;
+    tracing::info!(user_id, "Email verified");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// sends the signed in user a new verification token
+pub async fn resend_verification_email(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let user = state
+        .smpldb
+        .get_user_by_id(user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {user_id}"))?;
+
+    if user.email_verified_at.is_some() {
+        return Err(ApiError::EmailAlreadyVerified);
+    }
+
+    send_verification_email(&state, &user)
+        .await
+        .context("Failed to send verification email")?;
+    Ok(StatusCode::ACCEPTED)
+}
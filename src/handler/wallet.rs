@@ -1,59 +1,356 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Response, Json};
 use bigdecimal::{BigDecimal, FromPrimitive};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{utils::ValidateAuth, AppState};
+use crate::{
+    config::MoneyMovement,
+    db::{
+        self,
+        models::{LedgerLine, Transaction, Wallet},
+        WalletWithHeld,
+    },
+    error::ApiError,
+    utils::{ApiJson, ApiPath, ApiQuery, IdempotencyKey, ValidateAuth},
+    AppState,
+};
 
-pub async fn get_wallet(
+use super::{
+    currency_or_default,
+    idempotency::{self, Begun},
+    require_verified_email, wallet_ref,
+};
+
+/// A wallet with what of its balance is held and what can be spent
+#[derive(Debug, Serialize)]
+pub struct WalletBalances {
+    #[serde(flatten)]
+    wallet: Wallet,
+    /// Reserved by active holds and transfers waiting for review
+    held_balance: BigDecimal,
+    /// `balance` less `held_balance`
+    available_balance: BigDecimal,
+}
+
+impl From<WalletWithHeld> for WalletBalances {
+    fn from((wallet, held_balance): WalletWithHeld) -> Self {
+        WalletBalances {
+            available_balance: &wallet.balance - &held_balance,
+            held_balance,
+            wallet,
+        }
+    }
+}
+
+/// Looks up the holds on the wallets
+pub(super) async fn with_balances(
+    state: &AppState,
+    wallets: Vec<Wallet>,
+) -> Result<Vec<WalletBalances>, ApiError> {
+    let ids: Vec<i32> = wallets.iter().map(|w| w.id).collect();
+    let mut held = state
+        .smpldb
+        .held_balances(&ids)
+        .await
+        .context("Failed to get held balances")?;
+    Ok(wallets
+        .into_iter()
+        .map(|wallet| {
+            let held_balance = held.remove(&wallet.id).unwrap_or_default();
+            WalletBalances::from((wallet, held_balance))
+        })
+        .collect())
+}
+
+/// lists the user's open wallets
+pub async fn list_wallets(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<WalletBalances>>, ApiError> {
+    let wallets = state
+        .smpldb
+        .list_wallets(user_id)
+        .await
+        .context("Failed to list wallets for user")?;
+    Ok(Json(with_balances(&state, wallets).await?))
+}
+
+const MAX_WALLET_NAME_LEN: usize = 60;
+
+fn validate_wallet_name(name: &str) -> Result<(), ApiError> {
+    if name.trim().is_empty() || name.chars().count() > MAX_WALLET_NAME_LEN {
+        return Err(ApiError::InvalidWalletName);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWallet {
+    /// Defaults to `DEFAULT_CURRENCY`
+    currency: Option<String>,
+    /// Defaults to the currency code
+    name: Option<String>,
+}
+
+/// opens a named wallet. The first wallet in a currency is the default one for receiving it.
+pub async fn create_wallet(
     ValidateAuth(user_id): ValidateAuth,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    match state.smpldb.get_wallet(user_id).await {
-        Ok(wallet) => (StatusCode::OK, Json(wallet)).into_response(),
+    ApiJson(CreateWallet { currency, name }): ApiJson<CreateWallet>,
+) -> Result<(StatusCode, Json<Wallet>), ApiError> {
+    let currency = currency_or_default(&state, currency);
+    let name = name.unwrap_or_else(|| currency.clone());
+    validate_wallet_name(&name)?;
+
+    match state.smpldb.create_wallet(user_id, &currency, &name).await {
+        Ok(wallet) => {
+            tracing::info!(user_id, currency, name, "Wallet created");
+            Ok((StatusCode::CREATED, Json(wallet)))
+        }
+        Err(db::Error::Duplicate) => Err(ApiError::WalletAlreadyExists),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameWallet {
+    name: String,
+}
+
+pub async fn rename_wallet(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+    ApiJson(RenameWallet { name }): ApiJson<RenameWallet>,
+) -> Result<Json<Wallet>, ApiError> {
+    validate_wallet_name(&name)?;
+    match state.smpldb.rename_wallet(user_id, wallet_id, &name).await {
+        Ok(wallet) => Ok(Json(wallet)),
+        Err(db::Error::Duplicate) => Err(ApiError::WalletAlreadyExists),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// makes the wallet the one transfers in its currency are received into
+pub async fn set_default_wallet(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+) -> Result<Json<Wallet>, ApiError> {
+    let wallet = state.smpldb.set_default_wallet(user_id, wallet_id).await?;
+    Ok(Json(wallet))
+}
+
+/// closes an empty wallet
+pub async fn close_wallet(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiPath(wallet_id): ApiPath<i32>,
+) -> Result<Json<Wallet>, ApiError> {
+    let wallet = state.smpldb.close_wallet(user_id, wallet_id).await?;
+    tracing::info!(user_id, wallet_id, "Wallet closed");
+    Ok(Json(wallet))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MoveBetweenWallets {
+    from_wallet_id: i32,
+    to_wallet_id: i32,
+    amount: BigDecimal,
+}
+
+/// moves money between two of the user's wallets in the same currency
+pub async fn move_between_wallets(
+    ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<MoveBetweenWallets>,
+) -> Result<Response, ApiError> {
+    if request.amount <= BigDecimal::from(0) {
+        return Err(ApiError::InvalidAmount);
+    }
+
+    let key = idempotency_key.as_deref();
+    let route = "POST /wallets/transfers";
+    let reservation = match idempotency::begin(&state, user_id, key, route, &request).await? {
+        Begun::Replay(replay) => return Ok(replay),
+        Begun::Reserved(reservation) => reservation,
+    };
+
+    let MoveBetweenWallets {
+        from_wallet_id,
+        to_wallet_id,
+        amount,
+    } = request;
+    let respond =
+        |transaction: &Transaction| Some(idempotency::json(StatusCode::CREATED, transaction));
+    match state
+        .smpldb
+        .move_between_wallets(
+            user_id,
+            from_wallet_id,
+            to_wallet_id,
+            amount,
+            reservation.store(&respond),
+        )
+        .await
+    {
+        Ok(transaction) => Ok(idempotency::response(idempotency::json(
+            StatusCode::CREATED,
+            &transaction,
+        ))),
         Err(e) => {
-            tracing::error!(?e, user_id, "Failed to get wallet for user");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+            reservation.abort(&state).await;
+            Err(e.into())
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
+pub struct WalletQuery {
+    /// Defaults to the user's default wallet in `currency`
+    wallet_id: Option<i32>,
+    /// Defaults to `DEFAULT_CURRENCY`
+    currency: Option<String>,
+}
+
+pub async fn get_wallet(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiQuery(WalletQuery {
+        wallet_id,
+        currency,
+    }): ApiQuery<WalletQuery>,
+) -> Result<Json<WalletBalances>, ApiError> {
+    let wallet = wallet_ref(&state, wallet_id, currency);
+    let wallet = state.smpldb.get_wallet(user_id, &wallet).await?;
+    let mut wallets = with_balances(&state, vec![wallet]).await?;
+    Ok(Json(wallets.remove(0)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletLedger {
+    wallet_id: i32,
+    currency: String,
+    /// Balance cached on the wallet row
+    balance: BigDecimal,
+    /// Balance derived from the wallet's postings
+    ledger_balance: BigDecimal,
+    postings: Vec<LedgerLine>,
+}
+
+impl WalletLedger {
+    /// Logs an error if the wallet's cached balance has drifted from its postings
+    pub(super) fn new(wallet: Wallet, postings: Vec<LedgerLine>) -> Self {
+        let ledger_balance = postings.iter().map(|p| &p.amount).sum();
+        if ledger_balance != wallet.balance {
+            tracing::error!(
+                user_id = wallet.user_id,
+                wallet_id = wallet.id,
+                %wallet.balance,
+                %ledger_balance,
+                "Wallet balance does not match its ledger"
+            );
+        }
+        Self {
+            wallet_id: wallet.id,
+            currency: wallet.currency,
+            balance: wallet.balance,
+            ledger_balance,
+            postings,
+        }
+    }
+}
+
+pub async fn get_wallet_ledger(
+    ValidateAuth(user_id): ValidateAuth,
+    State(state): State<AppState>,
+    ApiQuery(WalletQuery {
+        wallet_id,
+        currency,
+    }): ApiQuery<WalletQuery>,
+) -> Result<Json<WalletLedger>, ApiError> {
+    let wallet = wallet_ref(&state, wallet_id, currency);
+    let (wallet, postings) = state.smpldb.get_wallet_ledger(user_id, &wallet).await?;
+    Ok(Json(WalletLedger::new(wallet, postings)))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub enum UpdateWalletType {
     Deposit,
     Withdraw,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateWallet {
     action: UpdateWalletType,
     amount: BigDecimal,
+    /// Defaults to the user's default wallet in `currency`
+    wallet_id: Option<i32>,
+    /// Defaults to `DEFAULT_CURRENCY`
+    currency: Option<String>,
 }
 
 pub async fn update_wallet(
     ValidateAuth(user_id): ValidateAuth,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
     State(state): State<AppState>,
-    Json(UpdateWallet { action, amount }): Json<UpdateWallet>,
-) -> impl IntoResponse {
-    if amount <= BigDecimal::from_u8(0).expect("shouldn't fail") {
-        return (StatusCode::BAD_REQUEST, "Amount cannot be zero").into_response();
+    ApiJson(request): ApiJson<UpdateWallet>,
+) -> Result<Response, ApiError> {
+    if request.amount <= BigDecimal::from_u8(0).expect("shouldn't fail") {
+        return Err(ApiError::InvalidAmount);
+    };
+
+    let movement = match request.action {
+        UpdateWalletType::Deposit => MoneyMovement::Receive,
+        UpdateWalletType::Withdraw => MoneyMovement::Send,
+    };
+
+    let key = idempotency_key.as_deref();
+    let reservation =
+        match idempotency::begin(&state, user_id, key, "PUT /wallet", &request).await? {
+            Begun::Replay(replay) => return Ok(replay),
+            Begun::Reserved(reservation) => reservation,
+        };
+    if let Err(e) = require_verified_email(&state, user_id, movement).await {
+        reservation.abort(&state).await;
+        return Err(e);
+    }
+
+    let UpdateWallet {
+        action,
+        amount,
+        wallet_id,
+        currency,
+    } = request;
+    let wallet = wallet_ref(&state, wallet_id, currency);
+    let respond = |updated: &WalletWithHeld| {
+        let wallet = WalletBalances::from(updated.clone());
+        Some(idempotency::json(StatusCode::OK, &wallet))
+    };
+    let idempotency = reservation.store(&respond);
+    let result = match action {
+        UpdateWalletType::Deposit => {
+            state
+                .smpldb
+                .deposit(user_id, &wallet, amount, idempotency)
+                .await
+        }
+        UpdateWalletType::Withdraw => {
+            state
+                .smpldb
+                .withdraw(user_id, &wallet, amount, idempotency)
+                .await
+        }
     };
 
-    match action {
-        UpdateWalletType::Deposit => match state.smpldb.deposit(user_id, amount).await {
-            Ok(wallet) => (StatusCode::OK, Json(wallet)).into_response(),
-            Err(e) => {
-                tracing::error!(?e, user_id, "Failed to deposit funds wallet for user");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-            }
-        },
-        UpdateWalletType::Withdraw => match state.smpldb.withdraw(user_id, amount).await {
-            Ok(wallet) => (StatusCode::OK, Json(wallet)).into_response(),
-            Err(crate::db::Error::RollbackTransaction) => {
-                (StatusCode::BAD_REQUEST, "Insufficient Funds").into_response()
-            }
-            Err(e) => {
-                tracing::error!(?e, user_id, "Failed to withdraw funds from wallet for user");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-            }
-        },
+    match result {
+        Ok(updated) => Ok(idempotency::response(idempotency::json(
+            StatusCode::OK,
+            &WalletBalances::from(updated),
+        ))),
+        Err(e) => {
+            reservation.abort(&state).await;
+            Err(e.into())
+        }
     }
 }
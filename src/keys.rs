@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::{
+    rand::SystemRandom,
+    rsa::PublicKeyComponents,
+    signature::{
+        EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+    },
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A key that tokens can be signed and verified with
+struct JwtKey {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// Public half of an asymmetric key, published in the JWKS
+    jwk: Option<Jwk>,
+}
+
+/// Public key in JSON Web Key format (RFC 7517)
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    kty: &'static str,
+    kid: String,
+    alg: &'static str,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// What a token can be used for, set as its `typ` header so one kind of token can't be passed off
+/// as another
+#[derive(Debug, Clone, Copy)]
+pub enum TokenType {
+    /// Authenticates API requests
+    Access,
+    /// Proves the password was checked, exchanged along with a TOTP code for an access token
+    MfaChallenge,
+}
+
+impl TokenType {
+    fn typ(self) -> &'static str {
+        match self {
+            TokenType::Access => "JWT",
+            TokenType::MfaChallenge => "mfa+jwt",
+        }
+    }
+}
+
+/// JWT signing and verification keys.
+///
+/// Every configured key is accepted for verification, selected by the token's `kid` header, but
+/// only the signing key issues new tokens. To rotate, add the new key, switch the signing key to it,
+/// and remove the old key once tokens signed with it have expired.
+pub struct KeyStore {
+    signing_kid: String,
+    keys: HashMap<String, JwtKey>,
+}
+
+impl KeyStore {
+    /// Loads keys from `JWT_KEYS` and `JWT_SIGNING_KEY_ID`.
+    ///
+    /// `JWT_KEYS` is a comma separated list of `<kid>:<algorithm>:<key>`. For `HS256`, `HS384` and
+    /// `HS512` `<key>` is the secret itself. For `RS256`, `ES256` and `EdDSA` it is the path to a
+    /// PKCS#8 PEM encoded private key.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let keys = std::env::var("JWT_KEYS").context("JWT_KEYS is not set")?;
+        let signing_kid =
+            std::env::var("JWT_SIGNING_KEY_ID").context("JWT_SIGNING_KEY_ID is not set")?;
+        Self::parse(&keys, signing_kid)
+    }
+
+    fn parse(spec: &str, signing_kid: String) -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.splitn(3, ':');
+            let (Some(kid), Some(algorithm), Some(material)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                bail!("Invalid JWT_KEYS entry, expected `<kid>:<algorithm>:<key>`");
+            };
+            let key = JwtKey::load(kid, algorithm, material)
+                .with_context(|| format!("Failed to load JWT key `{kid}`"))?;
+            if keys.insert(kid.to_string(), key).is_some() {
+                bail!("Duplicate JWT key id `{kid}`");
+            }
+        }
+
+        if !keys.contains_key(&signing_kid) {
+            bail!("JWT_SIGNING_KEY_ID `{signing_kid}` is not in JWT_KEYS");
+        }
+        Ok(Self { signing_kid, keys })
+    }
+
+    /// Signs `claims` with the signing key, setting the `kid` and `typ` headers
+    pub fn sign<T: Serialize>(
+        &self,
+        claims: &T,
+        token_type: TokenType,
+    ) -> jsonwebtoken::errors::Result<String> {
+        let key = &self.keys[&self.signing_kid];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.signing_kid.clone());
+        header.typ = Some(token_type.typ().to_string());
+        jsonwebtoken::encode(&header, claims, &key.encoding)
+    }
+
+    /// Verifies the signature and expiry of `token` with the key named by its `kid` header, and that
+    /// it is a `token_type` token
+    pub fn verify<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        token_type: TokenType,
+    ) -> jsonwebtoken::errors::Result<T> {
+        let header = jsonwebtoken::decode_header(token)?;
+        if header.typ.as_deref() != Some(token_type.typ()) {
+            return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+        }
+        let Some(key) = header.kid.as_ref().and_then(|kid| self.keys.get(kid)) else {
+            return Err(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat.into());
+        };
+        let mut validation = Validation::new(key.algorithm);
+        validation.leeway = 0;
+        jsonwebtoken::decode(token, &key.decoding, &validation).map(|t| t.claims)
+    }
+
+    /// Public keys of every asymmetric key, for other services to verify tokens with
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self.keys.values().filter_map(|k| k.jwk.clone()).collect();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        JwkSet { keys }
+    }
+}
+
+impl JwtKey {
+    fn load(kid: &str, algorithm: &str, material: &str) -> anyhow::Result<Self> {
+        let algorithm: Algorithm = algorithm
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Unknown algorithm `{algorithm}`"))?;
+
+        if matches!(
+            algorithm,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            if material.len() < 32 {
+                bail!("HMAC secrets must be at least 32 bytes");
+            }
+            return Ok(Self {
+                algorithm,
+                encoding: EncodingKey::from_secret(material.as_bytes()),
+                decoding: DecodingKey::from_secret(material.as_bytes()),
+                jwk: None,
+            });
+        }
+
+        let pem_bytes =
+            std::fs::read(material).with_context(|| format!("Failed to read `{material}`"))?;
+        let der = pem::parse(&pem_bytes)?.into_contents();
+        let b64 = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+
+        let (encoding, decoding, jwk) = match algorithm {
+            Algorithm::RS256 => {
+                let pair = RsaKeyPair::from_pkcs8(&der)
+                    .map_err(|e| anyhow::anyhow!("Invalid RSA key: {e}"))?;
+                let components = PublicKeyComponents::<Vec<u8>>::from(pair.public());
+                let n = b64(&components.n);
+                let e = b64(&components.e);
+                let decoding = DecodingKey::from_rsa_components(&n, &e)?;
+                let jwk = Jwk {
+                    n: Some(n),
+                    e: Some(e),
+                    ..Jwk::new("RSA", kid, "RS256", None)
+                };
+                (EncodingKey::from_rsa_pem(&pem_bytes)?, decoding, jwk)
+            }
+            Algorithm::ES256 => {
+                let pair = EcdsaKeyPair::from_pkcs8(
+                    &ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &der,
+                    &SystemRandom::new(),
+                )
+                .map_err(|e| anyhow::anyhow!("Invalid P-256 key: {e}"))?;
+                // uncompressed point: 0x04 || x || y
+                let point = pair.public_key().as_ref();
+                let x = b64(&point[1..33]);
+                let y = b64(&point[33..]);
+                let decoding = DecodingKey::from_ec_components(&x, &y)?;
+                let jwk = Jwk {
+                    x: Some(x),
+                    y: Some(y),
+                    ..Jwk::new("EC", kid, "ES256", Some("P-256"))
+                };
+                (EncodingKey::from_ec_pem(&pem_bytes)?, decoding, jwk)
+            }
+            Algorithm::EdDSA => {
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                    .map_err(|e| anyhow::anyhow!("Invalid Ed25519 key: {e}"))?;
+                let x = b64(pair.public_key().as_ref());
+                let decoding = DecodingKey::from_ed_components(&x)?;
+                let jwk = Jwk {
+                    x: Some(x),
+                    ..Jwk::new("OKP", kid, "EdDSA", Some("Ed25519"))
+                };
+                (EncodingKey::from_ed_pem(&pem_bytes)?, decoding, jwk)
+            }
+            other => bail!("Unsupported algorithm `{other:?}`"),
+        };
+
+        Ok(Self {
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+impl Jwk {
+    fn new(kty: &'static str, kid: &str, alg: &'static str, crv: Option<&'static str>) -> Self {
+        Self {
+            kty,
+            kid: kid.to_string(),
+            alg,
+            key_use: "sig",
+            crv,
+            n: None,
+            e: None,
+            x: None,
+            y: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    const SECRET_A: &str = "a-secret-that-is-at-least-32-bytes";
+    const SECRET_B: &str = "b-secret-that-is-at-least-32-bytes";
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "1".to_string(),
+            exp: chrono::Utc::now().timestamp() + 60,
+        }
+    }
+
+    fn kid(token: &str) -> Option<String> {
+        jsonwebtoken::decode_header(token).unwrap().kid
+    }
+
+    #[test]
+    fn parses_hmac_keys() {
+        let spec = format!(" old:HS256:{SECRET_A} , new:HS512:{SECRET_B},");
+        let store = KeyStore::parse(&spec, "new".to_string()).unwrap();
+        assert_eq!(store.keys.len(), 2);
+        assert_eq!(store.keys["old"].algorithm, Algorithm::HS256);
+        assert_eq!(store.keys["new"].algorithm, Algorithm::HS512);
+        // symmetric keys aren't published
+        assert!(store.jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn secret_may_contain_colons() {
+        let secret = format!("{SECRET_A}:with:colons");
+        let store = KeyStore::parse(&format!("k1:HS256:{secret}"), "k1".to_string()).unwrap();
+        let token = store.sign(&claims(), TokenType::Access).unwrap();
+        assert!(store.verify::<Claims>(&token, TokenType::Access).is_ok());
+        let only_secret = KeyStore::parse(&format!("k1:HS256:{SECRET_A}"), "k1".to_string())
+            .unwrap()
+            .verify::<Claims>(&token, TokenType::Access);
+        assert!(only_secret.is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        let invalid = [
+            (format!("k1:HS256:{SECRET_A}"), "k2"),
+            ("k1:HS256".to_string(), "k1"),
+            ("k1:HS256:too-short".to_string(), "k1"),
+            (format!("k1:XX256:{SECRET_A}"), "k1"),
+            (format!("k1:HS256:{SECRET_A},k1:HS256:{SECRET_B}"), "k1"),
+            (String::new(), "k1"),
+        ];
+        for (spec, signing_kid) in invalid {
+            assert!(
+                KeyStore::parse(&spec, signing_kid.to_string()).is_err(),
+                "{spec:?} with {signing_kid} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_tokens_signed_before_rotation() {
+        let spec = format!("old:HS256:{SECRET_A},new:HS256:{SECRET_B}");
+        let before = KeyStore::parse(&spec, "old".to_string()).unwrap();
+        let after = KeyStore::parse(&spec, "new".to_string()).unwrap();
+
+        let old_token = before.sign(&claims(), TokenType::Access).unwrap();
+        let new_token = after.sign(&claims(), TokenType::Access).unwrap();
+        assert_eq!(kid(&old_token).as_deref(), Some("old"));
+        assert_eq!(kid(&new_token).as_deref(), Some("new"));
+        assert!(after
+            .verify::<Claims>(&old_token, TokenType::Access)
+            .is_ok());
+
+        // once the old key is removed its tokens are rejected
+        let removed = KeyStore::parse(&format!("new:HS256:{SECRET_B}"), "new".to_string()).unwrap();
+        assert!(removed
+            .verify::<Claims>(&old_token, TokenType::Access)
+            .is_err());
+        assert!(removed
+            .verify::<Claims>(&new_token, TokenType::Access)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_token_of_another_type() {
+        let store = KeyStore::parse(&format!("k1:HS256:{SECRET_A}"), "k1".to_string()).unwrap();
+        let token = store.sign(&claims(), TokenType::MfaChallenge).unwrap();
+        assert!(store.verify::<Claims>(&token, TokenType::Access).is_err());
+        assert!(store
+            .verify::<Claims>(&token, TokenType::MfaChallenge)
+            .is_ok());
+    }
+
+    #[test]
+    fn loads_and_publishes_ed25519_key() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let path = std::env::temp_dir().join(format!("smpl-test-{}.pem", std::process::id()));
+        std::fs::write(
+            &path,
+            pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref())),
+        )
+        .unwrap();
+
+        let spec = format!("ed:EdDSA:{},hs:HS256:{SECRET_A}", path.display());
+        let store = KeyStore::parse(&spec, "ed".to_string());
+        std::fs::remove_file(&path).unwrap();
+        let store = store.unwrap();
+
+        let token = store.sign(&claims(), TokenType::Access).unwrap();
+        assert!(store.verify::<Claims>(&token, TokenType::Access).is_ok());
+
+        let jwks = store.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        let jwk = &jwks.keys[0];
+        assert_eq!(
+            (jwk.kty, jwk.alg, jwk.crv),
+            ("OKP", "EdDSA", Some("Ed25519"))
+        );
+        assert_eq!(jwk.kid, "ed");
+        assert!(jwk.x.is_some());
+    }
+
+    #[test]
+    fn missing_key_file_is_an_error() {
+        let spec = "ed:EdDSA:/nonexistent/key.pem";
+        assert!(KeyStore::parse(spec, "ed".to_string()).is_err());
+    }
+}
@@ -8,11 +8,16 @@
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
-    routing::{get, post, put},
+    routing::{delete, get, post, put},
     Router,
 };
+use config::Config;
 use db::SmplDB;
 use dotenvy::dotenv;
+use notifier::{FileNotifier, LogNotifier, Notifier};
+use revocation::RevocationCache;
+use risk::RiskEngine;
+use screening::Watchlist;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
@@ -20,13 +25,27 @@ use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod config;
 mod db;
+mod error;
 mod handler;
+mod keys;
+mod notifier;
+mod permission;
+mod revocation;
+mod risk;
+mod scheduler;
+mod screening;
 mod utils;
 
 #[derive(Clone)]
 struct AppState {
     smpldb: Arc<SmplDB>,
+    config: Arc<Config>,
+    revocations: Arc<RevocationCache>,
+    notifier: Arc<dyn Notifier>,
+    risk: Arc<RiskEngine>,
+    watchlist: Arc<Watchlist>,
 }
 
 #[tokio::main]
@@ -52,20 +71,177 @@ async fn main() {
         .unwrap();
 
     dotenv().ok();
-    let db_url = std::env::var("DATABASE_URL").unwrap_or_default();
+    let config = Config::from_env().expect("Failed to load configuration");
+    let notifier: Arc<dyn Notifier> = match &config.notifier_file {
+        Some(path) => Arc::new(FileNotifier::new(path.clone())),
+        None => Arc::new(LogNotifier),
+    };
     let state = AppState {
-        smpldb: SmplDB::new(&db_url)
+        smpldb: SmplDB::new(&config.database_url)
             .expect("Failed to initialise DB connections to DB")
             .into(),
+        revocations: RevocationCache::new(config.revocation_cache_ttl.to_std().unwrap_or_default())
+            .into(),
+        notifier,
+        risk: RiskEngine::from_config(&config).into(),
+        watchlist: Watchlist::load(
+            config.watchlist_file.as_deref(),
+            config.watchlist_match_threshold,
+            config.watchlist_auto_confirm,
+        )
+        .expect("Failed to load watchlist")
+        .into(),
+        config: config.into(),
     };
+    let currencies = state
+        .smpldb
+        .list_currencies()
+        .await
+        .expect("Failed to load currencies");
+    if !currencies
+        .iter()
+        .any(|c| c.code == state.config.default_currency)
+    {
+        panic!(
+            "DEFAULT_CURRENCY {} is not a supported currency",
+            state.config.default_currency
+        );
+    }
+    // the scheduler stops with the runtime when the server shuts down
+    tokio::spawn(scheduler::run(state.clone()));
+
     // Create a regular axum app.
     let app = Router::new()
         .route("/sign_up", post(handler::sign_up::sign_up))
         .route("/sign_in", post(handler::sign_in::sign_in))
+        .route("/sign_in/mfa", post(handler::mfa::sign_in_mfa))
+        .route("/sign_out", post(handler::sign_out::sign_out))
+        .route("/verify_email", post(handler::verify_email::verify_email))
+        .route(
+            "/verify_email/resend",
+            post(handler::verify_email::resend_verification_email),
+        )
+        .route("/token/refresh", post(handler::token::refresh_token))
+        .route("/.well-known/jwks.json", get(handler::token::jwks))
         .route("/profile", get(handler::profile::get_profile))
         .route("/profile", put(handler::profile::update_profile))
+        .route("/profile/password", put(handler::password::change_password))
+        .route(
+            "/password_reset",
+            post(handler::password::request_password_reset),
+        )
+        .route(
+            "/password_reset/confirm",
+            post(handler::password::confirm_password_reset),
+        )
+        .route("/mfa/totp", post(handler::mfa::enrol_totp))
+        .route("/mfa/totp", delete(handler::mfa::disable_totp))
+        .route("/mfa/totp/confirm", post(handler::mfa::confirm_totp))
+        .route("/currencies", get(handler::currency::list_currencies))
+        .route("/fx/rates", get(handler::fx::list_fx_rates))
+        .route("/fx/quotes", post(handler::fx::create_fx_quote))
+        .route("/admin/fx/rates", post(handler::fx::set_fx_rate))
+        .route("/admin/users", get(handler::admin::search_users))
+        .route("/admin/users/:id", get(handler::admin::get_user))
+        .route("/admin/users/:id/role", put(handler::admin::set_user_role))
+        .route(
+            "/admin/users/:id/tier",
+            put(handler::spending_limit::set_user_tier),
+        )
+        .route(
+            "/admin/users/:id/limits",
+            get(handler::spending_limit::get_user_limits),
+        )
+        .route(
+            "/admin/users/:id/limits",
+            put(handler::spending_limit::set_user_limits),
+        )
+        .route(
+            "/admin/limits",
+            get(handler::spending_limit::list_tier_limits),
+        )
+        .route(
+            "/admin/limits/:tier",
+            put(handler::spending_limit::set_tier_limits),
+        )
+        .route(
+            "/admin/screening_hits",
+            get(handler::screening::list_screening_hits),
+        )
+        .route(
+            "/admin/screening_hits/:id",
+            get(handler::screening::get_screening_hit),
+        )
+        .route(
+            "/admin/screening_hits/:id/confirm",
+            post(handler::screening::confirm_screening_hit),
+        )
+        .route(
+            "/admin/screening_hits/:id/dismiss",
+            post(handler::screening::dismiss_screening_hit),
+        )
+        .route(
+            "/admin/transfer_reviews",
+            get(handler::transfer_review::transfer_review_queue),
+        )
+        .route(
+            "/admin/transfer_reviews/:id",
+            get(handler::transfer_review::admin_get_transfer_review),
+        )
+        .route(
+            "/admin/transfer_reviews/:id/approve",
+            post(handler::transfer_review::approve_transfer_review),
+        )
+        .route(
+            "/admin/transfer_reviews/:id/reject",
+            post(handler::transfer_review::reject_transfer_review),
+        )
+        .route("/admin/users/:id/freeze", post(handler::admin::freeze_user))
+        .route(
+            "/admin/users/:id/unfreeze",
+            post(handler::admin::unfreeze_user),
+        )
+        .route("/admin/users/:id/close", post(handler::admin::close_user))
+        .route("/admin/wallets/:id", get(handler::admin::get_wallet))
+        .route(
+            "/admin/wallets/:id/ledger",
+            get(handler::admin::get_wallet_ledger),
+        )
+        .route(
+            "/admin/wallets/:id/transactions",
+            get(handler::admin::list_wallet_transactions),
+        )
+        .route(
+            "/admin/wallets/:id/adjustments",
+            post(handler::admin::create_adjustment),
+        )
+        .route(
+            "/admin/transactions/:id/reversals",
+            post(handler::admin::reverse_transaction),
+        )
+        .route(
+            "/admin/wallets/:id/freeze",
+            post(handler::admin::freeze_wallet),
+        )
+        .route(
+            "/admin/wallets/:id/unfreeze",
+            post(handler::admin::unfreeze_wallet),
+        )
+        .route("/wallets", get(handler::wallet::list_wallets))
+        .route("/wallets", post(handler::wallet::create_wallet))
+        .route("/wallets/:id", put(handler::wallet::rename_wallet))
+        .route("/wallets/:id", delete(handler::wallet::close_wallet))
+        .route(
+            "/wallets/:id/default",
+            put(handler::wallet::set_default_wallet),
+        )
+        .route(
+            "/wallets/transfers",
+            post(handler::wallet::move_between_wallets),
+        )
         .route("/wallet", get(handler::wallet::get_wallet))
         .route("/wallet", put(handler::wallet::update_wallet))
+        .route("/wallet/ledger", get(handler::wallet::get_wallet_ledger))
         .route(
             "/transactions/:id",
             get(handler::transaction::get_transaction_by_id),
@@ -78,6 +254,76 @@ async fn main() {
             "/transactions",
             get(handler::transaction::list_transactions),
         )
+        .route(
+            "/transactions/:id/refunds",
+            post(handler::transaction::create_refund),
+        )
+        .route(
+            "/transactions/conversions",
+            post(handler::transaction::create_conversion),
+        )
+        .route(
+            "/payment_requests",
+            post(handler::payment_request::create_payment_request),
+        )
+        .route(
+            "/payment_requests",
+            get(handler::payment_request::list_payment_requests),
+        )
+        .route(
+            "/payment_requests/:id",
+            get(handler::payment_request::get_payment_request),
+        )
+        .route(
+            "/payment_requests/:id/accept",
+            post(handler::payment_request::accept_payment_request),
+        )
+        .route(
+            "/payment_requests/:id/decline",
+            post(handler::payment_request::decline_payment_request),
+        )
+        .route(
+            "/payment_requests/:id/cancel",
+            post(handler::payment_request::cancel_payment_request),
+        )
+        .route(
+            "/scheduled_payments",
+            post(handler::scheduled_payment::create_scheduled_payment),
+        )
+        .route(
+            "/scheduled_payments",
+            get(handler::scheduled_payment::list_scheduled_payments),
+        )
+        .route(
+            "/scheduled_payments/:id",
+            get(handler::scheduled_payment::get_scheduled_payment),
+        )
+        .route(
+            "/scheduled_payments/:id/pause",
+            post(handler::scheduled_payment::pause_scheduled_payment),
+        )
+        .route(
+            "/scheduled_payments/:id/resume",
+            post(handler::scheduled_payment::resume_scheduled_payment),
+        )
+        .route(
+            "/scheduled_payments/:id/cancel",
+            post(handler::scheduled_payment::cancel_scheduled_payment),
+        )
+        .route("/limits", get(handler::spending_limit::get_limits))
+        .route("/holds", post(handler::hold::authorize_hold))
+        .route("/holds", get(handler::hold::list_holds))
+        .route("/holds/:id", get(handler::hold::get_hold))
+        .route("/holds/:id/capture", post(handler::hold::capture_hold))
+        .route("/holds/:id/void", post(handler::hold::void_hold))
+        .route(
+            "/transfer_reviews",
+            get(handler::transfer_review::list_transfer_reviews),
+        )
+        .route(
+            "/transfer_reviews/:id",
+            get(handler::transfer_review::get_transfer_review),
+        )
         .with_state(state)
         .layer((
             TraceLayer::new_for_http(),
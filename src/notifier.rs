@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use axum::async_trait;
+use chrono::Utc;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+/// Delivers messages, such as password reset links, to users
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()>;
+}
+
+/// Writes messages to the log. For local development only, as message bodies contain secrets.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
+        tracing::info!(to, subject, body, "Sending notification");
+        Ok(())
+    }
+}
+
+/// Appends messages to a file. For local development only.
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
+        let message = format!(
+            "Date: {}\nTo: {to}\nSubject: {subject}\n\n{body}\n\n",
+            Utc::now().to_rfc2822()
+        );
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.write_all(message.as_bytes()).await?;
+        Ok(())
+    }
+}
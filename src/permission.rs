@@ -0,0 +1,109 @@
+use crate::db::models::Role;
+
+/// Something a user may do beyond their own account, granted by their [`Role`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Search users and see their profiles
+    ViewUsers,
+    /// See any wallet, its ledger and its transactions
+    ViewWallets,
+    /// Freeze, unfreeze and close users and wallets
+    FreezeAccounts,
+    /// Credit or debit a wallet outside of a transfer
+    AdjustBalances,
+    /// Send a transfer back to its sender
+    ReverseTransactions,
+    /// Set exchange rates
+    ManageFxRates,
+    /// Change users' roles
+    ManageRoles,
+    /// Set spending limits and move users between tiers
+    ManageLimits,
+    /// Approve or reject transfers held as risky
+    ReviewTransfers,
+    /// Confirm or dismiss watchlist screening hits
+    ReviewScreening,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ViewUsers => "view_users",
+            Permission::ViewWallets => "view_wallets",
+            Permission::FreezeAccounts => "freeze_accounts",
+            Permission::AdjustBalances => "adjust_balances",
+            Permission::ReverseTransactions => "reverse_transactions",
+            Permission::ManageFxRates => "manage_fx_rates",
+            Permission::ManageRoles => "manage_roles",
+            Permission::ManageLimits => "manage_limits",
+            Permission::ReviewTransfers => "review_transfers",
+            Permission::ReviewScreening => "review_screening",
+        }
+    }
+}
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        match self {
+            Role::User => false,
+            Role::Support => matches!(permission, Permission::ViewUsers | Permission::ViewWallets),
+            Role::Admin => true,
+        }
+    }
+}
+
+/// A [`Permission`] named by a type, so handlers can require it with
+/// [`RequirePermission`](crate::utils::RequirePermission)
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct CanViewUsers;
+impl RequiredPermission for CanViewUsers {
+    const PERMISSION: Permission = Permission::ViewUsers;
+}
+
+pub struct CanViewWallets;
+impl RequiredPermission for CanViewWallets {
+    const PERMISSION: Permission = Permission::ViewWallets;
+}
+
+pub struct CanFreezeAccounts;
+impl RequiredPermission for CanFreezeAccounts {
+    const PERMISSION: Permission = Permission::FreezeAccounts;
+}
+
+pub struct CanAdjustBalances;
+impl RequiredPermission for CanAdjustBalances {
+    const PERMISSION: Permission = Permission::AdjustBalances;
+}
+
+pub struct CanReverseTransactions;
+impl RequiredPermission for CanReverseTransactions {
+    const PERMISSION: Permission = Permission::ReverseTransactions;
+}
+
+pub struct CanManageFxRates;
+impl RequiredPermission for CanManageFxRates {
+    const PERMISSION: Permission = Permission::ManageFxRates;
+}
+
+pub struct CanManageRoles;
+impl RequiredPermission for CanManageRoles {
+    const PERMISSION: Permission = Permission::ManageRoles;
+}
+
+pub struct CanManageLimits;
+impl RequiredPermission for CanManageLimits {
+    const PERMISSION: Permission = Permission::ManageLimits;
+}
+
+pub struct CanReviewTransfers;
+impl RequiredPermission for CanReviewTransfers {
+    const PERMISSION: Permission = Permission::ReviewTransfers;
+}
+
+pub struct CanReviewScreening;
+impl RequiredPermission for CanReviewScreening {
+    const PERMISSION: Permission = Permission::ReviewScreening;
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    db::{self, SmplDB},
+    utils::AuthToken,
+};
+
+/// Entries are pruned once a map grows past this
+const MAX_ENTRIES: usize = 10_000;
+
+/// Caches token revocation lookups so validating a token does not hit the DB on every request.
+///
+/// Revocations made by this process are written to the cache straight away. Revocations made by
+/// other instances are picked up once the cached entry is older than the TTL.
+pub struct RevocationCache {
+    ttl: Duration,
+    /// Whether a `jti` has been revoked
+    revoked: Mutex<HashMap<String, (bool, Instant)>>,
+    /// Per user, the time in microseconds before which tokens are rejected
+    valid_after: Mutex<HashMap<i32, (Option<i64>, Instant)>>,
+}
+
+impl RevocationCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            revoked: Mutex::new(HashMap::new()),
+            valid_after: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn is_revoked(&self, smpldb: &SmplDB, claims: &AuthToken) -> Result<bool, db::Error> {
+        let revoked = match self.get(&self.revoked, &claims.jti) {
+            Some(revoked) => revoked,
+            None => {
+                let revoked = smpldb.is_token_revoked(&claims.jti).await?;
+                self.insert(&self.revoked, claims.jti.clone(), revoked);
+                revoked
+            }
+        };
+        if revoked {
+            return Ok(true);
+        }
+
+        let valid_after = match self.get(&self.valid_after, &claims.id) {
+            Some(valid_after) => valid_after,
+            None => {
+                let valid_after = smpldb
+                    .get_tokens_valid_after(claims.id)
+                    .await?
+                    .map(|t| t.timestamp_micros());
+                self.insert(&self.valid_after, claims.id, valid_after);
+                valid_after
+            }
+        };
+        Ok(valid_after.is_some_and(|valid_after| claims.issued_at_micros() < valid_after))
+    }
+
+    pub fn mark_revoked(&self, jti: &str) {
+        self.insert(&self.revoked, jti.to_string(), true);
+    }
+
+    pub fn mark_all_revoked(&self, user_id: i32, valid_after: DateTime<Utc>) {
+        self.insert(
+            &self.valid_after,
+            user_id,
+            Some(valid_after.timestamp_micros()),
+        );
+    }
+
+    fn get<K: Eq + Hash, V: Copy>(
+        &self,
+        map: &Mutex<HashMap<K, (V, Instant)>>,
+        key: &K,
+    ) -> Option<V> {
+        let map = map.lock().expect("revocation cache lock poisoned");
+        map.get(key)
+            .filter(|(_, cached_at)| cached_at.elapsed() < self.ttl)
+            .map(|(value, _)| *value)
+    }
+
+    fn insert<K: Eq + Hash, V>(&self, map: &Mutex<HashMap<K, (V, Instant)>>, key: K, value: V) {
+        let mut map = map.lock().expect("revocation cache lock poisoned");
+        if map.len() >= MAX_ENTRIES {
+            map.retain(|_, (_, cached_at)| cached_at.elapsed() < self.ttl);
+        }
+        map.insert(key, (value, Instant::now()));
+    }
+}
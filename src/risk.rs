@@ -0,0 +1,330 @@
+use chrono::{Duration, Timelike};
+
+use crate::{
+    config::{Config, CurrencyAmounts},
+    db::{AssessRisk, RiskAssessment, RiskDecision, TransferRisk},
+};
+
+/// One signal that a transfer may be fraudulent
+pub trait RiskRule: Send + Sync {
+    /// Recorded on transfers the rule scored
+    fn name(&self) -> &'static str;
+    /// Points the rule adds to the transfer's score, 0 when it doesn't apply
+    fn score(&self, transfer: &TransferRisk) -> i32;
+}
+
+/// Scores transfers with a set of rules, holding them for review or blocking them when the total
+/// is high enough
+pub struct RiskEngine {
+    rules: Vec<Box<dyn RiskRule>>,
+    review_score: i32,
+    block_score: i32,
+}
+
+impl RiskEngine {
+    /// An engine without rules, which allows every transfer until rules are added
+    pub fn new(review_score: i32, block_score: i32) -> Self {
+        Self {
+            rules: Vec::new(),
+            review_score,
+            block_score,
+        }
+    }
+
+    pub fn with_rule(mut self, rule: impl RiskRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// The built in rules, with the thresholds in `config`
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.risk_review_score, config.risk_block_score)
+            .with_rule(NewRecipientLargeAmount {
+                min_amounts: config.risk_large_amount.clone(),
+                score: 40,
+            })
+            .with_rule(RapidSuccession {
+                window: Duration::minutes(10),
+                max_transfers: 5,
+                score: 40,
+            })
+            .with_rule(NewAccount {
+                max_age: Duration::days(1),
+                score: 30,
+            })
+            .with_rule(UnusualHour {
+                from_hour: 0,
+                to_hour: 5,
+                score: 15,
+            })
+    }
+}
+
+impl AssessRisk for RiskEngine {
+    fn assess(&self, transfer: &TransferRisk) -> RiskAssessment {
+        let mut score = 0;
+        let mut rules = Vec::new();
+        for rule in &self.rules {
+            let points = rule.score(transfer);
+            if points > 0 {
+                score += points;
+                rules.push(rule.name().to_string());
+            }
+        }
+        let decision = if score >= self.block_score {
+            RiskDecision::Block
+        } else if score >= self.review_score {
+            RiskDecision::Review
+        } else {
+            RiskDecision::Allow
+        };
+        RiskAssessment {
+            score,
+            rules,
+            decision,
+        }
+    }
+}
+
+/// A large first payment to someone
+pub struct NewRecipientLargeAmount {
+    /// Large amount in each currency. Transfers in other currencies never score.
+    pub min_amounts: CurrencyAmounts,
+    pub score: i32,
+}
+
+impl RiskRule for NewRecipientLargeAmount {
+    fn name(&self) -> &'static str {
+        "new_recipient_large_amount"
+    }
+
+    fn score(&self, transfer: &TransferRisk) -> i32 {
+        match self.min_amounts.get(&transfer.currency) {
+            Some(min_amount) if !transfer.known_recipient && transfer.amount >= *min_amount => {
+                self.score
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Many transfers in a short time
+pub struct RapidSuccession {
+    /// At most a day, as that is all the history a transfer comes with
+    pub window: Duration,
+    /// Transfers already made in `window` that make another one rapid
+    pub max_transfers: usize,
+    pub score: i32,
+}
+
+impl RiskRule for RapidSuccession {
+    fn name(&self) -> &'static str {
+        "rapid_succession"
+    }
+
+    fn score(&self, transfer: &TransferRisk) -> i32 {
+        let since = transfer.at - self.window;
+        let recent = transfer
+            .recent_transfers
+            .iter()
+            .filter(|at| **at > since)
+            .count();
+        if recent >= self.max_transfers {
+            self.score
+        } else {
+            0
+        }
+    }
+}
+
+/// A transfer from an account that was just opened
+pub struct NewAccount {
+    pub max_age: Duration,
+    pub score: i32,
+}
+
+impl RiskRule for NewAccount {
+    fn name(&self) -> &'static str {
+        "new_account"
+    }
+
+    fn score(&self, transfer: &TransferRisk) -> i32 {
+        match transfer.account_created_at {
+            Some(created_at) if transfer.at - created_at < self.max_age => self.score,
+            _ => 0,
+        }
+    }
+}
+
+/// A transfer in the middle of the night, UTC
+pub struct UnusualHour {
+    /// First hour of the night
+    pub from_hour: u32,
+    /// First hour after the night
+    pub to_hour: u32,
+    pub score: i32,
+}
+
+impl RiskRule for UnusualHour {
+    fn name(&self) -> &'static str {
+        "unusual_hour"
+    }
+
+    fn score(&self, transfer: &TransferRisk) -> i32 {
+        if (self.from_hour..self.to_hour).contains(&transfer.at.hour()) {
+            self.score
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    /// A transfer from an old account at noon that no rule scores
+    fn transfer(amount: u32, currency: &str) -> TransferRisk {
+        TransferRisk {
+            amount: BigDecimal::from(amount),
+            currency: currency.to_string(),
+            at: at("2026-10-18T12:00:00Z"),
+            account_created_at: Some(at("2025-01-01T00:00:00Z")),
+            known_recipient: false,
+            recent_transfers: Vec::new(),
+        }
+    }
+
+    fn large_amount() -> NewRecipientLargeAmount {
+        NewRecipientLargeAmount {
+            min_amounts: CurrencyAmounts::parse("USD:1000,EUR:900", "USD").unwrap(),
+            score: 40,
+        }
+    }
+
+    #[test]
+    fn large_amount_to_new_recipient_scores() {
+        let rule = large_amount();
+        assert_eq!(rule.score(&transfer(1000, "USD")), 40);
+        assert_eq!(rule.score(&transfer(999, "USD")), 0);
+        assert_eq!(rule.score(&transfer(900, "EUR")), 40);
+    }
+
+    #[test]
+    fn large_amount_to_known_recipient_does_not_score() {
+        let rule = large_amount();
+        let known = TransferRisk {
+            known_recipient: true,
+            ..transfer(5000, "USD")
+        };
+        assert_eq!(rule.score(&known), 0);
+    }
+
+    #[test]
+    fn large_amount_in_currency_without_threshold_does_not_score() {
+        assert_eq!(large_amount().score(&transfer(1_000_000, "JPY")), 0);
+    }
+
+    #[test]
+    fn rapid_succession_counts_transfers_in_window() {
+        let rule = RapidSuccession {
+            window: Duration::minutes(10),
+            max_transfers: 2,
+            score: 40,
+        };
+        let mut risky = transfer(10, "USD");
+        risky.recent_transfers = vec![at("2026-10-18T11:55:00Z"), at("2026-10-18T11:59:00Z")];
+        assert_eq!(rule.score(&risky), 40);
+
+        let mut spread = transfer(10, "USD");
+        spread.recent_transfers = vec![at("2026-10-18T11:00:00Z"), at("2026-10-18T11:59:00Z")];
+        assert_eq!(rule.score(&spread), 0);
+    }
+
+    #[test]
+    fn new_account_scores_until_max_age() {
+        let rule = NewAccount {
+            max_age: Duration::days(1),
+            score: 30,
+        };
+        let mut new = transfer(10, "USD");
+        new.account_created_at = Some(at("2026-10-18T00:00:00Z"));
+        assert_eq!(rule.score(&new), 30);
+
+        new.account_created_at = Some(at("2026-10-17T12:00:00Z"));
+        assert_eq!(rule.score(&new), 0);
+
+        new.account_created_at = None;
+        assert_eq!(rule.score(&new), 0);
+    }
+
+    #[test]
+    fn unusual_hour_covers_the_night() {
+        let rule = UnusualHour {
+            from_hour: 0,
+            to_hour: 5,
+            score: 15,
+        };
+        let mut night = transfer(10, "USD");
+        for (time, score) in [
+            ("2026-10-18T00:00:00Z", 15),
+            ("2026-10-18T04:59:59Z", 15),
+            ("2026-10-18T05:00:00Z", 0),
+            ("2026-10-18T23:59:59Z", 0),
+        ] {
+            night.at = at(time);
+            assert_eq!(rule.score(&night), score, "at {time}");
+        }
+    }
+
+    #[test]
+    fn engine_decides_on_total_score() {
+        let engine = RiskEngine::new(50, 100)
+            .with_rule(large_amount())
+            .with_rule(NewAccount {
+                max_age: Duration::days(1),
+                score: 30,
+            })
+            .with_rule(UnusualHour {
+                from_hour: 0,
+                to_hour: 5,
+                score: 15,
+            });
+
+        let quiet = engine.assess(&transfer(10, "USD"));
+        assert_eq!(quiet.score, 0);
+        assert!(quiet.rules.is_empty());
+        assert_eq!(quiet.decision, RiskDecision::Allow);
+
+        let mut review = transfer(1000, "USD");
+        review.account_created_at = Some(at("2026-10-18T11:00:00Z"));
+        let assessment = engine.assess(&review);
+        assert_eq!(assessment.score, 70);
+        assert_eq!(
+            assessment.rules,
+            ["new_recipient_large_amount", "new_account"]
+        );
+        assert_eq!(assessment.decision, RiskDecision::Review);
+
+        review.at = at("2026-10-18T03:00:00Z");
+        review.account_created_at = Some(at("2026-10-18T02:00:00Z"));
+        let assessment = engine.assess(&review);
+        assert_eq!(assessment.score, 85);
+        assert_eq!(assessment.decision, RiskDecision::Review);
+
+        let blocking = RiskEngine::new(50, 85)
+            .with_rule(large_amount())
+            .with_rule(NewAccount {
+                max_age: Duration::days(1),
+                score: 45,
+            });
+        assert_eq!(blocking.assess(&review).decision, RiskDecision::Block);
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::Context;
+
+use crate::{
+    db::{
+        models::{ScheduleStatus, ScheduledPayment},
+        ScheduledRun,
+    },
+    AppState,
+};
+
+/// Most due scheduled payments made per tick
+const BATCH_SIZE: i64 = 100;
+
+/// Makes due scheduled payments and expires holds every `SCHEDULER_INTERVAL_SECS`, until the
+/// server shuts down.
+///
+/// Each payment is made in one DB transaction with its schedule update, so stopping mid tick
+/// can't pay twice, and several instances can run the scheduler side by side.
+pub async fn run(state: AppState) {
+    let period = state
+        .config
+        .scheduler_interval
+        .to_std()
+        .unwrap_or_default()
+        .max(std::time::Duration::from_secs(1));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_due_payments(&state).await {
+            tracing::error!("Failed to run scheduled payments: {e:#}");
+        }
+        match state.smpldb.expire_holds().await {
+            Ok(0) => {}
+            Ok(expired) => tracing::info!(expired, "Expired holds"),
+            Err(e) => tracing::error!("Failed to expire holds: {e}"),
+        }
+    }
+}
+
+async fn run_due_payments(state: &AppState) -> anyhow::Result<()> {
+    let due = state
+        .smpldb
+        .due_scheduled_payments(BATCH_SIZE)
+        .await
+        .context("Failed to get due scheduled payments")?;
+    for schedule_id in due {
+        let run = state
+            .smpldb
+            .run_scheduled_payment(
+                schedule_id,
+                state.config.scheduled_payment_max_attempts,
+                state.config.scheduled_payment_retry_delay,
+                state.risk.as_ref(),
+            )
+            .await;
+        match run {
+            Ok(None) => {}
+            Ok(Some((schedule, ScheduledRun::Paid(transaction_id)))) => {
+                tracing::info!(schedule_id, transaction_id, "Made scheduled payment");
+                if schedule.status == ScheduleStatus::Completed {
+                    tracing::info!(schedule_id, "Scheduled payment completed");
+                }
+            }
+            Ok(Some((_, ScheduledRun::Held(review_id)))) => {
+                tracing::info!(schedule_id, review_id, "Held scheduled payment for review");
+            }
+            Ok(Some((schedule, ScheduledRun::WillRetry(e)))) => {
+                tracing::info!(
+                    schedule_id,
+                    retry_at = %schedule.next_run_at,
+                    "Scheduled payment failed, will retry: {e}"
+                );
+            }
+            Ok(Some((schedule, ScheduledRun::GaveUp(e)))) => {
+                tracing::warn!(schedule_id, "Scheduled payment failed: {e}");
+                if let Err(e) = notify_failure(state, &schedule, &e).await {
+                    tracing::error!(schedule_id, "{e:#}");
+                }
+            }
+            Err(e) => tracing::error!(schedule_id, "Failed to run scheduled payment: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Tells the user a scheduled payment was given up on
+async fn notify_failure(
+    state: &AppState,
+    schedule: &ScheduledPayment,
+    error: &impl std::fmt::Display,
+) -> anyhow::Result<()> {
+    let user = state
+        .smpldb
+        .get_user_by_id(schedule.user_id)
+        .await?
+        .with_context(|| format!("Failed to find user with id {}", schedule.user_id))?;
+
+    let next = match schedule.status {
+        ScheduleStatus::Active => format!("The next payment is due at {}.", schedule.next_run_at),
+        ScheduleStatus::Failed => "No more payments will be made.".to_string(),
+        _ => "It was the last payment.".to_string(),
+    };
+    let body = format!(
+        "Your scheduled payment {} of {} {} could not be made: {error}.\n\n{next}",
+        schedule.id, schedule.amount, schedule.currency
+    );
+    state
+        .notifier
+        .send(&user.email, "Scheduled payment failed", &body)
+        .await
+        .context("Failed to send scheduled payment failure")
+}
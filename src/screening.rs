@@ -0,0 +1,240 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::db::WatchlistMatch;
+
+/// A sanctioned or otherwise listed party
+#[derive(Debug, Deserialize)]
+struct Entry {
+    id: String,
+    name: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+}
+
+/// A row of a CSV watchlist, with aliases separated by `;`
+#[derive(Debug, Deserialize)]
+struct CsvEntry {
+    id: String,
+    name: String,
+    #[serde(default)]
+    aliases: String,
+}
+
+/// An entry with its name and aliases normalised for matching
+#[derive(Debug)]
+struct ListedParty {
+    id: String,
+    name: String,
+    names: Vec<String>,
+}
+
+/// Parties that users are screened against when they sign up and send or receive transfers
+#[derive(Debug)]
+pub struct Watchlist {
+    parties: Vec<ListedParty>,
+    /// Similarity at which a name is a hit
+    threshold: f64,
+    /// Whether exact matches are confirmed without review
+    auto_confirm: bool,
+}
+
+impl Watchlist {
+    /// Loads the watchlist from a `.csv` file with `id,name,aliases` columns, or a `.json` file
+    /// with an array of `{"id", "name", "aliases"}`. Without a file nobody is listed.
+    pub fn load(path: Option<&Path>, threshold: f64, auto_confirm: bool) -> anyhow::Result<Self> {
+        let entries = match path {
+            None => Vec::new(),
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read watchlist {}", path.display()))?;
+                let extension = path.extension().and_then(|e| e.to_str());
+                match extension.map(str::to_ascii_lowercase).as_deref() {
+                    Some("json") => serde_json::from_str(&contents)
+                        .with_context(|| format!("Invalid watchlist {}", path.display()))?,
+                    Some("csv") => parse_csv(&contents)
+                        .with_context(|| format!("Invalid watchlist {}", path.display()))?,
+                    _ => bail!("Watchlist {} must be a .csv or .json file", path.display()),
+                }
+            }
+        };
+        Ok(Self::new(entries, threshold, auto_confirm))
+    }
+
+    fn new(entries: Vec<Entry>, threshold: f64, auto_confirm: bool) -> Self {
+        let parties = entries
+            .into_iter()
+            .map(|entry| ListedParty {
+                names: std::iter::once(&entry.name)
+                    .chain(&entry.aliases)
+                    .map(|n| normalise(n))
+                    .filter(|n| !n.is_empty())
+                    .collect(),
+                id: entry.id,
+                name: entry.name,
+            })
+            .collect();
+        Self {
+            parties,
+            threshold,
+            auto_confirm,
+        }
+    }
+
+    /// Returns the best match of `names` against each listed party that is a hit
+    pub fn screen(&self, names: &[&str]) -> Vec<WatchlistMatch> {
+        let names: Vec<(&str, String)> = names
+            .iter()
+            .map(|&n| (n, normalise(n)))
+            .filter(|(_, n)| !n.is_empty())
+            .collect();
+
+        let mut matches = Vec::new();
+        for party in &self.parties {
+            let best = names
+                .iter()
+                .flat_map(|(name, normalised)| {
+                    party
+                        .names
+                        .iter()
+                        .map(move |listed| (*name, similarity(normalised, listed)))
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((name, score)) = best.filter(|(_, score)| *score >= self.threshold) {
+                matches.push(WatchlistMatch {
+                    screened_name: name.to_string(),
+                    entry_id: party.id.clone(),
+                    entry_name: party.name.clone(),
+                    score,
+                    confirmed: self.auto_confirm && score >= 1.0,
+                });
+            }
+        }
+        matches
+    }
+}
+
+fn parse_csv(contents: &str) -> anyhow::Result<Vec<Entry>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    reader
+        .deserialize::<CsvEntry>()
+        .map(|row| {
+            let row = row?;
+            Ok(Entry {
+                id: row.id,
+                name: row.name,
+                aliases: row
+                    .aliases
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .map(String::from)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Lowercases the name's letters and separates its words with single spaces, so `John_Smith`
+/// and `john smith` are the same
+fn normalise(name: &str) -> String {
+    name.split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Jaro-Winkler similarity of the names, in the order given or with their words sorted so
+/// `smith john` matches `john smith`. Only the same words score 1.
+fn similarity(a: &str, b: &str) -> f64 {
+    let sorted = |name: &str| {
+        let mut words: Vec<&str> = name.split(' ').collect();
+        words.sort_unstable();
+        words.join(" ")
+    };
+    strsim::jaro_winkler(a, b).max(strsim::jaro_winkler(&sorted(a), &sorted(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchlist(auto_confirm: bool) -> Watchlist {
+        let entries = parse_csv(
+            "id,name,aliases\n\
+             EX-001,Ivan Petrovsky,Ivan Petrovski; I. Petrovsky\n\
+             EX-002,Maria Delgado Ruiz,\n",
+        )
+        .unwrap();
+        Watchlist::new(entries, 0.9, auto_confirm)
+    }
+
+    #[test]
+    fn csv_aliases_are_split_and_trimmed() {
+        let entries = parse_csv("id,name,aliases\nEX-001,Ivan Petrovsky, a ;; b \n").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].aliases, ["a", "b"]);
+    }
+
+    #[test]
+    fn names_are_normalised() {
+        assert_eq!(normalise("John_Smith"), "john smith");
+        assert_eq!(normalise("  J.  SMITH-99 "), "j smith");
+        assert_eq!(normalise("1234"), "");
+    }
+
+    #[test]
+    fn word_order_does_not_matter() {
+        assert_eq!(similarity("smith john", "john smith"), 1.0);
+        assert!(similarity("jon smith", "john smith") < 1.0);
+        assert!(similarity("jon smith", "john smith") > 0.9);
+    }
+
+    #[test]
+    fn exact_match_is_confirmed_only_with_auto_confirm() {
+        let matches = watchlist(false).screen(&["petrovsky_ivan"]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].entry_id, "EX-001");
+        assert_eq!(matches[0].score, 1.0);
+        assert!(!matches[0].confirmed);
+
+        let matches = watchlist(true).screen(&["petrovsky_ivan"]);
+        assert!(matches[0].confirmed);
+    }
+
+    #[test]
+    fn close_match_is_never_confirmed() {
+        let matches = watchlist(true).screen(&["ivan petrovskyy"]);
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].score >= 0.9 && matches[0].score < 1.0);
+        assert!(!matches[0].confirmed);
+    }
+
+    #[test]
+    fn best_name_is_reported() {
+        let matches = watchlist(false).screen(&["alice", "Maria Delgado Ruiz"]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].entry_id, "EX-002");
+        assert_eq!(matches[0].screened_name, "Maria Delgado Ruiz");
+    }
+
+    #[test]
+    fn aliases_are_matched() {
+        let matches = watchlist(false).screen(&["ivan petrovski"]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].entry_name, "Ivan Petrovsky");
+        assert_eq!(matches[0].score, 1.0);
+    }
+
+    #[test]
+    fn unrelated_names_do_not_match() {
+        assert!(watchlist(true)
+            .screen(&["alice", "bob_jones", "42"])
+            .is_empty());
+    }
+}
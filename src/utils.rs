@@ -1,93 +1,301 @@
+use std::marker::PhantomData;
+
+use anyhow::Context;
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{header::AUTHORIZATION, request::Parts, StatusCode},
-    response::{IntoResponse, Response},
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING},
+        request::Parts,
+    },
 };
-use chrono::{DateTime, Utc};
-use hmac::{Hmac, Mac};
-use jwt::{AlgorithmType, Header, SignWithKey, Token, VerifyWithKey};
-use serde::{Deserialize, Serialize};
-use sha2::Sha384;
+use chrono::Duration;
+use jsonwebtoken::errors::ErrorKind;
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-/// Contains User ID
-pub struct ValidateAuth(pub i32);
+use crate::{
+    db::models::{AccountStatus, Role},
+    error::ApiError,
+    keys::{KeyStore, TokenType},
+    permission::RequiredPermission,
+    AppState,
+};
+
+/// `Json` extractor that rejects malformed bodies with an [`ApiError`]
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// [`ApiJson`] for an optional body. Requests without a body get `T::default()`, but a body that
+/// is sent is rejected like [`ApiJson`] if it is malformed.
+pub struct OptionalApiJson<T>(pub T);
 
 #[async_trait]
-impl<S> FromRequestParts<S> for ValidateAuth
+impl<T, S> FromRequest<S> for OptionalApiJson<T>
 where
+    T: DeserializeOwned + Default,
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ApiError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers();
+        let has_body = headers.contains_key(CONTENT_TYPE)
+            || headers.contains_key(TRANSFER_ENCODING)
+            || headers
+                .get(CONTENT_LENGTH)
+                .is_some_and(|length| length != "0");
+        if !has_body {
+            return Ok(OptionalApiJson(T::default()));
+        }
+        let ApiJson(value) = ApiJson::from_request(req, state).await?;
+        Ok(OptionalApiJson(value))
+    }
+}
+
+/// `Query` extractor that rejects malformed query strings with an [`ApiError`]
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
+
+/// `Path` extractor that rejects malformed paths with an [`ApiError`]
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct ApiPath<T>(pub T);
+
+/// Contains User ID. Rejects closed accounts, but not frozen ones, which can still see their
+/// wallets.
+pub struct ValidateAuth(pub i32);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ValidateAuth {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = AuthToken::from_request_parts(parts, state).await?;
+        let status = state
+            .smpldb
+            .get_user_status(claims.id)
+            .await
+            .with_context(|| format!("Failed to get status of user {}", claims.id))?;
+        match status {
+            Some(AccountStatus::Closed) => Err(ApiError::AccountClosed),
+            Some(_) => Ok(ValidateAuth(claims.id)),
+            None => Err(ApiError::InvalidToken),
+        }
+    }
+}
+
+/// Contains the User ID of an active user whose role grants the permission `P`
+pub struct RequirePermission<P>(pub i32, pub PhantomData<P>);
+
+#[async_trait]
+impl<P> FromRequestParts<AppState> for RequirePermission<P>
+where
+    P: RequiredPermission,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = AuthToken::from_request_parts(parts, state).await?;
+        if !claims.role.can(P::PERMISSION) {
+            return Err(ApiError::MissingPermission(P::PERMISSION));
+        }
+        let status = state
+            .smpldb
+            .get_user_status(claims.id)
+            .await
+            .with_context(|| format!("Failed to get status of user {}", claims.id))?;
+        match status {
+            Some(AccountStatus::Active) => Ok(RequirePermission(claims.id, PhantomData)),
+            Some(AccountStatus::Frozen) => Err(ApiError::AccountFrozen),
+            Some(AccountStatus::Closed) => Err(ApiError::AccountClosed),
+            None => Err(ApiError::InvalidToken),
+        }
+    }
+}
+
+/// Extracts the claims of a valid, unrevoked bearer token
+#[async_trait]
+impl FromRequestParts<AppState> for AuthToken {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
         let Some(value) = parts.headers.get(AUTHORIZATION) else {
-            return Err((StatusCode::BAD_REQUEST, "`Authorization` header is missing"));
+            return Err(ApiError::MissingAuthorization);
         };
         let Some(unvalidated_token) = value.to_str().ok().and_then(|k| k.strip_prefix("Bearer "))
         else {
-            return Err((StatusCode::BAD_REQUEST, "Invalid Token"));
+            return Err(ApiError::InvalidToken);
         };
 
-        validate_jwt(unvalidated_token).map(ValidateAuth)
+        let claims = validate_jwt(&state.config.jwt_keys, unvalidated_token)?;
+        let revoked = state
+            .revocations
+            .is_revoked(&state.smpldb, &claims)
+            .await
+            .with_context(|| format!("Failed to check token revocation for user {}", claims.id))?;
+        if revoked {
+            return Err(ApiError::TokenRevoked);
+        }
+        Ok(claims)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/// Contains the value of the optional `Idempotency-Key` header
+pub struct IdempotencyKey(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get("Idempotency-Key") else {
+            return Ok(IdempotencyKey(None));
+        };
+        match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= 255 => {
+                Ok(IdempotencyKey(Some(key.to_string())))
+            }
+            _ => Err(ApiError::InvalidHeader("Idempotency-Key")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AuthToken {
-    id: i32,
-    issued_at: i64,
+    #[serde(rename = "sub")]
+    pub id: i32,
+    #[serde(rename = "iat")]
+    pub issued_at: i64,
+    /// `iat` in microseconds, so tokens issued in the same second as a revocation of all the
+    /// user's tokens can be told apart from it. Tokens issued before it was added have none.
+    #[serde(rename = "iat_us", default, skip_serializing_if = "Option::is_none")]
+    pub issued_at_micros: Option<i64>,
+    #[serde(rename = "exp")]
+    pub expires_at: i64,
+    /// Unique token id, used to revoke this token
+    pub jti: String,
+    /// Role of the user when the token was issued. Tokens issued before roles existed have none.
+    #[serde(default)]
+    pub role: Role,
 }
 impl AuthToken {
-    pub fn new(id: i32) -> Self {
+    pub fn new(id: i32, role: Role, ttl: Duration) -> Self {
+        let now = chrono::Utc::now();
         Self {
             id,
-            issued_at: chrono::Utc::now().timestamp(),
+            issued_at: now.timestamp(),
+            issued_at_micros: Some(now.timestamp_micros()),
+            expires_at: (now + ttl).timestamp(),
+            jti: generate_token(),
+            role,
         }
     }
+
+    /// When the token was issued, in microseconds. Falls back to the start of the `iat` second.
+    pub fn issued_at_micros(&self) -> i64 {
+        self.issued_at_micros
+            .unwrap_or(self.issued_at.saturating_mul(1_000_000))
+    }
 }
 
-fn get_key() -> Hmac<Sha384> {
-    const KEY: [u8; 10] = *b"secret-key";
-    Hmac::new_from_slice(&KEY).expect("Should not fail")
+pub fn issue_new_jwt(
+    keys: &KeyStore,
+    id: i32,
+    role: Role,
+    ttl: Duration,
+) -> Result<String, ApiError> {
+    let claims = AuthToken::new(id, role, ttl);
+    let token = keys
+        .sign(&claims, TokenType::Access)
+        .context("Failed to sign token with key")?;
+    Ok(token)
 }
 
-pub fn issue_new_jwt(id: i32) -> Result<String, Response> {
-    let key = get_key();
-    let header = Header {
-        algorithm: AlgorithmType::Hs384,
-        ..Default::default()
-    };
-    let claims = AuthToken::new(id);
-
-    match Token::new(header, claims).sign_with_key(&key) {
-        Ok(t) => Ok(t.as_str().to_string()),
-        Err(e) => {
-            tracing::error!(?e, "Failed to sign token with key");
-            Err(
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-            )
-        }
+fn validate_jwt(keys: &KeyStore, token: &str) -> Result<AuthToken, ApiError> {
+    match keys.verify::<AuthToken>(token, TokenType::Access) {
+        Ok(claims) => Ok(claims),
+        Err(e) if *e.kind() == ErrorKind::ExpiredSignature => Err(ApiError::TokenExpired),
+        Err(_) => Err(ApiError::InvalidToken),
     }
 }
 
-fn validate_jwt(token: &str) -> Result<i32, (StatusCode, &'static str)> {
-    let key = get_key();
-    let token: Token<Header, AuthToken, _> = match token.verify_with_key(&key) {
-        Ok(t) => t,
-        Err(_) => return Err((StatusCode::BAD_REQUEST, "Malformed Token")),
-    };
-    let claims = token.claims();
+/// Claims of the token returned by `/sign_in` in place of an access token when the user has
+/// two-factor authentication enabled
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaChallenge {
+    sub: i32,
+    iat: i64,
+    exp: i64,
+}
 
-    let Some(start_time) = DateTime::from_timestamp(claims.issued_at, 0) else {
-        return Err((StatusCode::BAD_REQUEST, "Malformed Token"));
+pub fn issue_mfa_challenge(keys: &KeyStore, id: i32, ttl: Duration) -> Result<String, ApiError> {
+    let now = chrono::Utc::now();
+    let claims = MfaChallenge {
+        sub: id,
+        iat: now.timestamp(),
+        exp: (now + ttl).timestamp(),
     };
-    let end_time = Utc::now();
-    let duration = end_time.signed_duration_since(start_time);
-    let hours = duration.num_hours();
 
-    if hours > 1 {
-        return Err((StatusCode::UNAUTHORIZED, "Token Expired"));
+    let token = keys
+        .sign(&claims, TokenType::MfaChallenge)
+        .context("Failed to sign token with key")?;
+    Ok(token)
+}
+
+/// Returns the user id of a valid MFA challenge token
+pub fn validate_mfa_challenge(keys: &KeyStore, token: &str) -> Result<i32, ApiError> {
+    match keys.verify::<MfaChallenge>(token, TokenType::MfaChallenge) {
+        Ok(claims) => Ok(claims.sub),
+        Err(e) if *e.kind() == ErrorKind::ExpiredSignature => Err(ApiError::MfaTokenExpired),
+        Err(_) => Err(ApiError::InvalidMfaToken),
     }
-    Ok(claims.id)
+}
+
+/// Contains the value of the optional `X-TOTP-Code` header, used to step up transfers
+pub struct TotpCode(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TotpCode
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get("X-TOTP-Code") else {
+            return Ok(TotpCode(None));
+        };
+        match value.to_str() {
+            Ok(code) => Ok(TotpCode(Some(code.trim().to_string()))),
+            Err(_) => Err(ApiError::InvalidHeader("X-TOTP-Code")),
+        }
+    }
+}
+
+/// Generates a random 256 bit token, hex encoded
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Hashes a token for storage, so a leaked table cannot be used to authenticate
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
 }